@@ -0,0 +1,273 @@
+use std::{
+  collections::VecDeque,
+  fmt::{self, Write},
+  fs::{self, File},
+  io,
+  path::PathBuf,
+  time::SystemTime,
+};
+
+use sha2::{Digest, Sha256};
+
+/// The RIFF chunk holding the utterance's cache key and the length of each of its sentences,
+/// which other WAV readers ignore.
+const SENTENCES_CHUNK: &[u8; 4] = b"cqsl";
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// Everything an utterance was synthesised from, which it's cached under.
+///
+/// Each field is written as its name and then its value, both preceded by their length in bytes,
+/// so a key is the same for every build. Files on disk are named after its SHA-256, and store the
+/// key itself to be compared when they're read, so that a collision can't return the audio of
+/// another text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheKey(Vec<u8>);
+
+impl CacheKey {
+  pub fn add(&mut self, name: &str, value: impl fmt::Display) {
+    for bytes in [name.as_bytes(), value.to_string().as_bytes()] {
+      let len = bytes.len() as u32;
+      self.0.extend_from_slice(&len.to_le_bytes());
+      self.0.extend_from_slice(bytes);
+    }
+  }
+
+  /// Adds a field if it has a value. Fields are named, so one that's left out can't be mistaken
+  /// for another.
+  pub fn add_some(&mut self, name: &str, value: Option<impl fmt::Display>) {
+    if let Some(value) = value {
+      self.add(name, value);
+    }
+  }
+
+  pub fn digest(&self) -> String {
+    sha256_hex(&self.0)
+  }
+}
+
+/// The SHA-256 of some bytes in hexadecimal, for naming files after what they were made from.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+  Sha256::digest(bytes)
+    .iter()
+    .fold(String::with_capacity(64), |mut hex, byte| {
+      let _ = write!(hex, "{:02x}", byte);
+      hex
+    })
+}
+
+/// An on-disk cache of synthesised utterances, one mono 32-bit float WAV file per utterance.
+///
+/// Files are touched when they're read, and the least recently used ones are removed when the
+/// cache grows beyond its maximum size.
+#[derive(Debug)]
+pub struct DiskCache {
+  dir: PathBuf,
+  max_bytes: u64,
+}
+
+impl DiskCache {
+  /// Opens the cache in `dir`, creating it if necessary. A `max_bytes` of 0 means no limit.
+  pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+    let dir = dir.into();
+    fs::create_dir_all(&dir)?;
+    Ok(Self { dir, max_bytes })
+  }
+
+  fn path(&self, key: &CacheKey) -> PathBuf {
+    self.dir.join(format!("{}.wav", key.digest()))
+  }
+
+  /// Returns the sentences of the utterance cached under `key` and their sample rate.
+  pub fn get(&self, key: &CacheKey) -> io::Result<Option<(Vec<Vec<f32>>, u64)>> {
+    let path = self.path(key);
+    let bytes = match fs::read(&path) {
+      Ok(bytes) => bytes,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+      Err(e) => return Err(e),
+    };
+    let (sentences, sample_rate, stored_key) = decode_wav(&bytes).ok_or_else(|| {
+      io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} is not a cached utterance", path.display()),
+      )
+    })?;
+    if stored_key != key.0 {
+      // Another utterance with the same digest, which writing this one will replace.
+      return Ok(None);
+    }
+    File::options()
+      .write(true)
+      .open(&path)?
+      .set_modified(SystemTime::now())?;
+    Ok(Some((sentences, sample_rate)))
+  }
+
+  /// Caches an utterance under `key`, then evicts the least recently used utterances if the
+  /// cache has grown too large.
+  pub fn put(&self, key: &CacheKey, sentences: &[Vec<f32>], sample_rate: u64) -> io::Result<()> {
+    let path = self.path(key);
+    // Write to a temporary file first so that readers never see a partial file.
+    let partial = path.with_extension("wav.partial");
+    fs::write(&partial, encode_wav(key, sentences, sample_rate))?;
+    fs::rename(&partial, &path)?;
+    self.evict()
+  }
+
+  fn evict(&self) -> io::Result<()> {
+    if self.max_bytes == 0 {
+      return Ok(());
+    }
+    let mut files = vec![];
+    let mut total = 0;
+    for entry in fs::read_dir(&self.dir)? {
+      let entry = entry?;
+      let path = entry.path();
+      if path.extension().is_some_and(|extension| extension == "wav") {
+        let metadata = entry.metadata()?;
+        total += metadata.len();
+        files.push((metadata.modified()?, metadata.len(), path));
+      }
+    }
+    files.sort();
+    for (_, len, path) in files {
+      if total <= self.max_bytes {
+        break;
+      }
+      fs::remove_file(&path)?;
+      total -= len;
+    }
+    Ok(())
+  }
+}
+
+/// An in-memory cache of the most recently used utterances.
+#[derive(Debug, Default)]
+pub struct MemoryCache {
+  /// The most recently used first.
+  entries: VecDeque<(CacheKey, Vec<Vec<f32>>, u64)>,
+}
+
+impl MemoryCache {
+  /// Returns the sentences of the utterance cached under `key` and their sample rate.
+  pub fn get(&mut self, key: &CacheKey) -> Option<(Vec<Vec<f32>>, u64)> {
+    let index = self.entries.iter().position(|(k, ..)| k == key)?;
+    let entry = self.entries.remove(index)?;
+    let utterance = (entry.1.clone(), entry.2);
+    self.entries.push_front(entry);
+    Some(utterance)
+  }
+
+  /// Caches an utterance under `key`, evicting the least recently used ones beyond `capacity`.
+  pub fn put(&mut self, key: &CacheKey, sentences: &[Vec<f32>], sample_rate: u64, capacity: usize) {
+    self.entries.retain(|(k, ..)| k != key);
+    self
+      .entries
+      .push_front((key.clone(), sentences.to_vec(), sample_rate));
+    self.truncate(capacity);
+  }
+
+  pub fn truncate(&mut self, capacity: usize) {
+    self.entries.truncate(capacity);
+  }
+}
+
+fn encode_wav(key: &CacheKey, sentences: &[Vec<f32>], sample_rate: u64) -> Vec<u8> {
+  let samples: usize = sentences.iter().map(Vec::len).sum();
+  let mut fmt = vec![];
+  fmt.extend_from_slice(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+  fmt.extend_from_slice(&1u16.to_le_bytes());
+  fmt.extend_from_slice(&(sample_rate as u32).to_le_bytes());
+  fmt.extend_from_slice(&(sample_rate as u32 * 4).to_le_bytes());
+  fmt.extend_from_slice(&4u16.to_le_bytes());
+  fmt.extend_from_slice(&32u16.to_le_bytes());
+  // The key's length and the key, then the length of each sentence.
+  let mut lengths = vec![];
+  lengths.extend_from_slice(&(key.0.len() as u32).to_le_bytes());
+  lengths.extend_from_slice(&key.0);
+  lengths.extend(
+    sentences
+      .iter()
+      .flat_map(|sentence| (sentence.len() as u32).to_le_bytes()),
+  );
+  let data: Vec<u8> = sentences
+    .iter()
+    .flatten()
+    .flat_map(|sample| sample.to_le_bytes())
+    .collect();
+  let mut wav = Vec::with_capacity(44 + lengths.len() + samples * 4);
+  wav.extend_from_slice(b"RIFF");
+  wav.extend_from_slice(&0u32.to_le_bytes());
+  wav.extend_from_slice(b"WAVE");
+  for (id, chunk) in [(b"fmt ", fmt), (SENTENCES_CHUNK, lengths), (b"data", data)] {
+    wav.extend_from_slice(id);
+    wav.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&chunk);
+    // Chunks are padded to an even length.
+    if chunk.len() % 2 == 1 {
+      wav.push(0);
+    }
+  }
+  let riff_len = (wav.len() - 8) as u32;
+  wav[4..8].copy_from_slice(&riff_len.to_le_bytes());
+  wav
+}
+
+/// Decodes a cached utterance into its sentences, sample rate and cache key.
+fn decode_wav(wav: &[u8]) -> Option<(Vec<Vec<f32>>, u64, Vec<u8>)> {
+  if wav.get(0..4)? != b"RIFF" || wav.get(8..12)? != b"WAVE" {
+    return None;
+  }
+  let mut sample_rate = None;
+  let mut key = None;
+  let mut lengths = None;
+  let mut samples = None;
+  let mut rest = &wav[12..];
+  while rest.len() >= 8 {
+    let id = &rest[0..4];
+    let len = u32::from_le_bytes(rest[4..8].try_into().ok()?) as usize;
+    let chunk = rest.get(8..8 + len)?;
+    match id {
+      b"fmt " => {
+        let format = u16::from_le_bytes(chunk.get(0..2)?.try_into().ok()?);
+        let channels = u16::from_le_bytes(chunk.get(2..4)?.try_into().ok()?);
+        if format != WAVE_FORMAT_IEEE_FLOAT || channels != 1 {
+          return None;
+        }
+        sample_rate = Some(u32::from_le_bytes(chunk.get(4..8)?.try_into().ok()?) as u64);
+      },
+      id if id == SENTENCES_CHUNK => {
+        let key_len = u32::from_le_bytes(chunk.get(0..4)?.try_into().ok()?) as usize;
+        key = Some(chunk.get(4..4 + key_len)?.to_vec());
+        lengths = Some(
+          chunk
+            .get(4 + key_len..)?
+            .chunks_exact(4)
+            .map(|length| u32::from_le_bytes(length.try_into().unwrap()) as usize)
+            .collect::<Vec<_>>(),
+        );
+      },
+      b"data" => {
+        samples = Some(
+          chunk
+            .chunks_exact(4)
+            .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+            .collect::<Vec<_>>(),
+        );
+      },
+      _ => (),
+    }
+    // Chunks are padded to an even length.
+    rest = rest.get(8 + len + len % 2..).unwrap_or_default();
+  }
+  let samples = samples?;
+  let lengths = lengths?;
+  if lengths.iter().sum::<usize>() != samples.len() {
+    return None;
+  }
+  let mut samples = samples.into_iter();
+  let sentences = lengths
+    .into_iter()
+    .map(|length| samples.by_ref().take(length).collect())
+    .collect();
+  Some((sentences, sample_rate?, key?))
+}
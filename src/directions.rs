@@ -0,0 +1,27 @@
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+/// Text in parentheses or square brackets along with the whitespace before it, or a tone
+/// directive such as `[[dtmf:5]]`, which is left alone.
+static PATTERN: Lazy<Regex> = Lazy::new(|| {
+  Regex::new(r"(?P<directive>\[\[[^\[\]]*\]\])|\s*(?:\([^()]*\)|\[[^\[\]]*\])").unwrap()
+});
+
+/// Removes the stage directions that subtitles and transcripts put in parentheses or brackets,
+/// such as `(laughs)` or `[music]`, which models would otherwise read out. Nested brackets are
+/// removed from the inside out.
+pub fn strip(text: &str) -> String {
+  let mut text = text.to_owned();
+  loop {
+    let stripped = PATTERN.replace_all(&text, |captures: &Captures| {
+      captures
+        .name("directive")
+        .map_or("", |directive| directive.as_str())
+        .to_owned()
+    });
+    if stripped == text {
+      return text.trim_start().to_owned();
+    }
+    text = stripped.into_owned();
+  }
+}
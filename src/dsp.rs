@@ -0,0 +1,168 @@
+/// The length of the frames WSOLA overlaps, long enough to span a couple of pitch periods.
+const WSOLA_FRAME_MS: usize = 20;
+/// The amplitude of the watermark relative to the audio's, low enough to be masked by the speech.
+const WATERMARK_STRENGTH: f32 = 0.01;
+/// How quickly the watermark follows the audio's amplitude.
+const WATERMARK_ENVELOPE_MS: f64 = 10.0;
+/// Mixed into the state of pseudo-random number generators so that it isn't zero.
+const RANDOM_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Resamples mono audio from one sample rate to another using linear interpolation.
+pub fn resample_linear(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+  if from_rate == to_rate || samples.is_empty() {
+    return samples.to_vec();
+  }
+  let output_len = (samples.len() as u64 * to_rate as u64 / from_rate as u64) as usize;
+  interpolate(samples, from_rate as f64 / to_rate as f64, output_len)
+}
+
+/// The ratio by which shifting the pitch by `semitones` speeds up the audio.
+pub fn pitch_ratio(semitones: f64) -> f64 {
+  2f64.powf(semitones / 12.0)
+}
+
+/// Shifts the pitch of mono audio by `semitones` by resampling it, which also shortens or
+/// lengthens it by the same ratio.
+pub fn pitch_shift(samples: &[f32], semitones: f64) -> Vec<f32> {
+  if semitones == 0.0 || samples.is_empty() {
+    return samples.to_vec();
+  }
+  let step = pitch_ratio(semitones);
+  interpolate(samples, step, (samples.len() as f64 / step) as usize)
+}
+
+/// Amplifies or attenuates audio by `gain_db` decibels, clipping to [-1, 1].
+pub fn apply_gain(samples: &mut [f32], gain_db: f64) {
+  if gain_db == 0.0 {
+    return;
+  }
+  let factor = 10f64.powf(gain_db / 20.0) as f32;
+  for sample in samples {
+    *sample = (*sample * factor).clamp(-1.0, 1.0);
+  }
+}
+
+/// Adds an inaudible spread-spectrum watermark to mono audio: a pseudo-random sequence of ±1
+/// generated from `key`, scaled to a fraction of the audio's amplitude so that speech masks it and
+/// silence stays silent. It can be detected by correlating the audio with the same sequence, which
+/// starts again at the start of each buffer.
+pub fn watermark(samples: &mut [f32], sample_rate: u64, key: u64) {
+  let smoothing = 1.0 - (-1000.0 / (WATERMARK_ENVELOPE_MS * sample_rate as f64)).exp() as f32;
+  let mut state = key ^ RANDOM_SEED;
+  let mut envelope = 0.0f32;
+  for sample in samples {
+    envelope += smoothing * (sample.abs() - envelope);
+    let chip = if xorshift(&mut state) >> 63 == 1 {
+      1.0
+    }
+    else {
+      -1.0
+    };
+    *sample = (*sample + chip * WATERMARK_STRENGTH * envelope).clamp(-1.0, 1.0);
+  }
+}
+
+/// Converts audio to 16-bit integers. With `dither`, TPDF (triangular probability density
+/// function) noise of up to one step is added first, so that quiet speech doesn't turn into
+/// quantisation distortion. The noise generator's state is kept in `state`, so that the noise
+/// carries on from one buffer to the next.
+pub fn to_s16(samples: &[f32], dither: bool, state: &mut u64) -> Vec<i16> {
+  if *state == 0 {
+    *state = RANDOM_SEED;
+  }
+  samples
+    .iter()
+    .map(|sample| {
+      let noise = if dither {
+        // The difference of two uniform variables has a triangular distribution.
+        (xorshift(state) >> 40) as f32 / (1u64 << 24) as f32
+          - (xorshift(state) >> 40) as f32 / (1u64 << 24) as f32
+      }
+      else {
+        0.0
+      };
+      // Casting saturates, clipping samples outside [-1, 1].
+      (sample * i16::MAX as f32 + noise).round() as i16
+    })
+    .collect()
+}
+
+/// Advances xorshift64* pseudo-random number generator state, which must not be zero, returning
+/// the next number.
+fn xorshift(state: &mut u64) -> u64 {
+  *state ^= *state >> 12;
+  *state ^= *state << 25;
+  *state ^= *state >> 27;
+  state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+/// Reads `output_len` samples at intervals of `step` input samples, interpolating linearly.
+fn interpolate(samples: &[f32], step: f64, output_len: usize) -> Vec<f32> {
+  (0..output_len)
+    .map(|i| {
+      let position = i as f64 * step;
+      let index = position as usize;
+      let fraction = (position - index as f64) as f32;
+      let current = samples[index.min(samples.len() - 1)];
+      let next = samples[(index + 1).min(samples.len() - 1)];
+      current + (next - current) * fraction
+    })
+    .collect()
+}
+
+/// Time-stretches mono audio to `output_len` samples without changing its pitch, using WSOLA
+/// (waveform similarity overlap-add): each frame is taken from near where it would fall at the
+/// new tempo, at the position that best continues the previous frame's waveform.
+pub fn time_stretch(samples: &[f32], output_len: usize, sample_rate: u64) -> Vec<f32> {
+  if samples.is_empty() {
+    return vec![0.0; output_len];
+  }
+  if output_len == samples.len() {
+    return samples.to_vec();
+  }
+  let frame = (sample_rate as usize * WSOLA_FRAME_MS / 1000).max(16);
+  let hop = frame / 2;
+  let tolerance = hop / 2;
+  let window: Vec<f32> = (0..frame)
+    .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / frame as f32).cos())
+    .collect();
+  let input = |i: usize| samples.get(i).copied().unwrap_or(0.0);
+  let scale = samples.len() as f64 / output_len as f64;
+  let mut output = vec![0.0; output_len + frame];
+  let mut weights = vec![0.0; output_len + frame];
+  let mut previous = 0;
+  let mut position = 0;
+  while position < output_len {
+    let nominal = (position as f64 * scale) as usize;
+    let offset = if position == 0 {
+      0
+    }
+    else {
+      // Where the previous frame's waveform naturally continues.
+      let continuation = previous + hop;
+      (nominal.saturating_sub(tolerance)..=nominal + tolerance)
+        .map(|candidate| {
+          let similarity: f32 = (0..hop)
+            .map(|i| input(candidate + i) * input(continuation + i))
+            .sum();
+          (candidate, similarity)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(candidate, _)| candidate)
+        .unwrap_or(nominal)
+    };
+    for (i, weight) in window.iter().enumerate() {
+      output[position + i] += input(offset + i) * weight;
+      weights[position + i] += weight;
+    }
+    previous = offset;
+    position += hop;
+  }
+  output.truncate(output_len);
+  for (sample, weight) in output.iter_mut().zip(weights) {
+    if weight > 1e-3 {
+      *sample /= weight;
+    }
+  }
+  output
+}
@@ -1,16 +1,223 @@
 mod imp;
 
+use std::env;
+
 use gstreamer::{glib, prelude::StaticType, Rank};
 
+/// Environment variable setting the rank of the filter's `tts` alias, so that auto-plugging can
+/// be opted into without rebuilding, e.g. `GST_COQUITTS_TTS_RANK=primary`.
+const TTS_RANK_ENV: &str = "GST_COQUITTS_TTS_RANK";
+
+/// The name of the custom meta that applications can put on text buffers to have the audio
+/// time-stretched to an exact duration, given in nanoseconds by its `duration` field.
+pub const DURATION_META_NAME: &str = "CoquittsDurationMeta";
+
+/// The name of the custom meta on the audio of each row of `text/csv` input, with the row's `id`
+/// string field.
+pub const ROW_META_NAME: &str = "CoquittsRowMeta";
+
+/// The name of the custom meta that applications can put on text buffers to correlate them with
+/// their audio, given by its `id` string field, which is passed on to the audio's
+/// `CoquittsSourceMeta` and the element's messages about it.
+pub const REQUEST_META_NAME: &str = "CoquittsRequestMeta";
+
+/// The name of the custom meta marking a text buffer as a piece of a message that's still being
+/// written, such as tokens streamed from a language model. Pieces are gathered up and synthesised
+/// a sentence at a time, and the next buffer without the meta ends the message.
+pub const PARTIAL_META_NAME: &str = "CoquittsPartialMeta";
+
+/// The name of the custom meta on every audio buffer saying what it was synthesised from: the
+/// `text`, the `model` and `backend`, and the `speaker`, `language`, `speed`, `pitch-semitones`
+/// and `gain-db` used, along with the `id` of the request if it had one.
+pub const SOURCE_META_NAME: &str = "CoquittsSourceMeta";
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Default, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstCoquittsGpuMode")]
+pub enum GpuMode {
+  #[enum_value(name = "Run the model on the CPU", nick = "off")]
+  #[default]
+  Off,
+  #[enum_value(name = "Run the model on a CUDA GPU", nick = "on")]
+  On,
+  #[enum_value(
+    name = "Use a CUDA or Apple Silicon GPU if there is one, or else the CPU",
+    nick = "auto"
+  )]
+  Auto,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Default, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstCoquittsBackend")]
+pub enum Backend {
+  #[enum_value(name = "Run Coqui in this process, using embedded Python", nick = "local")]
+  #[default]
+  Local,
+  #[enum_value(name = "Send text to a Coqui TTS server over HTTP", nick = "server")]
+  Server,
+  #[enum_value(
+    name = "Run a VITS model exported to ONNX in Rust, without Coqui TTS, if built with the `onnx` feature",
+    nick = "onnx"
+  )]
+  Onnx,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Default, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstCoquittsSplitSentences")]
+pub enum SplitSentences {
+  #[enum_value(name = "Use the model's default", nick = "model-default")]
+  #[default]
+  ModelDefault,
+  #[enum_value(name = "Split text into sentences and push one buffer per sentence", nick = "on")]
+  On,
+  #[enum_value(name = "Synthesise text as a whole", nick = "off")]
+  Off,
+  #[enum_value(
+    name = "Split text into sentences with the element's own segmenter, which knows common abbreviations, instead of the model's, and push one buffer per sentence",
+    nick = "pre-split"
+  )]
+  PreSplit,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Default, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstCoquittsLeaky")]
+pub enum Leaky {
+  #[enum_value(name = "Not leaky, block upstream when the queue is full", nick = "none")]
+  #[default]
+  None,
+  #[enum_value(name = "Drop the newest text when the queue is full", nick = "upstream")]
+  Upstream,
+  #[enum_value(name = "Drop the oldest text when the queue is full", nick = "downstream")]
+  Downstream,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Default, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstCoquittsEmptyTextPolicy")]
+pub enum EmptyTextPolicy {
+  #[enum_value(name = "Pass empty text to the model", nick = "synthesize")]
+  Synthesize,
+  #[enum_value(name = "Skip empty text", nick = "skip")]
+  #[default]
+  Skip,
+  #[enum_value(name = "Skip empty text and push a gap event in its place", nick = "gap")]
+  Gap,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Default, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstCoquittsEmptyOutputPolicy")]
+pub enum EmptyOutputPolicy {
+  #[enum_value(name = "Skip the text with a warning", nick = "skip")]
+  #[default]
+  Skip,
+  #[enum_value(name = "Fail with an error", nick = "error")]
+  Error,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Default, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstCoquittsLongTextPolicy")]
+pub enum LongTextPolicy {
+  #[enum_value(name = "Synthesise the first `max-chars` characters of long text", nick = "truncate")]
+  #[default]
+  Truncate,
+  #[enum_value(name = "Skip long text", nick = "reject")]
+  Reject,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Default, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstCoquittsThrottlePolicy")]
+pub enum ThrottlePolicy {
+  #[enum_value(name = "Wait until the text is within the limit", nick = "delay")]
+  #[default]
+  Delay,
+  #[enum_value(name = "Drop text beyond the limit", nick = "drop")]
+  Drop,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Default, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstCoquittsSegmentStopPolicy")]
+pub enum SegmentStopPolicy {
+  #[enum_value(name = "Push all the audio, leaving downstream to clip it", nick = "push")]
+  #[default]
+  Push,
+  #[enum_value(name = "Cut the audio off at the segment's stop", nick = "clip")]
+  Clip,
+  #[enum_value(name = "Fail with an error", nick = "error")]
+  Error,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, Default, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstCoquittsPriority")]
+pub enum Priority {
+  #[enum_value(name = "Wait for all other synthesis, e.g. for pre-rendering", nick = "batch")]
+  Batch,
+  #[enum_value(name = "Wait for interactive synthesis", nick = "normal")]
+  #[default]
+  Normal,
+  #[enum_value(
+    name = "Go before all other synthesis, e.g. for replies to a user",
+    nick = "interactive"
+  )]
+  Interactive,
+}
+
 glib::wrapper! {
-  pub struct CoquittsFilter(ObjectSubclass<imp::CoquittsFilter>) @extends gstreamer_base::BaseTransform, gstreamer::Element, gstreamer::Object;
+  pub struct CoquittsFilter(ObjectSubclass<imp::CoquittsFilter>) @extends gstreamer_base::BaseTransform, gstreamer::Element, gstreamer::Object, @implements gstreamer::Preset;
 }
 
 pub fn register(plugin: &gstreamer::Plugin) -> Result<(), glib::BoolError> {
+  #[cfg(feature = "doc")]
+  {
+    use gstreamer::prelude::PluginApiExt;
+    GpuMode::static_type().mark_as_plugin_api(gstreamer::PluginAPIFlags::empty());
+    SplitSentences::static_type().mark_as_plugin_api(gstreamer::PluginAPIFlags::empty());
+    Leaky::static_type().mark_as_plugin_api(gstreamer::PluginAPIFlags::empty());
+    EmptyTextPolicy::static_type().mark_as_plugin_api(gstreamer::PluginAPIFlags::empty());
+    EmptyOutputPolicy::static_type().mark_as_plugin_api(gstreamer::PluginAPIFlags::empty());
+    LongTextPolicy::static_type().mark_as_plugin_api(gstreamer::PluginAPIFlags::empty());
+    ThrottlePolicy::static_type().mark_as_plugin_api(gstreamer::PluginAPIFlags::empty());
+    SegmentStopPolicy::static_type().mark_as_plugin_api(gstreamer::PluginAPIFlags::empty());
+    Priority::static_type().mark_as_plugin_api(gstreamer::PluginAPIFlags::empty());
+    Backend::static_type().mark_as_plugin_api(gstreamer::PluginAPIFlags::empty());
+  }
+
+  gstreamer::meta::CustomMeta::register(DURATION_META_NAME, &[]);
+  gstreamer::meta::CustomMeta::register(ROW_META_NAME, &[]);
+  gstreamer::meta::CustomMeta::register(PARTIAL_META_NAME, &[]);
+  gstreamer::meta::CustomMeta::register(REQUEST_META_NAME, &[]);
+  gstreamer::meta::CustomMeta::register(SOURCE_META_NAME, &[]);
+
   gstreamer::Element::register(
     Some(plugin),
     "coquitts",
     Rank::None,
     CoquittsFilter::static_type(),
+  )?;
+  gstreamer::Element::register(
+    Some(plugin),
+    "tts",
+    tts_rank(),
+    CoquittsFilter::static_type(),
   )
 }
+
+fn tts_rank() -> Rank {
+  match env::var(TTS_RANK_ENV)
+    .unwrap_or_default()
+    .to_ascii_lowercase()
+    .as_str()
+  {
+    "marginal" => Rank::Marginal,
+    "secondary" => Rank::Secondary,
+    "primary" => Rank::Primary,
+    _ => Rank::None,
+  }
+}
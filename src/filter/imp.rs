@@ -1,34 +1,153 @@
-use std::{str, sync::Mutex};
+use std::{
+  cell::{Cell, RefCell},
+  collections::{hash_map::RandomState, BTreeMap, HashMap, VecDeque},
+  env, fmt, fs,
+  hash::{BuildHasher, Hasher},
+  io::Write,
+  path::Path,
+  str,
+  sync::{Condvar, Mutex, TryLockError},
+  thread::{self, JoinHandle},
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use byte_slice_cast::AsByteSlice;
+use byte_slice_cast::{AsByteSlice, AsMutByteSlice, ToByteSlice, ToMutByteSlice};
 use gstreamer::{
   caps::NoFeature,
-  glib::{self, ParamSpec, Value},
+  glib::{self, translate::ToGlibPtr, ParamSpec, Value},
   param_spec::GstParamSpecBuilderExt,
-  prelude::{ParamSpecBuilderExt, ToValue},
+  prelude::{
+    AllocatorExt, ClockExt, Displayable, ElementExt, GstObjectExt, ObjectExt, PadExt,
+    PadExtManual, ParamSpecBuilderExt, PresetExt, StaticType, ToSendValue, ToValue,
+  },
+  query::Allocation,
   subclass::{
-    prelude::{ElementImpl, GstObjectImpl, ObjectImpl, ObjectSubclass},
+    prelude::{
+      ElementImpl, ElementImplExt, GstObjectImpl, ObjectImpl, ObjectSubclass, ObjectSubclassExt,
+      ObjectSubclassIsExt, PresetImpl,
+    },
     ElementMetadata,
   },
-  Buffer, Caps, CapsIntersectMode, DebugCategory, ErrorMessage, FlowError, PadDirection,
-  PadPresence, PadTemplate,
+  AllocationParams, Allocator, Buffer, BufferFlags, BufferRef, Caps, CapsIntersectMode,
+  ClockTime, DebugCategory, ErrorMessage, Event, EventType, EventView, FlowError, FlowSuccess,
+  Format, LoggableError, Memory, PadDirection, PadPresence, PadTemplate, QueryRef, QueryViewMut,
+  StateChange, StateChangeError, StateChangeSuccess,
+};
+use gstreamer_audio::{
+  AudioCapsBuilder, AudioInfo, AudioLayout, AudioMeta, AUDIO_FORMAT_F32, AUDIO_FORMAT_F64,
+  AUDIO_FORMAT_S16,
 };
-use gstreamer_audio::{AudioCapsBuilder, AUDIO_FORMAT_F32};
 use gstreamer_base::{
+  prelude::{BaseTransformExt, BaseTransformExtManual},
   subclass::{
-    base_transform::{BaseTransformImpl, BaseTransformImplExt, GenerateOutputSuccess},
+    base_transform::{BaseTransformImpl, BaseTransformImplExt},
     BaseTransformMode,
   },
   BaseTransform,
 };
 use once_cell::sync::Lazy;
-use pyo3::{
-  types::{PyBool, PyDict, PyList, PyModule},
-  Py, PyAny, Python,
+use pyo3::{exceptions::PyValueError, types::PyModule, Py, PyAny, PyErr, PyResult, Python};
+
+use super::{
+  Backend, EmptyOutputPolicy, EmptyTextPolicy, GpuMode, Leaky, LongTextPolicy, Priority,
+  SegmentStopPolicy, SplitSentences, ThrottlePolicy, DURATION_META_NAME, PARTIAL_META_NAME,
+  REQUEST_META_NAME, ROW_META_NAME, SOURCE_META_NAME,
 };
+use crate::{
+  cache::{sha256_hex, CacheKey, DiskCache, MemoryCache},
+  directions, dsp,
+  labels::SpeakerMap,
+  langdetect,
+  lexicon::Lexicon,
+  message::Message,
+  normalize,
+  onnx::OnnxModel,
+  profile::{self, Stage},
+  progress::Progress,
+  punctuate, python,
+  roomtone::RoomTone,
+  rules::ReplacementRules,
+  scheduler, segment, server, ssml,
+  throttle::Throttle,
+  tone::{self, Segment},
+  translit,
+  verbalize::{self, Locale},
+  voicemap::VoiceMap,
+};
+#[cfg(feature = "metrics")]
+use crate::metrics::{self, Metrics};
 
 const DEFAULT_MODEL: &str = "tts_models/tr/common-voice/glow-tts";
-const DEFAULT_GPU: bool = false;
+const DEFAULT_GPU: GpuMode = GpuMode::Off;
+const DEFAULT_FIT_DURATION: bool = false;
+const DEFAULT_VERBALIZE_NUMBERS: bool = false;
+const DEFAULT_SPLIT_SENTENCES: SplitSentences = SplitSentences::ModelDefault;
+const DEFAULT_MAX_QUEUE_BUFFERS: u32 = 0;
+const DEFAULT_LEAKY: Leaky = Leaky::None;
+const DEFAULT_MAX_LATENESS: i64 = -1;
+const DEFAULT_SILENCE_PADDING_START_MS: u32 = 0;
+const DEFAULT_SILENCE_PADDING_END_MS: u32 = 0;
+const DEFAULT_MAX_RETRIES: u32 = 0;
+const DEFAULT_FALLBACK_TO_CPU: bool = false;
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+const DEFAULT_EMPTY_TEXT_POLICY: EmptyTextPolicy = EmptyTextPolicy::Skip;
+const DEFAULT_EMPTY_TEXT_GAP_MS: u32 = 0;
+const DEFAULT_DRY_RUN: bool = false;
+const DEFAULT_DRY_RUN_SAMPLE_RATE: u32 = 22050;
+const DEFAULT_PITCH_SEMITONES: f64 = 0.0;
+const DEFAULT_GAIN_DB: f64 = 0.0;
+const DEFAULT_NUM_WORKERS: u32 = 1;
+const DEFAULT_MAX_LATENCY: u64 = 0;
+const DEFAULT_PROFILE: bool = false;
+const DEFAULT_CACHE_MAX_BYTES: u64 = 256 * 1024 * 1024;
+const DEFAULT_MEMORY_CACHE_UTTERANCES: u32 = 0;
+const DEFAULT_MAX_CHARS: u32 = 0;
+const DEFAULT_LONG_TEXT_POLICY: LongTextPolicy = LongTextPolicy::Truncate;
+const DEFAULT_WATCH_VOICE_FILE: bool = false;
+const DEFAULT_SSML: bool = false;
+const DEFAULT_PRIORITY: Priority = Priority::Normal;
+const DEFAULT_LOW_LATENCY_SPLIT: bool = false;
+const DEFAULT_SPEAKER_INDEX: i32 = -1;
+const DEFAULT_WATERMARK: bool = false;
+const DEFAULT_WATERMARK_KEY: u64 = 0;
+const DEFAULT_OUTPUT_BLOCK_SAMPLES: u32 = 0;
+const DEFAULT_PAD_LAST_BLOCK: bool = false;
+const DEFAULT_ADAPTIVE: bool = false;
+const DEFAULT_ADAPTIVE_THRESHOLD: u32 = 4;
+const DEFAULT_ADAPTIVE_SPEED: f64 = 1.25;
+#[cfg(feature = "metrics")]
+const DEFAULT_METRICS_PORT: u32 = 0;
+const DEFAULT_RESTORE_PUNCTUATION: bool = false;
+const DEFAULT_ROOM_TONE_GAIN_DB: f64 = -20.0;
+const DEFAULT_UNLOAD_AFTER_IDLE_SECONDS: u32 = 0;
+const DEFAULT_BACKEND: Backend = Backend::Local;
+const DEFAULT_SERVER_URL: &str = "http://localhost:5002";
+const DEFAULT_NORMALIZE_UNICODE: bool = true;
+const DEFAULT_LIVE_SCHEDULING: bool = false;
+const DEFAULT_SEGMENT_STOP_POLICY: SegmentStopPolicy = SegmentStopPolicy::Push;
+const DEFAULT_DITHER: bool = true;
+const DEFAULT_GAPLESS: bool = false;
+const DEFAULT_RESAMPLE: bool = false;
+const DEFAULT_MAX_CHARS_PER_MINUTE: u32 = 0;
+const DEFAULT_THROTTLE_POLICY: ThrottlePolicy = ThrottlePolicy::Delay;
+const DEFAULT_DEDUPE_WINDOW: u64 = 0;
+const DEFAULT_STRIP_STAGE_DIRECTIONS: bool = false;
+const DEFAULT_EMPTY_OUTPUT_POLICY: EmptyOutputPolicy = EmptyOutputPolicy::Skip;
+const VOICE_FILE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long waiting for the GIL has to take to be logged as contention.
+const GIL_CONTENTION_THRESHOLD: Duration = Duration::from_millis(50);
+/// How much each synthesis time measured moves the running average of them.
+const LATENCY_SMOOTHING: f64 = 0.2;
+/// How much the average synthesis time has to grow beyond the latency last reported before the
+/// pipeline is asked to recalculate its latency.
+const LATENCY_GROWTH_THRESHOLD: f64 = 1.1;
+const MAX_NUM_WORKERS: u32 = 16;
+/// How much silence to produce per character of text in dry-run mode, roughly matching speech.
+const DRY_RUN_MS_PER_CHAR: u32 = 60;
+/// What the `server` backend is asked to synthesise to find out its sample rate.
+const SERVER_PROBE_TEXT: &str = "Hello.";
+const AUTO_LANGUAGE: &str = "auto";
+const FALLBACK_LANGUAGE: &str = "en";
 
 static CAT: Lazy<DebugCategory> = Lazy::new(|| {
   DebugCategory::new(
@@ -38,14 +157,128 @@ static CAT: Lazy<DebugCategory> = Lazy::new(|| {
   )
 });
 
+static PROFILE_CAT: Lazy<DebugCategory> = Lazy::new(|| {
+  DebugCategory::new(
+    "coquitts-profile",
+    gstreamer::DebugColorFlags::empty(),
+    Some("Timings of the stages of text to speech using Coqui"),
+  )
+});
+
+/// Output caps, preferring 32-bit floats and an explicit interleaved layout, which some elements
+/// fail without. With only one channel both layouts hold the same samples. 16-bit integers are
+/// offered last, for elements that only take integers.
 fn src_caps_builder() -> AudioCapsBuilder<NoFeature> {
-  AudioCapsBuilder::new().format(AUDIO_FORMAT_F32).channels(1)
+  AudioCapsBuilder::new()
+    .format_list([AUDIO_FORMAT_F32, AUDIO_FORMAT_F64, AUDIO_FORMAT_S16])
+    .layout_list([AudioLayout::Interleaved, AudioLayout::NonInterleaved])
+    .channels(1)
 }
 
 static SRC_CAPS: Lazy<Caps> = Lazy::new(|| src_caps_builder().build());
 
-static SINK_CAPS: Lazy<Caps> =
-  Lazy::new(|| Caps::builder("text/x-raw").field("format", "utf8").build());
+static MARKS_CAPS: Lazy<Caps> = Lazy::new(|| Caps::builder("application/x-json").build());
+
+/// The name of the element message posted while a model is being downloaded, with the `model`,
+/// the `bytes` downloaded so far, and the `total` bytes and `percent` done if the size is known.
+const DOWNLOAD_PROGRESS_MESSAGE: &str = "coquitts-download-progress";
+const DOWNLOAD_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// The name of the element message posted when a model has been loaded, with the `model`, the
+/// `device` it's running on and the `load-time`.
+const MODEL_LOADED_MESSAGE: &str = "coquitts-model-loaded";
+/// The name of the element message posted when a text with an id has been synthesised, with the
+/// `id`, and the `timestamp` and `duration` of its audio.
+const UTTERANCE_MESSAGE: &str = "coquitts-utterance";
+/// The name of the custom serialized downstream event that switches the voice cloning input. Its
+/// structure has either a `path` string field or a `buffer` field holding an audio file.
+const SET_VOICE_FILE_EVENT: &str = "coquitts-set-voice-file";
+/// The name of the custom serialized downstream event that discards the texts waiting to be
+/// synthesised, while those already being synthesised carry on.
+const FLUSH_TEXT_QUEUE_EVENT: &str = "coquitts-flush-text-queue";
+/// The name of the custom serialized downstream event pushed just before the audio of an SSML
+/// mark. Its structure has the mark's `name`, and its `timestamp` if the text had one.
+const MARK_EVENT: &str = "coquitts-mark";
+thread_local! {
+  /// The index of the worker running on the current thread, which selects its synthesiser. Other
+  /// threads share the first worker's.
+  static WORKER_INDEX: Cell<usize> = const { Cell::new(0) };
+  /// The model `voice-map` picked for the text being synthesised on the current thread, which
+  /// takes the place of the active model.
+  static MAPPED_MODEL: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Selects the model `voice-map` picked for the current thread's text until it's dropped.
+struct MappedModel;
+
+impl MappedModel {
+  fn select(model: Option<String>) -> Self {
+    MAPPED_MODEL.with(|mapped| *mapped.borrow_mut() = model);
+    Self
+  }
+}
+
+impl Drop for MappedModel {
+  fn drop(&mut self) {
+    MAPPED_MODEL.with(|mapped| *mapped.borrow_mut() = None);
+  }
+}
+
+/// The voice to synthesise a message with, from the settings or the message's overrides.
+#[derive(Debug, Clone, Copy)]
+struct Voice<'a> {
+  language: Option<&'a str>,
+  speaker: Option<&'a str>,
+}
+
+/// Which part of a text buffer is being synthesised, as `low-latency-split` synthesises the first
+/// clause and the rest separately. Only the start of the first part and the end of the last have
+/// silence padding, so the parts join seamlessly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+  Whole,
+  FirstClause,
+  Rest,
+}
+
+/// Something to push downstream for a text buffer.
+enum Output {
+  Buffer(Buffer),
+  Event(Event),
+  /// The sample rate of the buffers that follow, which changes if the model is switched.
+  Rate(u64),
+  /// Speech marks for the `marks` pad.
+  Marks(Buffer),
+}
+
+/// What became of a text buffer that didn't stop the stream.
+enum Processed {
+  /// The audio and events to push for it.
+  Synthesised(Vec<Output>),
+  /// Nothing to push because the settings say so, e.g. `empty-text-policy=skip`.
+  Skipped,
+  /// Nothing to push because it couldn't be synthesised, which has already been reported.
+  Failed,
+}
+
+const PHONEMES_CAPS_NAME: &str = "text/x-phonemes";
+const CSV_CAPS_NAME: &str = "text/csv";
+
+static SINK_CAPS: Lazy<Caps> = Lazy::new(|| {
+  Caps::builder_full()
+    .structure(
+      gstreamer::Structure::builder("text/x-raw")
+        .field("format", "utf8")
+        .build(),
+    )
+    .structure(
+      gstreamer::Structure::builder("text/x-raw")
+        .field("format", "json")
+        .build(),
+    )
+    .structure(gstreamer::Structure::new_empty(PHONEMES_CAPS_NAME))
+    .structure(gstreamer::Structure::new_empty(CSV_CAPS_NAME))
+    .build()
+});
 
 #[derive(Debug, Clone, Default)]
 struct Settings {
@@ -53,17 +286,236 @@ struct Settings {
   speaker: Option<String>,
   language: Option<String>,
   voice_cloning_input_file: Option<String>,
-  gpu: bool,
+  gpu: GpuMode,
+  fit_duration: bool,
+  lexicon_file: Option<String>,
+  replacement_rules: ReplacementRules,
+  verbalize_numbers: bool,
+  python_home: Option<String>,
+  venv_path: Option<String>,
+  speaker_latents_cache_dir: Option<String>,
+  split_sentences: SplitSentences,
+  max_queue_buffers: u32,
+  leaky: Leaky,
+  max_lateness: i64,
+  model_candidates: Vec<String>,
+  silence_padding_start_ms: u32,
+  silence_padding_end_ms: u32,
+  max_retries: u32,
+  fallback_to_cpu: bool,
+  dry_run: bool,
+  dry_run_sample_rate: u32,
+  speaker_embedding: Option<Vec<f32>>,
+  empty_text_policy: EmptyTextPolicy,
+  empty_text_gap_ms: u32,
+  progress_file: Option<String>,
+  pitch_semitones: f64,
+  gain_db: f64,
+  num_workers: u32,
+  max_latency: u64,
+  profile: bool,
+  extra_kwargs: Option<String>,
+  cache_dir: Option<String>,
+  cache_max_bytes: u64,
+  memory_cache_utterances: u32,
+  max_chars: u32,
+  long_text_policy: LongTextPolicy,
+  device: Option<String>,
+  watch_voice_file: bool,
+  ssml: bool,
+  priority: Priority,
+  low_latency_split: bool,
+  speaker_index: i32,
+  watermark: bool,
+  watermark_key: u64,
+  output_block_samples: u32,
+  pad_last_block: bool,
+  adaptive: bool,
+  adaptive_threshold: u32,
+  adaptive_model: Option<String>,
+  adaptive_speed: f64,
+  #[cfg(feature = "metrics")]
+  metrics_port: u32,
+  sidecar_dir: Option<String>,
+  restore_punctuation: bool,
+  speaker_map: SpeakerMap,
+  room_tone_file: Option<String>,
+  room_tone_gain_db: f64,
+  unload_after_idle_seconds: u32,
+  backend: Backend,
+  server_url: String,
+  onnx_model_path: Option<String>,
+  normalize_unicode: bool,
+  live_scheduling: bool,
+  segment_stop_policy: SegmentStopPolicy,
+  initial_text: Option<String>,
+  dither: bool,
+  gapless: bool,
+  resample: bool,
+  max_chars_per_minute: u32,
+  throttle_policy: ThrottlePolicy,
+  dedupe_window: u64,
+  strip_stage_directions: bool,
+  voice_map: VoiceMap,
+  empty_output_policy: EmptyOutputPolicy,
 }
 
+#[derive(Debug, Default)]
+struct State {
+  last_detected_language: Option<String>,
+  /// The language of the text stream according to its `language-code` tag, used if the
+  /// `language` property isn't set.
+  tag_language: Option<String>,
+  /// The `tts-speaker` and `tts-language` fields of the sink caps, used if the `speaker` and
+  /// `language` properties aren't set.
+  caps_speaker: Option<String>,
+  caps_language: Option<String>,
+  /// The running time before which downstream can no longer render audio in time, according to
+  /// the last QoS event.
+  qos_earliest_time: Option<ClockTime>,
+  /// The model picked from `model-candidates` to match the negotiated output rate.
+  selected_model: Option<String>,
+  /// Whether the sink caps are `text/x-phonemes`, so input bypasses text preprocessing and
+  /// grapheme-to-phoneme conversion.
+  phoneme_input: bool,
+  /// Whether the sink caps are `text/x-raw,format=json`, so each buffer is a JSON message with
+  /// per-message voice overrides.
+  json_input: bool,
+  /// Whether the sink caps are `text/csv`, so input is split into rows of an id and a text.
+  csv_input: bool,
+  /// The end of the last `text/csv` buffer, the start of a row that isn't complete yet.
+  csv_remainder: Vec<u8>,
+  /// Text of `CoquittsPartialMeta` buffers that doesn't make up a whole sentence yet.
+  partial_text: Vec<u8>,
+  /// The timestamp of the piece that `partial_text` starts in.
+  partial_pts: Option<ClockTime>,
+  /// The request id of the partial message, from its pieces' `CoquittsRequestMeta`.
+  partial_id: Option<String>,
+  /// The allocator and parameters downstream asked for, if audio can't be handed over in the
+  /// memory it was synthesised into.
+  allocation: Option<(Option<Allocator>, AllocationParams)>,
+  /// Whether downstream supports `GstAudioMeta` on buffers.
+  audio_meta: bool,
+  /// Whether `adaptive` mode is catching up with a backlog of text.
+  adapting: bool,
+  /// Whether the src caps are `F64`, so samples are converted to double precision.
+  f64_output: bool,
+  /// Whether the src caps are `S16`, so samples are converted to integers.
+  s16_output: bool,
+  /// The state of the dither noise generator for `S16` output.
+  dither_state: u64,
+  /// Whether the src caps have the non-interleaved layout, which requires `GstAudioMeta`.
+  non_interleaved: bool,
+  /// The sample rate of the src caps, which audio is resampled to with `resample`.
+  output_rate: Option<u64>,
+  /// Incremented by each `coquitts-set-voice-file` event, so that only the latest one takes
+  /// effect.
+  voice_file_generation: u64,
+  /// Where the audio pushed so far ends, answering position queries.
+  output_position: Option<ClockTime>,
+  /// The number of samples synthesised since the last flush in `gapless` mode, the offset of the
+  /// next buffer.
+  gapless_offset: u64,
+  /// How much audio has been pushed since the last flush, answering duration queries upstream
+  /// can't.
+  output_duration: Option<ClockTime>,
+  /// A running average of how long synthesising a text takes.
+  average_latency: Option<ClockTime>,
+  /// The measured latency last reported in a latency query.
+  reported_latency: Option<ClockTime>,
+  /// Whether `initial-text` has been synthesised since the element started.
+  initial_text_pushed: bool,
+  /// `initial-text` waiting for upstream to start its stream before it's queued.
+  pending_initial_text: Option<String>,
+  /// Whether upstream has sent a segment since the element started, so that text can be queued.
+  upstream_segment: bool,
+  /// Whether text from the `synthesize` signal was queued before anything arrived on the sink
+  /// pad, so a worker has to start the output stream before pushing its audio.
+  start_stream: bool,
+  /// The last text synthesised and when it arrived, for `dedupe-window`.
+  last_text: Option<(String, Instant)>,
+}
+
+/// Text buffers waiting to be synthesised by the worker threads.
+#[derive(Debug, Default)]
+struct Queue {
+  buffers: VecDeque<Buffer>,
+  /// How many buffers taken from the queue the workers are currently synthesising.
+  busy: usize,
+  flushing: bool,
+  shutdown: bool,
+  /// Incremented on every flush, so the workers can tell that their current buffers are stale.
+  epoch: u64,
+  /// The sequence number given to the next buffer taken from the queue.
+  next_sequence: u64,
+  /// The sequence number of the buffer whose output is to be pushed next, so that output stays
+  /// in input order when several workers synthesise in parallel.
+  next_push: u64,
+  /// The last error the worker got when pushing downstream, returned to upstream on its next
+  /// buffer.
+  flow_error: Option<FlowError>,
+  processed: u64,
+  dropped: u64,
+  /// The texts the workers are synthesising, by epoch and sequence number.
+  in_flight: BTreeMap<(u64, u64), String>,
+  /// The number of text buffers accepted, used as the offset of buffers that don't have one.
+  input_count: u64,
+}
+
+/// A loaded model and the name it was loaded from.
+type Synth = (String, Py<PyAny>);
+
+/// The audio of each of an utterance's sentences, and its sample rate.
+type Utterance = (Vec<Vec<f32>>, u64);
+
 pub struct CoquittsFilter {
   #[allow(dead_code)]
   settings: Mutex<Settings>,
-  synth: Mutex<Option<Py<PyAny>>>,
+  state: Mutex<State>,
+  queue: Mutex<Queue>,
+  queue_cond: Condvar,
+  workers: Mutex<Vec<JoinHandle<()>>>,
+  /// The loaded model and the name it was loaded from, for each worker.
+  synths: Vec<Mutex<Option<Synth>>>,
+  /// The models each worker has switched away from for `voice-map`, kept so that switching back
+  /// doesn't load them again.
+  parked_synths: Vec<Mutex<HashMap<String, Py<PyAny>>>>,
+  /// Native sample rates of the models in `model-candidates`, so each is only looked up once.
+  candidate_rates: Mutex<HashMap<String, u64>>,
+  /// Candidate models that had to be loaded to find their rates, kept until one is selected so
+  /// that it isn't loaded a second time.
+  candidate_synths: Mutex<HashMap<String, Py<PyAny>>>,
+  /// What each loaded model supports, as reported by the `model-capabilities` property.
+  model_capabilities: Mutex<HashMap<String, gstreamer::Structure>>,
+  lexicon: Mutex<Option<Lexicon>>,
+  /// The sample rate of the audio from the `server` backend, once it's known.
+  server_rate: Mutex<Option<u64>>,
+  /// The `onnx` backend's model, once it's loaded.
+  onnx_model: Mutex<Option<OnnxModel>>,
+  /// The loaded `room-tone-file`, which keeps its place in the loop between utterances.
+  room_tone: Mutex<Option<RoomTone>>,
+  /// Spends `max-chars-per-minute`, shared by all the workers.
+  throttle: Mutex<Throttle>,
+  speaker_latents: Mutex<HashMap<String, Py<PyAny>>>,
+  /// The temporary file holding the reference audio of a `coquitts-set-voice-file` event's
+  /// buffer, while it's the voice cloning input file.
+  voice_buffer_file: Mutex<Option<String>>,
+  /// Recently synthesised utterances, for `memory-cache-utterances`.
+  utterances: Mutex<MemoryCache>,
+  progress: Mutex<Option<Progress>>,
+  /// The `marks` request pad, if it has been requested.
+  marks_pad: Mutex<Option<gstreamer::Pad>>,
+  gil_stats: python::GilStats,
+  /// Counters served on `metrics-port`, which carry on across restarts.
+  #[cfg(feature = "metrics")]
+  metrics: Mutex<Metrics>,
+  #[cfg(feature = "metrics")]
+  metrics_server: Mutex<Option<metrics::Server>>,
 }
 
 #[glib::object_subclass]
 impl ObjectSubclass for CoquittsFilter {
+  type Interfaces = (gstreamer::Preset,);
   type ParentType = BaseTransform;
   type Type = super::CoquittsFilter;
 
@@ -77,8 +529,104 @@ impl ObjectSubclass for CoquittsFilter {
         language: None,
         voice_cloning_input_file: None,
         gpu: DEFAULT_GPU,
+        fit_duration: DEFAULT_FIT_DURATION,
+        lexicon_file: None,
+        replacement_rules: ReplacementRules::default(),
+        verbalize_numbers: DEFAULT_VERBALIZE_NUMBERS,
+        python_home: None,
+        venv_path: None,
+        speaker_latents_cache_dir: None,
+        split_sentences: DEFAULT_SPLIT_SENTENCES,
+        max_queue_buffers: DEFAULT_MAX_QUEUE_BUFFERS,
+        leaky: DEFAULT_LEAKY,
+        max_lateness: DEFAULT_MAX_LATENESS,
+        model_candidates: vec![],
+        silence_padding_start_ms: DEFAULT_SILENCE_PADDING_START_MS,
+        silence_padding_end_ms: DEFAULT_SILENCE_PADDING_END_MS,
+        max_retries: DEFAULT_MAX_RETRIES,
+        fallback_to_cpu: DEFAULT_FALLBACK_TO_CPU,
+        dry_run: DEFAULT_DRY_RUN,
+        dry_run_sample_rate: DEFAULT_DRY_RUN_SAMPLE_RATE,
+        speaker_embedding: None,
+        empty_text_policy: DEFAULT_EMPTY_TEXT_POLICY,
+        empty_text_gap_ms: DEFAULT_EMPTY_TEXT_GAP_MS,
+        progress_file: None,
+        pitch_semitones: DEFAULT_PITCH_SEMITONES,
+        gain_db: DEFAULT_GAIN_DB,
+        num_workers: DEFAULT_NUM_WORKERS,
+        max_latency: DEFAULT_MAX_LATENCY,
+        profile: DEFAULT_PROFILE,
+        extra_kwargs: None,
+        cache_dir: None,
+        cache_max_bytes: DEFAULT_CACHE_MAX_BYTES,
+        memory_cache_utterances: DEFAULT_MEMORY_CACHE_UTTERANCES,
+        max_chars: DEFAULT_MAX_CHARS,
+        long_text_policy: DEFAULT_LONG_TEXT_POLICY,
+        device: None,
+        watch_voice_file: DEFAULT_WATCH_VOICE_FILE,
+        ssml: DEFAULT_SSML,
+        priority: DEFAULT_PRIORITY,
+        low_latency_split: DEFAULT_LOW_LATENCY_SPLIT,
+        speaker_index: DEFAULT_SPEAKER_INDEX,
+        watermark: DEFAULT_WATERMARK,
+        watermark_key: DEFAULT_WATERMARK_KEY,
+        output_block_samples: DEFAULT_OUTPUT_BLOCK_SAMPLES,
+        pad_last_block: DEFAULT_PAD_LAST_BLOCK,
+        adaptive: DEFAULT_ADAPTIVE,
+        adaptive_threshold: DEFAULT_ADAPTIVE_THRESHOLD,
+        adaptive_model: None,
+        adaptive_speed: DEFAULT_ADAPTIVE_SPEED,
+        #[cfg(feature = "metrics")]
+        metrics_port: DEFAULT_METRICS_PORT,
+        sidecar_dir: None,
+        restore_punctuation: DEFAULT_RESTORE_PUNCTUATION,
+        speaker_map: SpeakerMap::default(),
+        room_tone_file: None,
+        room_tone_gain_db: DEFAULT_ROOM_TONE_GAIN_DB,
+        unload_after_idle_seconds: DEFAULT_UNLOAD_AFTER_IDLE_SECONDS,
+        backend: DEFAULT_BACKEND,
+        server_url: DEFAULT_SERVER_URL.into(),
+        onnx_model_path: None,
+        normalize_unicode: DEFAULT_NORMALIZE_UNICODE,
+        live_scheduling: DEFAULT_LIVE_SCHEDULING,
+        segment_stop_policy: DEFAULT_SEGMENT_STOP_POLICY,
+        initial_text: None,
+        dither: DEFAULT_DITHER,
+        gapless: DEFAULT_GAPLESS,
+        resample: DEFAULT_RESAMPLE,
+        max_chars_per_minute: DEFAULT_MAX_CHARS_PER_MINUTE,
+        throttle_policy: DEFAULT_THROTTLE_POLICY,
+        dedupe_window: DEFAULT_DEDUPE_WINDOW,
+        strip_stage_directions: DEFAULT_STRIP_STAGE_DIRECTIONS,
+        voice_map: VoiceMap::default(),
+        empty_output_policy: DEFAULT_EMPTY_OUTPUT_POLICY,
       }),
-      synth: Mutex::new(None),
+      state: Mutex::new(State::default()),
+      queue: Mutex::new(Queue::default()),
+      queue_cond: Condvar::new(),
+      workers: Mutex::new(vec![]),
+      synths: (0..MAX_NUM_WORKERS).map(|_| Mutex::new(None)).collect(),
+      parked_synths: (0..MAX_NUM_WORKERS)
+        .map(|_| Mutex::new(HashMap::new()))
+        .collect(),
+      candidate_rates: Mutex::new(HashMap::new()),
+      candidate_synths: Mutex::new(HashMap::new()),
+      model_capabilities: Mutex::new(HashMap::new()),
+      lexicon: Mutex::new(None),
+      server_rate: Mutex::new(None),
+      onnx_model: Mutex::new(None),
+      room_tone: Mutex::new(None),
+      throttle: Mutex::new(Throttle::default()),
+      speaker_latents: Mutex::new(HashMap::new()),
+      voice_buffer_file: Mutex::new(None),
+      utterances: Mutex::new(MemoryCache::default()),
+      progress: Mutex::new(None),
+      marks_pad: Mutex::new(None),
+      gil_stats: python::GilStats::default(),
+      #[cfg(feature = "metrics")]
+      metrics: Mutex::new(Metrics::default()),
+      #[cfg(feature = "metrics")]
+      metrics_server: Mutex::new(None),
     }
   }
 }
@@ -92,14 +640,52 @@ impl ObjectImpl for CoquittsFilter {
         .blurb(&format!("The Coqui TTS model to use. Defaults to {}. Possible values can be listed with `tts --list_models`", DEFAULT_MODEL))
         .mutable_ready()
         .build(),
+      glib::ParamSpecEnum::builder_with_default("backend", DEFAULT_BACKEND)
+        .nick("Backend")
+        .blurb("Where to synthesise: in this process with embedded Python, by sending text to the Coqui TTS server at `server-url`, or with the VITS model exported to ONNX at `onnx-model-path`, which needs the plugin built with the `onnx` cargo feature and the model's `config.json` next to it")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("server-url")
+        .nick("Server URL")
+        .blurb(&format!("The http:// URL of the Coqui TTS server (`tts-server`) to use with `backend=server`. Defaults to {}", DEFAULT_SERVER_URL))
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("onnx-model-path")
+        .nick("ONNX model path")
+        .blurb("The VITS model exported to ONNX to use with `backend=onnx`. The model's Coqui `config.json` must be in the same directory. Models that use phonemes aren't supported.")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("preset")
+        .nick("Preset")
+        .blurb("Loads the named voice preset, setting `model`, `speaker`, `language` and so on. Presets are read from `GstCoquittsFilter.prs` files, such as the one shipped in `presets/`, in the directories in `GST_PRESET_PATH` and GStreamer's preset directories.")
+        .write_only()
+        .mutable_ready()
+        .build(),
       glib::ParamSpecString::builder("speaker")
         .nick("Speaker")
         .blurb("The speaker name to use, for multi-speaker models.")
         .mutable_ready()
         .build(),
+      glib::ParamSpecInt::builder("speaker-index")
+        .nick("Speaker index")
+        .blurb(&format!("The index of the speaker to use in the model's list of speakers, for multi-speaker models whose speaker names are unwieldy, or -1 to use `speaker`. Only used if `speaker` isn't set. Defaults to {}", DEFAULT_SPEAKER_INDEX))
+        .minimum(-1)
+        .default_value(DEFAULT_SPEAKER_INDEX)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("speaker-map")
+        .nick("Speaker map")
+        .blurb("A JSON object mapping speaker labels at the start of texts, as in scripts (e.g. \"JOHN: Hello\"), to the model's speakers, e.g. {\"JOHN\": \"p225\"}. Labels in the map are removed before synthesis.")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("voice-map")
+        .nick("Voice map")
+        .blurb("A JSON object mapping languages, whether detected or tagged, to the `model` and `speaker` to synthesise them with, e.g. {\"en\": {\"model\": \"tts_models/en/vctk/vits\", \"speaker\": \"p225\"}, \"de\": {\"model\": \"tts_models/de/thorsten/vits\"}}. Mapped models are loaded when first needed and kept loaded. Only speakers apply with backends other than `local`.")
+        .mutable_playing()
+        .build(),
       glib::ParamSpecString::builder("language")
         .nick("Language")
-        .blurb("The language identifier to use, for multi-language models.")
+        .blurb(&format!("The language identifier to use, for multi-language models. Set to `{}` to detect the language of each buffer. If unset, the language of the text stream's `language-code` tag is used.", AUTO_LANGUAGE))
         .mutable_ready()
         .build(),
       glib::ParamSpecString::builder("voice-cloning-input-file")
@@ -107,27 +693,484 @@ impl ObjectImpl for CoquittsFilter {
         .blurb("A WAV file to clone the voice from, for models that support voice cloning.")
         .mutable_ready()
         .build(),
-      glib::ParamSpecBoolean::builder("use-gpu")
+      glib::ParamSpecBoolean::builder("watch-voice-file")
+        .nick("Watch voice file")
+        .blurb(&format!("Whether to watch `voice-cloning-input-file` while running, and switch to the new voice in the background when the file is overwritten. Defaults to {}", DEFAULT_WATCH_VOICE_FILE))
+        .default_value(DEFAULT_WATCH_VOICE_FILE)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecEnum::builder_with_default("use-gpu", DEFAULT_GPU)
         .nick("Use GPU")
-        .blurb(&format!("Whether to use the GPU. Defaults to {}", DEFAULT_GPU))
+        .blurb("Whether to run the model on a GPU. In auto mode the best device available is picked, and an info message names it.")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("device")
+        .nick("Device")
+        .blurb("The torch device to run the model on, such as `mps` for Apple Silicon or `cuda:1` for a particular GPU, overriding `use-gpu`")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecBoolean::builder("fit-duration")
+        .nick("Fit duration")
+        .blurb(&format!("When input buffers have a duration, speed up synthesised speech that would be longer than it. Defaults to {}", DEFAULT_FIT_DURATION))
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("lexicon-file")
+        .nick("Lexicon file")
+        .blurb("A pronunciation lexicon (TSV of word<TAB>replacement, or a JSON object if the name ends in .json) applied to text before synthesis. Reloaded when the file changes.")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("replacement-rules")
+        .nick("Replacement rules")
+        .blurb("A JSON array of [regex, replacement] pairs applied in order to text before synthesis, e.g. [[\"\\\\bAPI\\\\b\", \"A P I\"]]")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("normalize-unicode")
+        .nick("Normalize Unicode")
+        .blurb(&format!("Whether to clean up input text before anything else: removing byte order marks, composing characters to Unicode NFC and collapsing runs of whitespace. Defaults to {}", DEFAULT_NORMALIZE_UNICODE))
+        .default_value(DEFAULT_NORMALIZE_UNICODE)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("strip-stage-directions")
+        .nick("Strip stage directions")
+        .blurb(&format!("Whether to remove text in parentheses or square brackets, such as `(laughs)` or `[music]` in subtitles, which models would otherwise read out. Tone directives are kept. Defaults to {}", DEFAULT_STRIP_STAGE_DIRECTIONS))
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("verbalize-numbers")
+        .nick("Verbalize numbers")
+        .blurb(&format!("Whether to expand numbers, ordinals, currency amounts and dates into words before synthesis, according to the `language` property. Only English is currently supported. Defaults to {}", DEFAULT_VERBALIZE_NUMBERS))
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("python-home")
+        .nick("Python home")
+        .blurb("The PYTHONHOME to initialise the embedded Python interpreter with. Python state isn't isolated per element: there's one interpreter for the whole process, shared by every coquitts, coquittsmixer and coquittsvc element, so this only takes effect for the first element to start Python, and others set to a different home post a warning and use the running interpreter. Use separate processes, or `backend=server`, for different Python installations.")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("venv-path")
+        .nick("Virtualenv path")
+        .blurb("A Python virtualenv whose site-packages are added to the interpreter's module search path before loading the model. Python state isn't isolated per element: the module search path and imported modules are shared by every element in the process, so other elements can import from this virtualenv too, and if Coqui TTS has already been imported from elsewhere, that's what is used, with a warning. Use separate processes, or `backend=server`, for different packages.")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("speaker-latents-cache-dir")
+        .nick("Speaker latents cache directory")
+        .blurb("A directory in which to persist the speaker latents computed from the voice cloning input file, for models such as XTTS that support them, so they are reused across runs.")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecEnum::builder_with_default("split-sentences", DEFAULT_SPLIT_SENTENCES)
+        .nick("Split sentences")
+        .blurb("Whether to split text into sentences before synthesis. When on, each sentence is pushed as a separate buffer. With pre-split, the element's own segmenter is used instead of the model's, so sentence splitting doesn't mistake abbreviations such as \"Dr.\" for the end of a sentence.")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("max-queue-buffers")
+        .nick("Max queue buffers")
+        .blurb(&format!("The maximum number of texts waiting to be synthesised, or 0 for no limit. What happens when the queue is full is controlled by `leaky`. Defaults to {}", DEFAULT_MAX_QUEUE_BUFFERS))
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder_with_default("leaky", DEFAULT_LEAKY)
+        .nick("Leaky")
+        .blurb("Whether to block upstream or drop texts when the queue is full. Dropped texts are reported with QoS messages.")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecInt64::builder("max-lateness")
+        .nick("Max lateness")
+        .blurb(&format!("Skip texts whose timestamps are more than this many nanoseconds behind the time downstream reports it can render, according to QoS events, or -1 to synthesise every text. Skipped texts are reported with QoS messages. Defaults to {}", DEFAULT_MAX_LATENESS))
+        .minimum(-1)
+        .default_value(DEFAULT_MAX_LATENESS)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("model-candidates")
+        .nick("Model candidates")
+        .blurb("A comma-separated list of models, in order of preference. When set, the output rate is negotiated from the native rates of these models, read from their configs where possible, and the first one matching the rate downstream picks is used instead of `model`.")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecUInt::builder("silence-padding-start-ms")
+        .nick("Silence padding start")
+        .blurb(&format!("Milliseconds of silence to prepend to each utterance. The output is timestamped so that speech still starts at the text buffer's timestamp. Defaults to {}", DEFAULT_SILENCE_PADDING_START_MS))
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("silence-padding-end-ms")
+        .nick("Silence padding end")
+        .blurb(&format!("Milliseconds of silence to append to each utterance. Defaults to {}", DEFAULT_SILENCE_PADDING_END_MS))
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("max-retries")
+        .nick("Max retries")
+        .blurb(&format!("How many times to retry synthesis after a CUDA out-of-memory error, clearing torch's cache and backing off exponentially from {}ms between attempts. Defaults to {}", RETRY_BACKOFF.as_millis(), DEFAULT_MAX_RETRIES))
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("fallback-to-cpu")
+        .nick("Fallback to CPU")
+        .blurb(&format!("Whether to move the model to the CPU for the rest of the stream if synthesis still runs out of GPU memory after `max-retries` retries. Defaults to {}", DEFAULT_FALLBACK_TO_CPU))
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("dry-run")
+        .nick("Dry run")
+        .blurb(&format!("Skip Python entirely and produce {}ms of silence per character of text instead of speech, for testing pipelines on machines without Coqui TTS. Defaults to {}", DRY_RUN_MS_PER_CHAR, DEFAULT_DRY_RUN))
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecUInt::builder("dry-run-sample-rate")
+        .nick("Dry run sample rate")
+        .blurb(&format!("The output sample rate in dry-run mode. Defaults to {}", DEFAULT_DRY_RUN_SAMPLE_RATE))
+        .minimum(1)
+        .default_value(DEFAULT_DRY_RUN_SAMPLE_RATE)
+        .mutable_ready()
+        .build(),
+      gstreamer::ParamSpecArray::builder("speaker-embedding")
+        .nick("Speaker embedding")
+        .blurb("A precomputed speaker embedding (d-vector) to use instead of `speaker` or `voice-cloning-input-file`, for models that accept d-vectors and have no separate vocoder. An empty array unsets it.")
+        .element_spec(&glib::ParamSpecFloat::builder("value").build())
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder_with_default("empty-text-policy", DEFAULT_EMPTY_TEXT_POLICY)
+        .nick("Empty text policy")
+        .blurb("What to do with text buffers that are empty or only contain whitespace.")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("empty-text-gap-ms")
+        .nick("Empty text gap")
+        .blurb(&format!("The duration of gap events pushed for empty text when `empty-text-policy` is `gap` and the text buffer has no duration of its own. Defaults to {}", DEFAULT_EMPTY_TEXT_GAP_MS))
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder_with_default("empty-output-policy", DEFAULT_EMPTY_OUTPUT_POLICY)
+        .nick("Empty output policy")
+        .blurb(&format!("What to do when the model returns no audio for text, as some failed inferences do. Defaults to {}", enum_nick(DEFAULT_EMPTY_OUTPUT_POLICY)))
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("progress-file")
+        .nick("Progress file")
+        .blurb("A file in which to record the offsets of text buffers that have been synthesised. Buffers already recorded there are skipped, so an interrupted batch job can be resumed by running it again. Texts that fail to synthesise aren't recorded, so they're tried again.")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecDouble::builder("pitch-semitones")
+        .nick("Pitch (semitones)")
+        .blurb(&format!("Shift the pitch of the synthesised speech by this many semitones, by resampling it, which also makes it faster or slower to the same degree. Defaults to {}", DEFAULT_PITCH_SEMITONES))
+        .minimum(-24.0)
+        .maximum(24.0)
+        .default_value(DEFAULT_PITCH_SEMITONES)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecDouble::builder("gain-db")
+        .nick("Gain (dB)")
+        .blurb(&format!("Amplify or attenuate the synthesised speech by this many decibels, clipping it if necessary. Defaults to {}", DEFAULT_GAIN_DB))
+        .minimum(-60.0)
+        .maximum(24.0)
+        .default_value(DEFAULT_GAIN_DB)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("num-workers")
+        .nick("Number of workers")
+        .blurb(&format!("The number of threads synthesising queued text in parallel, each with its own instance of the model. Audio is still pushed in the order the text arrived. Defaults to {}", DEFAULT_NUM_WORKERS))
+        .minimum(1)
+        .maximum(MAX_NUM_WORKERS)
+        .default_value(DEFAULT_NUM_WORKERS)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecUInt64::builder("max-latency")
+        .nick("Max latency")
+        .blurb(&format!("The latency in nanoseconds to report in latency queries, i.e. how long synthesising a text may take, or 0 to report a running average of measured synthesis times, posting a latency message when it grows. Defaults to {}", DEFAULT_MAX_LATENCY))
+        .default_value(DEFAULT_MAX_LATENCY)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("gapless")
+        .nick("Gapless")
+        .blurb(&format!("Whether to timestamp the audio as one continuous stream from the start of the segment, each utterance starting where the one before it ends whatever the text's timestamps, with buffer offsets counting samples, e.g. for audiobooks. Texts are then synthesised one at a time, whatever `num-workers` is. Defaults to {}", DEFAULT_GAPLESS))
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecBoolean::builder("live-scheduling")
+        .nick("Live scheduling")
+        .blurb(&format!("Whether to timestamp each text with the running time it arrived at, rather than keeping upstream's timestamps, so that its speech starts on the pipeline clock the reported latency (see `max-latency`) after the text arrived, or once the speech before it has finished. Defaults to {}", DEFAULT_LIVE_SCHEDULING))
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("profile")
+        .nick("Profile")
+        .blurb(&format!("Whether to time the stages of synthesising each text buffer (preprocessing, waiting for the GIL, inference, conversion, DSP and copying into buffers), logging them as a structure in the coquitts-profile debug category at INFO level. Defaults to {}", DEFAULT_PROFILE))
+        .default_value(DEFAULT_PROFILE)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("extra-kwargs")
+        .nick("Extra kwargs")
+        .blurb("A JSON object of extra keyword arguments passed verbatim to the model's tts() call, for model-specific options, e.g. {\"voice_dir\": \"voices\", \"preset\": \"fast\"}")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("cache-dir")
+        .nick("Cache directory")
+        .blurb("A directory in which to cache synthesised utterances as WAV files, keyed by their text and voice settings, so that repeated texts are loaded instead of synthesised again")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt64::builder("cache-max-bytes")
+        .nick("Cache max bytes")
+        .blurb(&format!("The maximum size of the cache in `cache-dir`, beyond which the least recently used utterances are removed, or 0 for no limit. Defaults to {}", DEFAULT_CACHE_MAX_BYTES))
+        .default_value(DEFAULT_CACHE_MAX_BYTES)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("memory-cache-utterances")
+        .nick("Memory cache utterances")
+        .blurb(&format!("How many of the most recently synthesised utterances to keep in memory, so that repeated texts are output without synthesising them again, or 0 for none. Defaults to {}", DEFAULT_MEMORY_CACHE_UTTERANCES))
+        .default_value(DEFAULT_MEMORY_CACHE_UTTERANCES)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("max-chars")
+        .nick("Max chars")
+        .blurb(&format!("The maximum number of characters in a text buffer, beyond which `long-text-policy` applies, so that malformed input can't hang synthesis, or 0 for no limit. Defaults to {}", DEFAULT_MAX_CHARS))
+        .default_value(DEFAULT_MAX_CHARS)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder_with_default("long-text-policy", DEFAULT_LONG_TEXT_POLICY)
+        .nick("Long text policy")
+        .blurb("What to do with text buffers longer than `max-chars`.")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("max-chars-per-minute")
+        .nick("Max chars per minute")
+        .blurb(&format!("The most characters to synthesise per minute, beyond which `throttle-policy` applies, so that a runaway producer of text can't monopolise a shared GPU or server, or 0 for no limit. Short bursts of up to a minute's worth go through straight away. Defaults to {}", DEFAULT_MAX_CHARS_PER_MINUTE))
+        .default_value(DEFAULT_MAX_CHARS_PER_MINUTE)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder_with_default("throttle-policy", DEFAULT_THROTTLE_POLICY)
+        .nick("Throttle policy")
+        .blurb("What to do with text beyond `max-chars-per-minute`.")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt64::builder("dedupe-window")
+        .nick("Dedupe window")
+        .blurb(&format!("Skip a text identical to the one before it, ignoring leading and trailing whitespace, if it arrives within this many nanoseconds of it, e.g. for chat integrations that send messages twice, or 0 to synthesise every text. Defaults to {}", DEFAULT_DEDUPE_WINDOW))
+        .default_value(DEFAULT_DEDUPE_WINDOW)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("ssml")
+        .nick("SSML")
+        .blurb(&format!("Whether text is SSML. `<mark name=\"...\"/>` elements push a `{}` custom downstream event just before their audio, and other markup is removed. Defaults to {}", MARK_EVENT, DEFAULT_SSML))
+        .default_value(DEFAULT_SSML)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder_with_default("priority", DEFAULT_PRIORITY)
+        .nick("Priority")
+        .blurb("How synthesis for this element is ordered against synthesis for other elements in the process, which share the Python interpreter and GPU. Text of a lower priority waits while any of a higher priority is being synthesised or waiting to be. JSON messages can override it with a `priority` field.")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("low-latency-split")
+        .nick("Low latency split")
+        .blurb(&format!("Synthesise the first clause of each text, up to its first comma or full stop, and push its audio before synthesising the rest, so that speech starts sooner. The rest follows on seamlessly. Defaults to {}", DEFAULT_LOW_LATENCY_SPLIT))
+        .default_value(DEFAULT_LOW_LATENCY_SPLIT)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("room-tone-file")
+        .nick("Room tone file")
+        .blurb("A WAV file of ambience, such as room tone or background noise, looped and mixed under the synthesised speech")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecDouble::builder("room-tone-gain-db")
+        .nick("Room tone gain (dB)")
+        .blurb(&format!("Amplify or attenuate the `room-tone-file` audio by this many decibels before mixing it under the speech. Defaults to {}", DEFAULT_ROOM_TONE_GAIN_DB))
+        .minimum(-60.0)
+        .maximum(24.0)
+        .default_value(DEFAULT_ROOM_TONE_GAIN_DB)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("watermark")
+        .nick("Watermark")
+        .blurb(&format!("Whether to add an inaudible spread-spectrum watermark to the audio, marking it as machine-generated. It's a pseudo-random sequence generated from `watermark-key`, starting at the start of each buffer, that can be detected by correlation. Defaults to {}", DEFAULT_WATERMARK))
+        .default_value(DEFAULT_WATERMARK)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt64::builder("watermark-key")
+        .nick("Watermark key")
+        .blurb(&format!("The seed of the watermark's pseudo-random sequence, which is needed to detect it. Defaults to {}", DEFAULT_WATERMARK_KEY))
+        .default_value(DEFAULT_WATERMARK_KEY)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("output-block-samples")
+        .nick("Output block samples")
+        .blurb(&format!("Cut each utterance's audio into buffers of this many samples, for RTP payloaders that work best with constant-size buffers, or 0 for a buffer per sentence. The last block is shorter unless `pad-last-block` is set. Defaults to {}", DEFAULT_OUTPUT_BLOCK_SAMPLES))
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("pad-last-block")
+        .nick("Pad last block")
+        .blurb(&format!("Whether to pad the last block of each utterance with silence to `output-block-samples`. Defaults to {}", DEFAULT_PAD_LAST_BLOCK))
+        .default_value(DEFAULT_PAD_LAST_BLOCK)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("adaptive")
+        .nick("Adaptive")
+        .blurb(&format!("Trade quality for speed under load: while more than `adaptive-threshold` texts are waiting, synthesise with `adaptive-model` and `adaptive-speed` until the queue has drained. Defaults to {}", DEFAULT_ADAPTIVE))
+        .default_value(DEFAULT_ADAPTIVE)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("adaptive-threshold")
+        .nick("Adaptive threshold")
+        .blurb(&format!("How many texts have to be waiting for `adaptive` mode to speed up synthesis. Defaults to {}", DEFAULT_ADAPTIVE_THRESHOLD))
+        .default_value(DEFAULT_ADAPTIVE_THRESHOLD)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("adaptive-model")
+        .nick("Adaptive model")
+        .blurb("A faster model to synthesise with while `adaptive` mode is catching up, or unset to keep the model. It's loaded in place of the model each time the mode changes, and should have the same sample rate.")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecDouble::builder("adaptive-speed")
+        .nick("Adaptive speed")
+        .blurb(&format!("What to multiply the speed of speech by while `adaptive` mode is catching up. Defaults to {}", DEFAULT_ADAPTIVE_SPEED))
+        .minimum(1.0)
+        .maximum(4.0)
+        .default_value(DEFAULT_ADAPTIVE_SPEED)
+        .mutable_playing()
+        .build(),
+      #[cfg(feature = "metrics")]
+      glib::ParamSpecUInt::builder("metrics-port")
+        .nick("Metrics port")
+        .blurb(&format!("The TCP port to serve Prometheus metrics on while the element is running, or 0 for none. Defaults to {}", DEFAULT_METRICS_PORT))
+        .maximum(u16::MAX as u32)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("sidecar-dir")
+        .nick("Sidecar directory")
+        .blurb("A directory in which to write a JSON file for each utterance, named after the offset of its text buffer, with its text, model, speaker, language, and the timestamps and durations of the utterance and its sentences in nanoseconds, for building datasets")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecBoolean::builder("restore-punctuation")
+        .nick("Restore punctuation")
+        .blurb(&format!("Add punctuation to texts without any, such as transcripts from speech recognition, so they aren't spoken in a monotone. It's capitalised and ends in a full stop, or for English a question mark if it starts like a question, and English also gets commas after interjections and before some conjunctions. Defaults to {}", DEFAULT_RESTORE_PUNCTUATION))
+        .default_value(DEFAULT_RESTORE_PUNCTUATION)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("unload-after-idle-seconds")
+        .nick("Unload after idle seconds")
+        .blurb(&format!("Unload the model to free memory once no text has been synthesised for this many seconds, loading it again when text next arrives. 0 keeps it loaded. Defaults to {}", DEFAULT_UNLOAD_AFTER_IDLE_SECONDS))
+        .default_value(DEFAULT_UNLOAD_AFTER_IDLE_SECONDS)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder_with_default("segment-stop-policy", DEFAULT_SEGMENT_STOP_POLICY)
+        .nick("Segment stop policy")
+        .blurb("What to do with synthesised audio that would run past the stop of a bounded segment, e.g. one from a seek to a range.")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("initial-text")
+        .nick("Initial text")
+        .blurb("Text to synthesise as soon as the pipeline is playing, e.g. a greeting. Without an upstream element, it's synthesised as the whole stream, followed by EOS.")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecBoolean::builder("resample")
+        .nick("Resample")
+        .blurb(&format!("Whether to resample the model's audio to the rate downstream wants when it doesn't accept the model's own, including when it asks for new caps mid-stream. Otherwise negotiation fails with an error. Defaults to {}", DEFAULT_RESAMPLE))
         .mutable_ready()
         .build(),
+      glib::ParamSpecBoolean::builder("dither")
+        .nick("Dither")
+        .blurb(&format!("Whether to add TPDF dither when converting samples to S16 for downstream elements that need integer samples, so quiet speech doesn't have quantisation distortion. Defaults to {}", DEFAULT_DITHER))
+        .default_value(DEFAULT_DITHER)
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("sample-rate")
+        .nick("Sample rate")
+        .blurb("The native output sample rate of the model, loading it if necessary. 0 if the model can't be loaded.")
+        .read_only()
+        .build(),
+      glib::ParamSpecBoolean::builder("is-loaded")
+        .nick("Is loaded")
+        .blurb("Whether the model has been loaded, so that synthesis can start without delay. A `coquitts-model-loaded` element message is posted when it has.")
+        .read_only()
+        .build(),
+      glib::ParamSpecUInt::builder("queue-length")
+        .nick("Queue length")
+        .blurb("The number of texts waiting to be synthesised.")
+        .read_only()
+        .build(),
+      glib::ParamSpecString::builder("current-text")
+        .nick("Current text")
+        .blurb("The text being synthesised, or the earliest of them with several workers. Unset when idle.")
+        .read_only()
+        .build(),
+      glib::ParamSpecBoxed::builder::<gstreamer::Structure>("stats")
+        .nick("Statistics")
+        .blurb("The number of texts processed and dropped, and how many times the GIL was acquired and how long was spent waiting for it and holding it, to tell whether other Python users in the process are slowing synthesis down.")
+        .read_only()
+        .build(),
+      glib::ParamSpecBoxed::builder::<gstreamer::Structure>("model-capabilities")
+        .nick("Model capabilities")
+        .blurb("What the loaded model supports: whether it is multi-speaker or multi-lingual, whether it supports voice cloning and phoneme input, its native sample rate and its languages. Unset until the model is loaded.")
+        .read_only()
+        .build(),
+      glib::ParamSpecBoxed::builder::<gstreamer::Structure>("speakers-detailed")
+        .nick("Speakers in detail")
+        .blurb("The loaded model's speakers, as a `speakers` array of structures with each one's `name` and `index`, and its `gender` and `locale` where the model gives them away, for voice pickers to sort and filter by. Unset until the model is loaded.")
+        .read_only()
+        .build(),
+      glib::ParamSpecBoxed::builder::<gstreamer::Structure>("effective-settings")
+        .nick("Effective settings")
+        .blurb("The settings actually in use: the loaded model, the speaker, the resolved language and the sample rate, once known. Notified when any of them change.")
+        .read_only()
+        .build(),
     ]
     });
     PROPERTIES.as_ref()
   }
 
+  fn signals() -> &'static [glib::subclass::Signal] {
+    static SIGNALS: Lazy<Vec<glib::subclass::Signal>> = Lazy::new(|| {
+      vec![
+        glib::subclass::Signal::builder("synthesize")
+          .param_types([String::static_type()])
+          .return_type::<bool>()
+          .action()
+          .class_handler(|_, args| {
+            let element = args[0].get::<super::CoquittsFilter>().unwrap();
+            let text = args[1].get::<String>().unwrap();
+            Some(element.imp().synthesize(text).to_value())
+          })
+          .build(),
+        glib::subclass::Signal::builder("load-model")
+          .return_type::<bool>()
+          .action()
+          .class_handler(|_, args| {
+            let element = args[0].get::<super::CoquittsFilter>().unwrap();
+            Some(element.imp().load_model().to_value())
+          })
+          .build(),
+        glib::subclass::Signal::builder("unload-model")
+          .return_type::<bool>()
+          .action()
+          .class_handler(|_, args| {
+            let element = args[0].get::<super::CoquittsFilter>().unwrap();
+            Some(element.imp().unload_model().to_value())
+          })
+          .build(),
+      ]
+    });
+    SIGNALS.as_ref()
+  }
+
   fn set_property(&self, _id: usize, value: &Value, pspec: &ParamSpec) {
+    if pspec.name() == "preset" {
+      // Loading a preset sets the other properties, so this has to happen before the settings are
+      // locked below.
+      if let Some(name) = value.get::<Option<String>>().unwrap() {
+        if let Err(e) = self.obj().load_preset(&name) {
+          gstreamer::element_imp_warning!(
+            self,
+            gstreamer::LibraryError::Settings,
+            ("Failed to load preset {}", name),
+            ["{}", e]
+          );
+        }
+      }
+      return;
+    }
     let mut settings = self.settings.lock().unwrap();
     match pspec.name() {
       "model" => {
         settings.model = value.get().unwrap();
       },
+      // An empty string unsets the speaker or language, as presets can't store a null string.
       "speaker" => {
-        settings.speaker = value.get().unwrap();
+        settings.speaker = value
+          .get::<Option<String>>()
+          .unwrap()
+          .filter(|speaker| !speaker.is_empty());
       },
       "language" => {
-        settings.language = value.get().unwrap();
+        settings.language = value
+          .get::<Option<String>>()
+          .unwrap()
+          .filter(|language| !language.is_empty());
       },
       "voice-cloning-input-file" => {
         settings.voice_cloning_input_file = value.get().unwrap();
@@ -135,11 +1178,361 @@ impl ObjectImpl for CoquittsFilter {
       "use-gpu" => {
         settings.gpu = value.get().unwrap();
       },
+      "fit-duration" => {
+        settings.fit_duration = value.get().unwrap();
+      },
+      "lexicon-file" => {
+        settings.lexicon_file = value.get().unwrap();
+        *self.lexicon.lock().unwrap() = None;
+      },
+      "replacement-rules" => {
+        let source: Option<String> = value.get().unwrap();
+        settings.replacement_rules = match source {
+          Some(source) => ReplacementRules::parse(&source).unwrap_or_else(|e| {
+            gstreamer::error!(CAT, "set_property(): ignoring replacement rules: {}", e);
+            ReplacementRules::default()
+          }),
+          None => ReplacementRules::default(),
+        };
+      },
+      "verbalize-numbers" => {
+        settings.verbalize_numbers = value.get().unwrap();
+      },
+      "python-home" => {
+        settings.python_home = value.get().unwrap();
+      },
+      "venv-path" => {
+        settings.venv_path = value.get().unwrap();
+      },
+      "speaker-latents-cache-dir" => {
+        settings.speaker_latents_cache_dir = value.get().unwrap();
+      },
+      "split-sentences" => {
+        settings.split_sentences = value.get().unwrap();
+      },
+      "max-queue-buffers" => {
+        settings.max_queue_buffers = value.get().unwrap();
+        // Wake up upstream if it's blocked on a queue that is no longer full.
+        self.queue_cond.notify_all();
+      },
+      "leaky" => {
+        settings.leaky = value.get().unwrap();
+        self.queue_cond.notify_all();
+      },
+      "max-lateness" => {
+        settings.max_lateness = value.get().unwrap();
+      },
+      "model-candidates" => {
+        let candidates: Option<String> = value.get().unwrap();
+        settings.model_candidates = candidates
+          .iter()
+          .flat_map(|candidates| candidates.split(','))
+          .map(str::trim)
+          .filter(|candidate| !candidate.is_empty())
+          .map(String::from)
+          .collect();
+      },
+      "silence-padding-start-ms" => {
+        settings.silence_padding_start_ms = value.get().unwrap();
+      },
+      "silence-padding-end-ms" => {
+        settings.silence_padding_end_ms = value.get().unwrap();
+      },
+      "max-retries" => {
+        settings.max_retries = value.get().unwrap();
+      },
+      "fallback-to-cpu" => {
+        settings.fallback_to_cpu = value.get().unwrap();
+      },
+      "dry-run" => {
+        settings.dry_run = value.get().unwrap();
+      },
+      "dry-run-sample-rate" => {
+        settings.dry_run_sample_rate = value.get().unwrap();
+      },
+      "empty-text-policy" => {
+        settings.empty_text_policy = value.get().unwrap();
+      },
+      "empty-text-gap-ms" => {
+        settings.empty_text_gap_ms = value.get().unwrap();
+      },
+      "progress-file" => {
+        settings.progress_file = value.get().unwrap();
+      },
+      "speaker-embedding" => {
+        // An empty array unsets the embedding.
+        let embedding: Vec<f32> = value
+          .get::<gstreamer::Array>()
+          .unwrap()
+          .iter()
+          .map(|value| value.get::<f32>().unwrap())
+          .collect();
+        settings.speaker_embedding = Some(embedding).filter(|embedding| !embedding.is_empty());
+      },
+      "pitch-semitones" => {
+        settings.pitch_semitones = value.get().unwrap();
+      },
+      "gain-db" => {
+        settings.gain_db = value.get().unwrap();
+      },
+      "num-workers" => {
+        settings.num_workers = value.get().unwrap();
+      },
+      "max-latency" => {
+        settings.max_latency = value.get().unwrap();
+      },
+      "profile" => {
+        settings.profile = value.get().unwrap();
+      },
+      "extra-kwargs" => {
+        let json: Option<String> = value.get().unwrap();
+        settings.extra_kwargs = json
+          .filter(|json| !json.is_empty())
+          .filter(|json| match serde_json::from_str(json) {
+            Ok(serde_json::Value::Object(_)) => true,
+            Ok(_) => {
+              gstreamer::error!(
+                CAT,
+                "set_property(): ignoring extra kwargs: not a JSON object"
+              );
+              false
+            },
+            Err(e) => {
+              gstreamer::error!(CAT, "set_property(): ignoring extra kwargs: {}", e);
+              false
+            },
+          });
+      },
+      "cache-dir" => {
+        settings.cache_dir = value.get().unwrap();
+      },
+      "cache-max-bytes" => {
+        settings.cache_max_bytes = value.get().unwrap();
+      },
+      "memory-cache-utterances" => {
+        settings.memory_cache_utterances = value.get().unwrap();
+        self
+          .utterances
+          .lock()
+          .unwrap()
+          .truncate(settings.memory_cache_utterances as usize);
+      },
+      "max-chars" => {
+        settings.max_chars = value.get().unwrap();
+      },
+      "long-text-policy" => {
+        settings.long_text_policy = value.get().unwrap();
+      },
+      "device" => {
+        settings.device = value.get().unwrap();
+      },
+      "watch-voice-file" => {
+        settings.watch_voice_file = value.get().unwrap();
+      },
+      "ssml" => {
+        settings.ssml = value.get().unwrap();
+      },
+      "priority" => {
+        settings.priority = value.get().unwrap();
+      },
+      "low-latency-split" => {
+        settings.low_latency_split = value.get().unwrap();
+      },
+      "speaker-index" => {
+        settings.speaker_index = value.get().unwrap();
+      },
+      "watermark" => {
+        settings.watermark = value.get().unwrap();
+      },
+      "watermark-key" => {
+        settings.watermark_key = value.get().unwrap();
+      },
+      "output-block-samples" => {
+        settings.output_block_samples = value.get().unwrap();
+      },
+      "pad-last-block" => {
+        settings.pad_last_block = value.get().unwrap();
+      },
+      "adaptive" => {
+        settings.adaptive = value.get().unwrap();
+      },
+      "adaptive-threshold" => {
+        settings.adaptive_threshold = value.get().unwrap();
+      },
+      "adaptive-model" => {
+        settings.adaptive_model = value
+          .get::<Option<String>>()
+          .unwrap()
+          .filter(|model| !model.is_empty());
+      },
+      "adaptive-speed" => {
+        settings.adaptive_speed = value.get().unwrap();
+      },
+      #[cfg(feature = "metrics")]
+      "metrics-port" => {
+        settings.metrics_port = value.get().unwrap();
+      },
+      "sidecar-dir" => {
+        settings.sidecar_dir = value
+          .get::<Option<String>>()
+          .unwrap()
+          .filter(|dir| !dir.is_empty());
+      },
+      "restore-punctuation" => {
+        settings.restore_punctuation = value.get().unwrap();
+      },
+      "speaker-map" => {
+        let source: Option<String> = value.get().unwrap();
+        settings.speaker_map = match source.filter(|source| !source.is_empty()) {
+          Some(source) => SpeakerMap::parse(&source).unwrap_or_else(|e| {
+            gstreamer::error!(CAT, "set_property(): ignoring speaker map: {}", e);
+            SpeakerMap::default()
+          }),
+          None => SpeakerMap::default(),
+        };
+      },
+      "room-tone-file" => {
+        settings.room_tone_file = value
+          .get::<Option<String>>()
+          .unwrap()
+          .filter(|path| !path.is_empty());
+        *self.room_tone.lock().unwrap() = None;
+      },
+      "room-tone-gain-db" => {
+        settings.room_tone_gain_db = value.get().unwrap();
+      },
+      "unload-after-idle-seconds" => {
+        settings.unload_after_idle_seconds = value.get().unwrap();
+        // Have idle workers start waiting for the new timeout.
+        self.queue_cond.notify_all();
+      },
+      "backend" => {
+        settings.backend = value.get().unwrap();
+      },
+      "server-url" => {
+        settings.server_url = value
+          .get::<Option<String>>()
+          .unwrap()
+          .unwrap_or_else(|| DEFAULT_SERVER_URL.into());
+        *self.server_rate.lock().unwrap() = None;
+      },
+      "onnx-model-path" => {
+        settings.onnx_model_path = value
+          .get::<Option<String>>()
+          .unwrap()
+          .filter(|path| !path.is_empty());
+        *self.onnx_model.lock().unwrap() = None;
+      },
+      "normalize-unicode" => {
+        settings.normalize_unicode = value.get().unwrap();
+      },
+      "live-scheduling" => {
+        settings.live_scheduling = value.get().unwrap();
+      },
+      "segment-stop-policy" => {
+        settings.segment_stop_policy = value.get().unwrap();
+      },
+      "initial-text" => {
+        settings.initial_text = value
+          .get::<Option<String>>()
+          .unwrap()
+          .filter(|text| !text.is_empty());
+      },
+      "dither" => {
+        settings.dither = value.get().unwrap();
+      },
+      "gapless" => {
+        settings.gapless = value.get().unwrap();
+      },
+      "resample" => {
+        settings.resample = value.get().unwrap();
+      },
+      "max-chars-per-minute" => {
+        settings.max_chars_per_minute = value.get().unwrap();
+      },
+      "throttle-policy" => {
+        settings.throttle_policy = value.get().unwrap();
+      },
+      "dedupe-window" => {
+        settings.dedupe_window = value.get().unwrap();
+      },
+      "strip-stage-directions" => {
+        settings.strip_stage_directions = value.get().unwrap();
+      },
+      "voice-map" => {
+        let source: Option<String> = value.get().unwrap();
+        settings.voice_map = match source.filter(|source| !source.is_empty()) {
+          Some(source) => VoiceMap::parse(&source).unwrap_or_else(|e| {
+            gstreamer::error!(CAT, "set_property(): ignoring voice map: {}", e);
+            VoiceMap::default()
+          }),
+          None => VoiceMap::default(),
+        };
+      },
+      "empty-output-policy" => {
+        settings.empty_output_policy = value.get().unwrap();
+      },
       other => panic!("no such property: {}", other),
     }
+    drop(settings);
+    if matches!(
+      pspec.name(),
+      "model" | "speaker" | "speaker-index" | "language" | "dry-run" | "dry-run-sample-rate"
+    ) {
+      self.obj().notify("effective-settings");
+    }
+    if pspec.name() == "voice-cloning-input-file" {
+      let file = self.settings.lock().unwrap().voice_cloning_input_file.clone();
+      self.voice_file_switched(file.as_deref(), false);
+    }
   }
 
   fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
+    if pspec.name() == "sample-rate" {
+      // Loading the model locks the settings, so this has to happen before they're locked below.
+      return match self.sample_rate() {
+        Ok(sample_rate) => (sample_rate as u32).to_value(),
+        Err(e) => {
+          gstreamer::warning!(CAT, "property(): failed to load model: {}", e);
+          0u32.to_value()
+        },
+      };
+    }
+    if pspec.name() == "effective-settings" {
+      return self.effective_settings().to_value();
+    }
+    if pspec.name() == "is-loaded" {
+      return self.is_loaded().to_value();
+    }
+    if pspec.name() == "stats" {
+      return self.stats().to_value();
+    }
+    if pspec.name() == "queue-length" {
+      return (self.queue.lock().unwrap().buffers.len() as u32).to_value();
+    }
+    if pspec.name() == "current-text" {
+      let queue = self.queue.lock().unwrap();
+      return queue.in_flight.values().next().cloned().to_value();
+    }
+    if pspec.name() == "model-capabilities" {
+      let model = self.active_model();
+      return self
+        .model_capabilities
+        .lock()
+        .unwrap()
+        .get(&model)
+        .cloned()
+        .to_value();
+    }
+    if pspec.name() == "speakers-detailed" {
+      let model = self.active_model();
+      let capabilities = self.model_capabilities.lock().unwrap();
+      return capabilities
+        .get(&model)
+        .and_then(|capabilities| capabilities.get::<gstreamer::Array>("speakers").ok())
+        .map(|speakers| speakers_detailed(&model, &speakers))
+        .to_value();
+    }
     let settings = self.settings.lock().unwrap();
     match pspec.name() {
       "model" => settings.model.to_value(),
@@ -147,19 +1540,109 @@ impl ObjectImpl for CoquittsFilter {
       "language" => settings.language.to_value(),
       "voice-cloning-input-file" => settings.voice_cloning_input_file.to_value(),
       "use-gpu" => settings.gpu.to_value(),
+      "fit-duration" => settings.fit_duration.to_value(),
+      "lexicon-file" => settings.lexicon_file.to_value(),
+      "replacement-rules" => settings.replacement_rules.source().to_value(),
+      "verbalize-numbers" => settings.verbalize_numbers.to_value(),
+      "python-home" => settings.python_home.to_value(),
+      "venv-path" => settings.venv_path.to_value(),
+      "speaker-latents-cache-dir" => settings.speaker_latents_cache_dir.to_value(),
+      "split-sentences" => settings.split_sentences.to_value(),
+      "max-queue-buffers" => settings.max_queue_buffers.to_value(),
+      "leaky" => settings.leaky.to_value(),
+      "max-lateness" => settings.max_lateness.to_value(),
+      "model-candidates" => {
+        if settings.model_candidates.is_empty() {
+          None::<String>.to_value()
+        }
+        else {
+          settings.model_candidates.join(",").to_value()
+        }
+      },
+      "silence-padding-start-ms" => settings.silence_padding_start_ms.to_value(),
+      "silence-padding-end-ms" => settings.silence_padding_end_ms.to_value(),
+      "max-retries" => settings.max_retries.to_value(),
+      "fallback-to-cpu" => settings.fallback_to_cpu.to_value(),
+      "dry-run" => settings.dry_run.to_value(),
+      "dry-run-sample-rate" => settings.dry_run_sample_rate.to_value(),
+      "empty-text-policy" => settings.empty_text_policy.to_value(),
+      "empty-text-gap-ms" => settings.empty_text_gap_ms.to_value(),
+      "progress-file" => settings.progress_file.to_value(),
+      "speaker-embedding" => settings
+        .speaker_embedding
+        .as_ref()
+        .map(|embedding| gstreamer::Array::new(embedding.iter().copied()))
+        .unwrap_or_default()
+        .to_value(),
+      "pitch-semitones" => settings.pitch_semitones.to_value(),
+      "gain-db" => settings.gain_db.to_value(),
+      "num-workers" => settings.num_workers.to_value(),
+      "max-latency" => settings.max_latency.to_value(),
+      "profile" => settings.profile.to_value(),
+      "extra-kwargs" => settings.extra_kwargs.to_value(),
+      "cache-dir" => settings.cache_dir.to_value(),
+      "cache-max-bytes" => settings.cache_max_bytes.to_value(),
+      "memory-cache-utterances" => settings.memory_cache_utterances.to_value(),
+      "max-chars" => settings.max_chars.to_value(),
+      "long-text-policy" => settings.long_text_policy.to_value(),
+      "device" => settings.device.to_value(),
+      "watch-voice-file" => settings.watch_voice_file.to_value(),
+      "ssml" => settings.ssml.to_value(),
+      "priority" => settings.priority.to_value(),
+      "low-latency-split" => settings.low_latency_split.to_value(),
+      "speaker-index" => settings.speaker_index.to_value(),
+      "watermark" => settings.watermark.to_value(),
+      "watermark-key" => settings.watermark_key.to_value(),
+      "output-block-samples" => settings.output_block_samples.to_value(),
+      "pad-last-block" => settings.pad_last_block.to_value(),
+      "adaptive" => settings.adaptive.to_value(),
+      "adaptive-threshold" => settings.adaptive_threshold.to_value(),
+      "adaptive-model" => settings.adaptive_model.to_value(),
+      "adaptive-speed" => settings.adaptive_speed.to_value(),
+      #[cfg(feature = "metrics")]
+      "metrics-port" => settings.metrics_port.to_value(),
+      "sidecar-dir" => settings.sidecar_dir.to_value(),
+      "restore-punctuation" => settings.restore_punctuation.to_value(),
+      "speaker-map" => settings.speaker_map.source().to_value(),
+      "room-tone-file" => settings.room_tone_file.to_value(),
+      "room-tone-gain-db" => settings.room_tone_gain_db.to_value(),
+      "unload-after-idle-seconds" => settings.unload_after_idle_seconds.to_value(),
+      "backend" => settings.backend.to_value(),
+      "server-url" => settings.server_url.to_value(),
+      "onnx-model-path" => settings.onnx_model_path.to_value(),
+      "normalize-unicode" => settings.normalize_unicode.to_value(),
+      "live-scheduling" => settings.live_scheduling.to_value(),
+      "segment-stop-policy" => settings.segment_stop_policy.to_value(),
+      "initial-text" => settings.initial_text.to_value(),
+      "dither" => settings.dither.to_value(),
+      "gapless" => settings.gapless.to_value(),
+      "resample" => settings.resample.to_value(),
+      "max-chars-per-minute" => settings.max_chars_per_minute.to_value(),
+      "throttle-policy" => settings.throttle_policy.to_value(),
+      "dedupe-window" => settings.dedupe_window.to_value(),
+      "strip-stage-directions" => settings.strip_stage_directions.to_value(),
+      "voice-map" => settings.voice_map.source().to_value(),
+      "empty-output-policy" => settings.empty_output_policy.to_value(),
       other => panic!("no such property: {}", other),
     }
   }
+
+  fn dispose(&self) {
+    self.voice_file_switched(None, false);
+  }
 }
 
 impl GstObjectImpl for CoquittsFilter {}
 
+/// Presets are read from `GstCoquittsFilter.prs` files by GStreamer's default implementation.
+impl PresetImpl for CoquittsFilter {}
+
 impl ElementImpl for CoquittsFilter {
   fn metadata() -> Option<&'static ElementMetadata> {
     static ELEMENT_METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
       ElementMetadata::new(
         "Coqui TTS",
-        "Converter/Text/Audio",
+        "Filter/Converter/Text/Audio",
         "Text to speech filter using Coqui",
         "Jasper Hugo <jasper@avstack.io>",
       )
@@ -181,79 +1664,3879 @@ impl ElementImpl for CoquittsFilter {
       )
       .unwrap();
 
-      vec![src_pad_template, sink_pad_template]
+      let marks_pad_template = PadTemplate::new(
+        "marks",
+        PadDirection::Src,
+        PadPresence::Request,
+        &MARKS_CAPS,
+      )
+      .unwrap();
+
+      vec![src_pad_template, sink_pad_template, marks_pad_template]
     });
 
     PAD_TEMPLATES.as_ref()
   }
-}
 
-impl CoquittsFilter {
-  fn init_synth(&self) -> Py<PyAny> {
-    gstreamer::debug!(CAT, "init_synth(): initialising Python interpreter");
-    pyo3::prepare_freethreaded_python();
-    gstreamer::debug!(CAT, "init_synth(): acquiring GIL");
-    let result = Python::with_gil(|py| {
-      gstreamer::debug!(CAT, "init_synth(): init synth");
-      let tts_api_module = PyModule::import(py, "TTS.api").unwrap();
-      let kwargs = {
-        let settings = self.settings.lock().unwrap();
-        let d = PyDict::new(py);
-        d.set_item("model_name", &settings.model).unwrap();
-        d.set_item("progress_bar", false).unwrap();
-        d.set_item("gpu", settings.gpu).unwrap();
-        d
-      };
-      let synth = tts_api_module.call_method("TTS", (), Some(kwargs)).unwrap();
-      gstreamer::debug!(CAT, "init_synth(): synth init complete");
+  fn request_new_pad(
+    &self,
+    templ: &PadTemplate,
+    _name: Option<&str>,
+    _caps: Option<&Caps>,
+  ) -> Option<gstreamer::Pad> {
+    let mut marks_pad = self.marks_pad.lock().unwrap();
+    if marks_pad.is_some() {
+      gstreamer::debug!(CAT, "request_new_pad(): marks pad already requested");
+      return None;
+    }
+    let pad = gstreamer::Pad::builder_with_template(templ, Some("marks")).build();
+    pad.set_active(true).ok()?;
+    self.obj().add_pad(&pad).ok()?;
+    *marks_pad = Some(pad.clone());
+    Some(pad)
+  }
+
+  fn release_pad(&self, pad: &gstreamer::Pad) {
+    *self.marks_pad.lock().unwrap() = None;
+    let _ = pad.set_active(false);
+    let _ = self.obj().remove_pad(pad);
+  }
+
+  fn change_state(&self, transition: StateChange) -> Result<StateChangeSuccess, StateChangeError> {
+    let result = self.parent_change_state(transition)?;
+    let standalone = !self.obj().sink_pad().is_linked();
+    match transition {
+      // Without upstream, nothing else is going to preroll the sinks.
+      StateChange::ReadyToPaused if standalone => self.push_initial_text(true),
+      StateChange::PausedToPlaying if !standalone => self.push_initial_text(false),
+      _ => (),
+    }
+    Ok(result)
+  }
+}
+
+impl CoquittsFilter {
+  /// Warns that `python-home` was ignored because the interpreter, which the whole process
+  /// shares, was already initialised with another home, by another element or the application.
+  fn check_python_home(&self, py: Python, python_home: Option<&str>) {
+    let python_home = match python_home {
+      Some(python_home) => python_home,
+      None => return,
+    };
+    let prefix = python::prefix(py);
+    if prefix
+      .as_deref()
+      .is_some_and(|prefix| Path::new(prefix) == Path::new(python_home))
+    {
+      return;
+    }
+    gstreamer::element_imp_warning!(
+      self,
+      gstreamer::LibraryError::Settings,
+      (
+        "Ignoring python-home={}, as Python is already running from {}",
+        python_home,
+        prefix.as_deref().unwrap_or("another home")
+      ),
+      ["The interpreter is shared by the whole process; use separate processes or the server backend for different Python installations"]
+    );
+  }
+
+  /// Warns that Coqui TTS was already imported from outside `venv-path`, by another element or
+  /// the application, in which case that's the one used, since imported modules are shared by
+  /// the whole process.
+  fn check_venv_path(&self, py: Python, venv_path: &str) {
+    let file = match python::imported_from(py, "TTS") {
+      Some(file) => file,
+      None => return,
+    };
+    if Path::new(&file).starts_with(venv_path) {
+      return;
+    }
+    gstreamer::element_imp_warning!(
+      self,
+      gstreamer::LibraryError::Settings,
+      (
+        "Using Coqui TTS from {} rather than venv-path={}, as it was already imported",
+        file,
+        venv_path
+      ),
+      ["Python modules are shared by the whole process; use separate processes or the server backend for different packages"]
+    );
+  }
+
+  fn init_synth(&self, model: &str) -> Result<Py<PyAny>, ErrorMessage> {
+    let started = Instant::now();
+    let (python_home, venv_path, gpu, device) = {
+      let settings = self.settings.lock().unwrap();
+      (
+        settings.python_home.clone(),
+        settings.venv_path.clone(),
+        settings.gpu,
+        settings.device.clone(),
+      )
+    };
+    gstreamer::debug!(CAT, "init_synth(): initialising Python interpreter");
+    let python_home_applied = python::initialise(python_home.as_deref());
+    gstreamer::debug!(CAT, "init_synth(): acquiring GIL");
+    let result = self.gil_stats.with_gil(|py| {
+      if !python_home_applied {
+        self.check_python_home(py, python_home.as_deref());
+      }
+      if let Some(venv_path) = venv_path {
+        let dirs = python::add_venv(py, Path::new(&venv_path)).map_err(|e| {
+          e.to_error_message(gstreamer::LibraryError::Init, "Failed to add virtualenv")
+        })?;
+        gstreamer::debug!(CAT, "init_synth(): added site dirs: {:?}", dirs);
+        self.check_venv_path(py, &venv_path);
+      }
+      gstreamer::debug!(CAT, "init_synth(): init synth");
+      let device = match (device.as_deref(), gpu) {
+        (Some(device), _) => device,
+        (None, GpuMode::Off) => python::CPU_DEVICE,
+        (None, GpuMode::On) => python::CUDA_DEVICE,
+        (None, GpuMode::Auto) => {
+          let device = python::best_device(py).map_err(|e| {
+            e.to_error_message(gstreamer::LibraryError::Init, "Failed to detect GPUs")
+          })?;
+          gstreamer::element_imp_info!(
+            self,
+            gstreamer::LibraryError::Settings,
+            ("Running model {} on device {}", model, device)
+          );
+          device
+        },
+      };
+      gstreamer::debug!(CAT, "init_synth(): using device {}", device);
+      let progress = self.download_progress(model);
+      let synth = python::load_tts(py, model, device, Some(progress)).map_err(|e| {
+        e.to_error_message(
+          gstreamer::LibraryError::Init,
+          "Failed to load Coqui TTS model",
+        )
+      })?;
+      gstreamer::debug!(CAT, "init_synth(): synth init complete");
+      let is_multi_lingual = python::bool_attr(synth, "is_multi_lingual")
+        .map_err(|e| e.to_error_message(gstreamer::LibraryError::Init, "Unsupported model"))?;
+      let is_multi_speaker = python::bool_attr(synth, "is_multi_speaker")
+        .map_err(|e| e.to_error_message(gstreamer::LibraryError::Init, "Unsupported model"))?;
       {
         let settings = self.settings.lock().unwrap();
-        if settings.language.is_none()
-          && synth
-            .getattr("is_multi_lingual")
+        if settings.language.is_none() && is_multi_lingual {
+          // The language may still come from a `language-code` tag or a JSON message.
+          gstreamer::warning!(
+            CAT,
+            "init_synth(): model is multi-lingual but the `language` property isn't set"
+          );
+        }
+        if settings.speaker.is_none() && settings.speaker_index < 0 && is_multi_speaker {
+          // The speaker may still come from the sink caps or a JSON message.
+          gstreamer::warning!(
+            CAT,
+            "init_synth(): model is multi-speaker but the `speaker` property isn't set"
+          );
+        }
+        if settings.speaker.is_none() && settings.speaker_index >= 0 {
+          let speakers = speakers(synth).map_err(|e| {
+            python::PyBridgeError::from_py(py, &e)
+              .to_error_message(gstreamer::LibraryError::Init, "Unsupported model")
+          })?;
+          if settings.speaker_index as usize >= speakers.len() {
+            return Err(gstreamer::error_msg!(
+              gstreamer::LibraryError::Settings,
+              (
+                "`speaker-index` {} is out of range, the model has {} speakers",
+                settings.speaker_index,
+                speakers.len()
+              )
+            ));
+          }
+        }
+      }
+      let load_time = ClockTime::from_nseconds(started.elapsed().as_nanos() as u64);
+      gstreamer::debug!(CAT, "init_synth(): loaded {} in {}", model, load_time);
+      let structure = gstreamer::Structure::builder(MODEL_LOADED_MESSAGE)
+        .field("model", model)
+        .field("device", device)
+        .field("load-time", load_time)
+        .build();
+      let _ = self.obj().post_message(
+        gstreamer::message::Element::builder(structure)
+          .src(&*self.obj())
+          .build(),
+      );
+      Ok(synth.into())
+    });
+    gstreamer::debug!(CAT, "init_synth(): released GIL");
+    result
+  }
+
+  /// Posts `coquitts-download-progress` element messages while `model` is being downloaded, at
+  /// most every `DOWNLOAD_PROGRESS_INTERVAL` and when it's complete.
+  fn download_progress(&self, model: &str) -> python::DownloadProgress {
+    let element = self.obj().downgrade();
+    let model = model.to_owned();
+    let last_posted = Mutex::new(None::<Instant>);
+    Box::new(move |bytes, total| {
+      let element = match element.upgrade() {
+        Some(element) => element,
+        None => return,
+      };
+      let complete = total.is_some_and(|total| bytes >= total);
+      {
+        let mut last_posted = last_posted.lock().unwrap();
+        if !complete
+          && last_posted.is_some_and(|posted| posted.elapsed() < DOWNLOAD_PROGRESS_INTERVAL)
+        {
+          return;
+        }
+        *last_posted = Some(Instant::now());
+      }
+      let mut structure = gstreamer::Structure::builder(DOWNLOAD_PROGRESS_MESSAGE)
+        .field("model", &model)
+        .field("bytes", bytes)
+        .build();
+      if let Some(total) = total {
+        structure.set("total", total);
+        structure.set("percent", bytes as f64 * 100.0 / total as f64);
+      }
+      gstreamer::debug!(CAT, "download_progress(): {}", structure);
+      let _ = element.post_message(
+        gstreamer::message::Element::builder(structure)
+          .src(&element)
+          .build(),
+      );
+    })
+  }
+
+  fn with_synth<R, F: FnOnce(&PyAny) -> R>(&self, f: F) -> Result<R, ErrorMessage> {
+    let model = self.active_model();
+    gstreamer::debug!(CAT, "with_synth(): locking synth");
+    let mut synth = self.synth().lock().unwrap();
+    let loaded = synth.as_ref().is_none_or(|(loaded, _)| *loaded != model);
+    if loaded {
+      let parked = self.swap_parked_synth(synth.take(), &model);
+      let s = match parked {
+        Some(s) => {
+          gstreamer::debug!(CAT, "with_synth(): switching back to {}", model);
+          s
+        },
+        None => match self.candidate_synths.lock().unwrap().remove(&model) {
+          Some(s) => {
+            gstreamer::debug!(CAT, "with_synth(): using candidate {}", model);
+            s
+          },
+          None => {
+            gstreamer::debug!(CAT, "with_synth(): no synth for {}, will init", model);
+            self.init_synth(&model)?
+          },
+        },
+      };
+      *synth = Some((model.clone(), s));
+    }
+    gstreamer::debug!(CAT, "with_synth(): acquiring GIL");
+    let acquiring = Instant::now();
+    let result = self.gil_stats.with_gil(move |py| {
+      let waited = acquiring.elapsed();
+      profile::record(Stage::GilWait, waited);
+      if waited > GIL_CONTENTION_THRESHOLD {
+        gstreamer::debug!(
+          CAT,
+          "with_synth(): waited {:?} for the GIL, held by another Python user in the process",
+          waited
+        );
+      }
+      let s = synth.as_ref().unwrap().1.as_ref(py);
+      if loaded {
+        // Remember the rate so that `effective-settings` can report it without the synth.
+        if let Ok(rate) = python::output_sample_rate(s) {
+          self
+            .candidate_rates
+            .lock()
+            .unwrap()
+            .insert(model.clone(), rate);
+        }
+        match model_capabilities(s, &model) {
+          Ok(capabilities) => {
+            self
+              .model_capabilities
+              .lock()
+              .unwrap()
+              .insert(model, capabilities);
+          },
+          Err(e) => gstreamer::warning!(
+            CAT,
+            "with_synth(): failed to get model capabilities: {}",
+            python::format_traceback(py, &e)
+          ),
+        }
+      }
+      let result = f(s);
+      drop(synth);
+      gstreamer::debug!(CAT, "with_synth(): unlocked synth");
+      result
+    });
+    gstreamer::debug!(CAT, "with_synth(): released GIL");
+    if loaded {
+      self.obj().notify("is-loaded");
+      self.obj().notify("sample-rate");
+      self.obj().notify("effective-settings");
+      self.obj().notify("model-capabilities");
+      self.obj().notify("speakers-detailed");
+    }
+    Ok(result)
+  }
+
+  /// Parks the worker's previous model while `voice-map` is set, or else frees it, and takes
+  /// `model` out of the parked ones if it's there.
+  fn swap_parked_synth(
+    &self,
+    previous: Option<(String, Py<PyAny>)>,
+    model: &str,
+  ) -> Option<Py<PyAny>> {
+    let park = !self.settings.lock().unwrap().voice_map.is_empty();
+    let mut parked = self.parked_synths[WORKER_INDEX.with(Cell::get)]
+      .lock()
+      .unwrap();
+    if let Some((name, synth)) = previous.filter(|_| park) {
+      gstreamer::debug!(CAT, "swap_parked_synth(): parking {}", name);
+      parked.insert(name, synth);
+    }
+    parked.remove(model)
+  }
+
+  /// The `load-model` action signal: loads the model now, if it isn't loaded already, rather than
+  /// when text next arrives.
+  fn load_model(&self) -> bool {
+    gstreamer::debug!(CAT, "load_model()");
+    let result = match self.settings.lock().unwrap().backend {
+      Backend::Local => self.with_synth(|_| ()),
+      Backend::Server => self.server_sample_rate().map(|_| ()),
+      Backend::Onnx => self.with_onnx_model(|_| ()),
+    };
+    match result {
+      Ok(()) => true,
+      Err(e) => {
+        self.post_error_message(e);
+        false
+      },
+    }
+  }
+
+  /// The `unload-model` action signal: frees every worker's model, and the speaker latents
+  /// computed with it, waiting for any synthesis in progress to finish. The model is loaded again
+  /// when text next arrives. Returns whether a model was loaded.
+  fn unload_model(&self) -> bool {
+    if self.onnx_model.lock().unwrap().take().is_some() {
+      gstreamer::debug!(CAT, "unload_model(): unloading ONNX model");
+      self.obj().notify("is-loaded");
+      return true;
+    }
+    let mut unloaded = self
+      .synths
+      .iter()
+      .filter_map(|synth| synth.lock().unwrap().take())
+      .collect::<Vec<_>>();
+    for parked in &self.parked_synths {
+      unloaded.extend(parked.lock().unwrap().drain());
+    }
+    unloaded.extend(self.candidate_synths.lock().unwrap().drain());
+    gstreamer::debug!(CAT, "unload_model(): unloading {} models", unloaded.len());
+    if unloaded.is_empty() {
+      return false;
+    }
+    self.free_models(unloaded);
+    true
+  }
+
+  /// Frees models taken out of the workers' synthesisers, along with the speaker latents
+  /// computed with them.
+  fn free_models(&self, models: Vec<(String, Py<PyAny>)>) {
+    let latents = std::mem::take(&mut *self.speaker_latents.lock().unwrap());
+    self.gil_stats.with_gil(|py| {
+      // Drop the models while holding the GIL, so that Python can free them straight away.
+      drop(models);
+      drop(latents);
+      if let Err(e) = PyModule::import(py, "gc").and_then(|gc| gc.call_method0("collect")) {
+        gstreamer::debug!(CAT, "free_models(): failed to collect garbage: {}", e);
+      }
+      if let Err(e) = python::empty_gpu_cache(py) {
+        gstreamer::debug!(CAT, "free_models(): failed to empty GPU cache: {}", e);
+      }
+    });
+    self.obj().notify("is-loaded");
+  }
+
+  /// The `stats` property.
+  fn stats(&self) -> gstreamer::Structure {
+    let (processed, dropped) = {
+      let queue = self.queue.lock().unwrap();
+      (queue.processed, queue.dropped)
+    };
+    let to_clock_time = |duration: Duration| ClockTime::from_nseconds(duration.as_nanos() as u64);
+    gstreamer::Structure::builder("coquitts-stats")
+      .field("processed", processed)
+      .field("dropped", dropped)
+      .field("gil-acquisitions", self.gil_stats.acquisitions())
+      .field("gil-wait", to_clock_time(self.gil_stats.wait()))
+      .field("gil-hold", to_clock_time(self.gil_stats.hold()))
+      .build()
+  }
+
+  /// Whether the first worker's synthesiser, which `start()` loads, has the active model loaded.
+  fn is_loaded(&self) -> bool {
+    match self.settings.lock().unwrap().backend {
+      Backend::Local => (),
+      Backend::Server => return self.server_rate.lock().unwrap().is_some(),
+      Backend::Onnx => return self.onnx_model.lock().unwrap().is_some(),
+    }
+    let model = self.active_model();
+    match self.synths[0].try_lock() {
+      Ok(synth) => synth.as_ref().is_some_and(|(loaded, _)| *loaded == model),
+      // It's either loading or synthesising, and the capabilities are only known once it has
+      // loaded.
+      Err(TryLockError::WouldBlock) => self
+        .model_capabilities
+        .lock()
+        .unwrap()
+        .contains_key(&model),
+      Err(TryLockError::Poisoned(_)) => false,
+    }
+  }
+
+  /// The name of the speaker selected by `speaker-index` in the active model's speakers.
+  fn indexed_speaker(&self) -> Option<String> {
+    let index = usize::try_from(self.settings.lock().unwrap().speaker_index).ok()?;
+    let model = self.active_model();
+    let capabilities = self.model_capabilities.lock().unwrap();
+    capabilities
+      .get(&model)?
+      .get::<gstreamer::Array>("speakers")
+      .ok()?
+      .get(index)?
+      .get::<String>()
+      .ok()
+  }
+
+  /// The synthesiser of the worker running on the current thread.
+  fn synth(&self) -> &Mutex<Option<Synth>> {
+    &self.synths[WORKER_INDEX.with(Cell::get)]
+  }
+
+  /// The settings in effect, as reported by the `effective-settings` property.
+  fn effective_settings(&self) -> gstreamer::Structure {
+    let model = self.active_model();
+    let (caps_speaker, caps_language) = {
+      let state = self.state.lock().unwrap();
+      (state.caps_speaker.clone(), state.caps_language.clone())
+    };
+    let (speaker, language, dry_run_sample_rate, backend) = {
+      let settings = self.settings.lock().unwrap();
+      (
+        settings.speaker.clone().or(caps_speaker),
+        settings.language.clone().or(caps_language),
+        settings
+          .dry_run
+          .then_some(settings.dry_run_sample_rate as u64),
+        settings.backend,
+      )
+    };
+    let speaker = speaker.or_else(|| self.indexed_speaker());
+    let language = match language {
+      Some(language) if language == AUTO_LANGUAGE => Some(
+        self
+          .state
+          .lock()
+          .unwrap()
+          .last_detected_language
+          .clone()
+          .unwrap_or_else(|| FALLBACK_LANGUAGE.into()),
+      ),
+      None => self.state.lock().unwrap().tag_language.clone(),
+      language => language,
+    };
+    let rate = match backend {
+      Backend::Local => {
+        dry_run_sample_rate.or_else(|| self.candidate_rates.lock().unwrap().get(&model).copied())
+      },
+      Backend::Server => dry_run_sample_rate.or(*self.server_rate.lock().unwrap()),
+      Backend::Onnx => dry_run_sample_rate.or_else(|| {
+        self
+          .onnx_model
+          .lock()
+          .unwrap()
+          .as_ref()
+          .map(OnnxModel::sample_rate)
+      }),
+    };
+    let mut structure = gstreamer::Structure::builder("effective-settings")
+      .field("model", model)
+      .build();
+    if self.settings.lock().unwrap().adaptive {
+      structure.set("adapting", self.state.lock().unwrap().adapting);
+    }
+    if let Some(speaker) = speaker {
+      structure.set("speaker", speaker);
+    }
+    if let Some(language) = language {
+      structure.set("language", language);
+    }
+    if let Some(rate) = rate {
+      structure.set("rate", rate as i32);
+    }
+    structure
+  }
+
+  /// The model to synthesise with: the one `voice-map` picked for the text being synthesised on
+  /// this thread, or else the one selected from `model-candidates` during negotiation, or `model`.
+  fn active_model(&self) -> String {
+    if let Some(model) = MAPPED_MODEL.with(|mapped| mapped.borrow().clone()) {
+      return model;
+    }
+    let (selected_model, adapting) = {
+      let state = self.state.lock().unwrap();
+      (state.selected_model.clone(), state.adapting)
+    };
+    let settings = self.settings.lock().unwrap();
+    match settings.adaptive_model.clone().filter(|_| adapting) {
+      Some(model) => model,
+      None => selected_model.unwrap_or_else(|| settings.model.clone()),
+    }
+  }
+
+  /// Starts `adaptive` mode when more than `adaptive-threshold` texts are waiting, and ends it
+  /// once none are.
+  fn adapt(&self, backlog: usize) {
+    let (adaptive, threshold) = {
+      let settings = self.settings.lock().unwrap();
+      (settings.adaptive, settings.adaptive_threshold as usize)
+    };
+    let (adapting, changed) = {
+      let mut state = self.state.lock().unwrap();
+      let adapting = adaptive && (backlog > threshold || (state.adapting && backlog > 0));
+      (
+        adapting,
+        std::mem::replace(&mut state.adapting, adapting) != adapting,
+      )
+    };
+    if changed {
+      gstreamer::debug!(
+        CAT,
+        "adapt(): {} texts waiting, adapting: {}",
+        backlog,
+        adapting
+      );
+      self.obj().notify("effective-settings");
+    }
+  }
+
+  /// Returns the native sample rate of each candidate model that can be loaded, in order of
+  /// preference. Rates are read from the models' configs, so that the streaming thread doesn't
+  /// have to load every candidate to negotiate.
+  fn candidate_rates(&self, candidates: &[String]) -> Vec<(String, u64)> {
+    let mut rates = vec![];
+    for candidate in candidates {
+      let cached = self.candidate_rates.lock().unwrap().get(candidate).copied();
+      let rate = match cached {
+        Some(rate) => rate,
+        None => {
+          let loaded = self
+            .synth()
+            .lock()
             .unwrap()
-            .downcast::<PyBool>()
+            .as_ref()
+            .filter(|(model, _)| model == candidate)
+            .map(|(_, synth)| {
+              self
+                .gil_stats
+                .with_gil(|py| python::output_sample_rate(synth.as_ref(py)))
+            });
+          let loaded = match loaded {
+            Some(rate) => rate.map_err(|e| e.to_string()),
+            None => self
+              .gil_stats
+              .with_gil(|py| python::model_config_paths(py, candidate))
+              .map_err(|e| e.to_string())
+              .and_then(|paths| config_sample_rate(&paths))
+              .or_else(|e| {
+                gstreamer::debug!(
+                  CAT,
+                  "candidate_rates(): no rate in the config of {}, loading it: {}",
+                  candidate,
+                  e
+                );
+                self.load_candidate(candidate)
+              }),
+          };
+          let rate = match loaded {
+            Ok(rate) => rate,
+            Err(e) => {
+              gstreamer::warning!(
+                CAT,
+                "candidate_rates(): skipping model {}: {}",
+                candidate,
+                e
+              );
+              continue;
+            },
+          };
+          gstreamer::debug!(
+            CAT,
+            "candidate_rates(): model {} has sample rate {}",
+            candidate,
+            rate
+          );
+          self
+            .candidate_rates
+            .lock()
             .unwrap()
-            .is_true()
-        {
-          panic!("This model is multi-lingual and requires specifying the `language` property");
+            .insert(candidate.clone(), rate);
+          rate
+        },
+      };
+      rates.push((candidate.clone(), rate));
+    }
+    rates
+  }
+
+  /// Loads a candidate model to find its rate, keeping it in case it's selected.
+  fn load_candidate(&self, candidate: &str) -> Result<u64, String> {
+    let synth = self.init_synth(candidate).map_err(|e| e.to_string())?;
+    let rate = self
+      .gil_stats
+      .with_gil(|py| python::output_sample_rate(synth.as_ref(py)))
+      .map_err(|e| e.to_string())?;
+    self
+      .candidate_synths
+      .lock()
+      .unwrap()
+      .insert(candidate.to_owned(), synth);
+    Ok(rate)
+  }
+
+  fn resolve_language(&self, text: &str) -> Option<String> {
+    let language = self.settings.lock().unwrap().language.clone();
+    let language = language.or_else(|| self.state.lock().unwrap().caps_language.clone());
+    if language.is_none() {
+      return self.state.lock().unwrap().tag_language.clone();
+    }
+    if language.as_deref() != Some(AUTO_LANGUAGE) {
+      return language;
+    }
+    let mut state = self.state.lock().unwrap();
+    let mut changed = false;
+    match langdetect::detect(text) {
+      Some(detected) => {
+        gstreamer::debug!(CAT, "resolve_language(): detected language: {}", detected);
+        changed = state.last_detected_language.as_deref() != Some(detected);
+        state.last_detected_language = Some(detected.into());
+      },
+      None => gstreamer::debug!(
+        CAT,
+        "resolve_language(): detection unreliable, using {:?}",
+        state.last_detected_language
+      ),
+    }
+    let language = state
+      .last_detected_language
+      .clone()
+      .unwrap_or_else(|| FALLBACK_LANGUAGE.into());
+    drop(state);
+    if changed {
+      self.obj().notify("effective-settings");
+    }
+    Some(language)
+  }
+
+  fn preprocess_text(&self, text: &str, language: Option<&str>) -> String {
+    let (lexicon_file, mut text) = {
+      let settings = self.settings.lock().unwrap();
+      let mut text = if settings.restore_punctuation {
+        let english = language.is_none_or(|language| language.starts_with("en"));
+        punctuate::restore(text, english)
+      }
+      else {
+        text.to_owned()
+      };
+      text = settings.replacement_rules.apply(&text);
+      if settings.verbalize_numbers {
+        match Locale::from_language(language) {
+          Some(locale) => text = verbalize::verbalize(&text, locale),
+          None => gstreamer::debug!(
+            CAT,
+            "preprocess_text(): number verbalization not supported for language {:?}",
+            language
+          ),
+        }
+      }
+      (settings.lexicon_file.clone(), text)
+    };
+    if let Some(path) = lexicon_file {
+      let mut lexicon = self.lexicon.lock().unwrap();
+      let result = match lexicon.as_mut() {
+        Some(lexicon) => lexicon.reload_if_changed().map(|reloaded| {
+          if reloaded {
+            gstreamer::debug!(CAT, "preprocess_text(): reloaded lexicon from {}", path);
+          }
+        }),
+        None => Lexicon::load(&path).map(|loaded| {
+          gstreamer::debug!(
+            CAT,
+            "preprocess_text(): loaded {} lexicon entries from {}",
+            loaded.len(),
+            path
+          );
+          *lexicon = Some(loaded);
+        }),
+      };
+      if let Err(e) = result {
+        gstreamer::element_imp_warning!(
+          self,
+          gstreamer::ResourceError::Read,
+          ("Failed to load lexicon file"),
+          ["{}: {}", path, e]
+        );
+      }
+      if let Some(lexicon) = lexicon.as_ref() {
+        text = lexicon.apply(&text);
+      }
+    }
+    text
+  }
+
+  /// Mixes the `room-tone-file`, if it's set, under the audio of an utterance, loading it first
+  /// if it hasn't been loaded yet.
+  fn mix_room_tone(&self, sentences: &mut [Vec<f32>], sample_rate: u64) {
+    let (path, gain_db) = {
+      let settings = self.settings.lock().unwrap();
+      match settings.room_tone_file.clone() {
+        Some(path) => (path, settings.room_tone_gain_db),
+        None => return,
+      }
+    };
+    let mut room_tone = self.room_tone.lock().unwrap();
+    if room_tone.is_none() {
+      match RoomTone::load(&path) {
+        Ok(loaded) => {
+          gstreamer::debug!(CAT, "mix_room_tone(): loaded room tone from {}", path);
+          *room_tone = Some(loaded);
+        },
+        Err(e) => {
+          gstreamer::element_imp_warning!(
+            self,
+            gstreamer::ResourceError::Read,
+            ("Failed to load room tone file"),
+            ["{}: {}", path, e]
+          );
+          return;
+        },
+      }
+    }
+    if let Some(room_tone) = room_tone.as_mut() {
+      profile::time(Stage::Dsp, || {
+        for sentence in sentences {
+          room_tone.mix(sentence, sample_rate, gain_db);
+        }
+      });
+    }
+  }
+
+  /// Synthesises an utterance according to the `split-sentences` property, returning the audio
+  /// for each sentence separately if sentence splitting is on.
+  fn synthesise_utterance(
+    &self,
+    s: &PyAny,
+    text: &str,
+    voice: Voice,
+    speed: Option<f64>,
+  ) -> Option<Vec<Vec<f32>>> {
+    let split_sentences = self.settings.lock().unwrap().split_sentences;
+    match split_sentences {
+      SplitSentences::On => match split_into_sentences(s, text) {
+        Ok(sentences) => {
+          gstreamer::debug!(
+            CAT,
+            "synthesise_utterance(): split into {} sentences",
+            sentences.len()
+          );
+          sentences
+            .iter()
+            .map(|sentence| self.synthesise(s, sentence, voice, speed, Some(false)))
+            .collect()
+        },
+        Err(e) => {
+          gstreamer::debug!(
+            CAT,
+            "synthesise_utterance(): model can't split sentences, synthesising whole text: {}",
+            e
+          );
+          self
+            .synthesise(s, text, voice, speed, Some(true))
+            .map(|audio| vec![audio])
+        },
+      },
+      SplitSentences::PreSplit => {
+        let mut sentences = segment::sentences(text);
+        if sentences.is_empty() {
+          sentences.push(text);
+        }
+        gstreamer::debug!(
+          CAT,
+          "synthesise_utterance(): pre-split into {} sentences",
+          sentences.len()
+        );
+        sentences
+          .iter()
+          .map(|sentence| self.synthesise(s, sentence, voice, speed, Some(false)))
+          .collect()
+      },
+      SplitSentences::Off => self
+        .synthesise(s, text, voice, speed, Some(false))
+        .map(|audio| vec![audio]),
+      SplitSentences::ModelDefault => self
+        .synthesise(s, text, voice, speed, None)
+        .map(|audio| vec![audio]),
+    }
+  }
+
+  fn synthesise(
+    &self,
+    s: &PyAny,
+    text: &str,
+    voice: Voice,
+    speed: Option<f64>,
+    split_sentences: Option<bool>,
+  ) -> Option<Vec<f32>> {
+    let (speaker_embedding, cloning_file, latents_cache_dir, max_retries, fallback_to_cpu) = {
+      let settings = self.settings.lock().unwrap();
+      (
+        settings.speaker_embedding.clone(),
+        settings.voice_cloning_input_file.clone(),
+        settings.speaker_latents_cache_dir.clone(),
+        settings.max_retries,
+        settings.fallback_to_cpu,
+      )
+    };
+    let attempt = || match (speaker_embedding.as_deref(), cloning_file.as_deref()) {
+      (Some(embedding), _) => synthesise_with_embedding(s, text, voice.language, embedding),
+      (None, Some(file)) if supports_conditioning_latents(s) => self.synthesise_with_latents(
+        s,
+        text,
+        voice.language,
+        file,
+        speed,
+        latents_cache_dir.as_deref(),
+      ),
+      _ if self.state.lock().unwrap().phoneme_input => with_phoneme_input(s, || {
+        self.synthesise_with_kwargs(s, text, voice, speed, split_sentences)
+      }),
+      _ => self.synthesise_with_kwargs(s, text, voice, speed, split_sentences),
+    };
+    let py = s.py();
+    let mut retries = 0;
+    let mut on_cpu = false;
+    let result = loop {
+      match attempt() {
+        Err(e) if python::is_gpu_oom(py, &e) && retries < max_retries => {
+          let backoff = RETRY_BACKOFF.saturating_mul(2u32.saturating_pow(retries));
+          retries += 1;
+          gstreamer::warning!(
+            CAT,
+            "synthesise(): GPU out of memory, retry {}/{} in {:?}",
+            retries,
+            max_retries,
+            backoff
+          );
+          if let Err(e) = python::empty_gpu_cache(py) {
+            gstreamer::debug!(CAT, "synthesise(): failed to empty GPU cache: {}", e);
+          }
+          py.allow_threads(|| thread::sleep(backoff));
+        },
+        Err(e) if python::is_gpu_oom(py, &e) && fallback_to_cpu && !on_cpu => {
+          gstreamer::element_imp_warning!(
+            self,
+            gstreamer::ResourceError::NoSpaceLeft,
+            ("Out of GPU memory, falling back to CPU"),
+            ["{}", python::format_traceback(py, &e)]
+          );
+          on_cpu = true;
+          if let Err(e) = s.call_method1("to", ("cpu",)) {
+            break Err(e);
+          }
+          // Cached latents live on the GPU.
+          self.speaker_latents.lock().unwrap().clear();
+        },
+        result => break result,
+      }
+    };
+    match result {
+      Ok(audio) => Some(audio),
+      Err(e) if !text.is_ascii() && python::is_unsupported_character(py, &e) => {
+        let transliteration = translit::transliterate(text);
+        let substituted: String = transliteration.substituted.iter().collect();
+        gstreamer::element_imp_warning!(
+          self,
+          gstreamer::StreamError::Format,
+          (
+            "Retrying with characters the model doesn't support transliterated: {}",
+            substituted
+          ),
+          ["{}", python::format_traceback(py, &e)]
+        );
+        // The transliterated text is ASCII, so this doesn't retry again.
+        self.synthesise(s, &transliteration.text, voice, speed, split_sentences)
+      },
+      Err(e) => {
+        let traceback = python::format_traceback(s.py(), &e);
+        gstreamer::warning!(
+          CAT,
+          "synthesise(): failed to synthesise samples:\n{}",
+          traceback
+        );
+        gstreamer::element_imp_warning!(
+          self,
+          gstreamer::LibraryError::Failed,
+          ("Failed to synthesise text"),
+          ["{}", traceback]
+        );
+        None
+      },
+    }
+  }
+
+  fn synthesise_with_kwargs(
+    &self,
+    s: &PyAny,
+    text: &str,
+    voice: Voice,
+    speed: Option<f64>,
+    split_sentences: Option<bool>,
+  ) -> PyResult<Vec<f32>> {
+    let (cloning_file, extra_kwargs) = {
+      let settings = self.settings.lock().unwrap();
+      (
+        settings.voice_cloning_input_file.clone(),
+        settings.extra_kwargs.clone(),
+      )
+    };
+    let mut kwargs = python::Kwargs::new(s.py())
+      .set("text", text)?
+      .set_some("speaker", voice.speaker)?
+      .set_some("speaker_wav", cloning_file)?
+      .set_some("language", voice.language)?
+      .set_some("speed", speed)?
+      .set_some("split_sentences", split_sentences)?;
+    if let Some(extra_kwargs) = extra_kwargs {
+      kwargs = kwargs.update_from_json(&extra_kwargs)?;
+    }
+    let audio = s.call_method("tts", (), Some(kwargs.into_dict()))?;
+    profile::time(Stage::Conversion, || python::extract_audio(audio))
+  }
+
+  /// Synthesises with a voice cloning model that supports precomputed conditioning latents (XTTS),
+  /// computing the latents for `file` only once rather than on every call.
+  fn synthesise_with_latents(
+    &self,
+    s: &PyAny,
+    text: &str,
+    language: Option<&str>,
+    file: &str,
+    speed: Option<f64>,
+    cache_dir: Option<&str>,
+  ) -> PyResult<Vec<f32>> {
+    let py = s.py();
+    let tts_model = s.getattr("synthesizer")?.getattr("tts_model")?;
+    let latents = self.speaker_latents(s, file, cache_dir)?;
+    let latents = latents.as_ref(py);
+    let kwargs = python::Kwargs::new(py)
+      .set("text", text)?
+      .set("language", language.unwrap_or(FALLBACK_LANGUAGE))?
+      .set("gpt_cond_latent", latents.get_item(0)?)?
+      .set("speaker_embedding", latents.get_item(1)?)?
+      .set_some("speed", speed)?;
+    let audio = tts_model
+      .call_method("inference", (), Some(kwargs.into_dict()))?
+      .get_item("wav")?;
+    profile::time(Stage::Conversion, || python::extract_audio(audio))
+  }
+
+  /// Returns the conditioning latents for `file`, computing them if they aren't cached yet.
+  fn speaker_latents(&self, s: &PyAny, file: &str, cache_dir: Option<&str>) -> PyResult<Py<PyAny>> {
+    let py = s.py();
+    let tts_model = s.getattr("synthesizer")?.getattr("tts_model")?;
+    let key = {
+      let settings = self.settings.lock().unwrap();
+      let modified = fs::metadata(file)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos());
+      format!("{}\0{}\0{}", settings.model, file, modified)
+    };
+    let mut cache = self.speaker_latents.lock().unwrap();
+    match cache.get(&key) {
+      Some(latents) => Ok(latents.clone_ref(py)),
+      None => {
+        let latents = load_or_compute_latents(tts_model, file, &key, cache_dir)?;
+        cache.insert(key, latents.clone_ref(py));
+        Ok(latents)
+      },
+    }
+  }
+
+  /// Handles a `coquitts-set-voice-file` event, switching the voice cloning input to the file it
+  /// names or the reference audio it carries. The latents for the new voice are computed on a
+  /// separate thread, and text keeps being synthesised with the old voice until they're ready.
+  fn set_voice_file(&self, structure: &gstreamer::StructureRef) {
+    // Whether the path is of a temporary file holding the event's reference audio.
+    let (path, temporary) = match (
+      structure.get_optional::<String>("path"),
+      structure.get_optional::<Buffer>("buffer"),
+    ) {
+      (Ok(Some(path)), _) => (path, false),
+      (_, Ok(Some(buffer))) => match write_voice_buffer(&buffer) {
+        Ok(path) => (path, true),
+        Err(e) => {
+          gstreamer::element_imp_warning!(
+            self,
+            gstreamer::ResourceError::Write,
+            (
+              "Failed to store reference audio from {} event",
+              SET_VOICE_FILE_EVENT
+            ),
+            ["{}", e]
+          );
+          return;
+        },
+      },
+      _ => {
+        gstreamer::warning!(
+          CAT,
+          "set_voice_file(): ignoring {} event without a path or buffer",
+          SET_VOICE_FILE_EVENT
+        );
+        return;
+      },
+    };
+    let generation = {
+      let mut state = self.state.lock().unwrap();
+      state.voice_file_generation += 1;
+      state.voice_file_generation
+    };
+    gstreamer::debug!(CAT, "set_voice_file(): preparing voice file {}", path);
+    let element = self.obj().clone();
+    let result = thread::Builder::new()
+      .name("coquitts-voice".into())
+      .spawn(move || element.imp().prepare_voice_file(path, generation, temporary));
+    if let Err(e) = result {
+      gstreamer::error!(CAT, "set_voice_file(): failed to spawn thread: {}", e);
+    }
+  }
+
+  /// Computes the latents for a new voice cloning input file, then switches to it unless another
+  /// one has been requested in the meantime. A temporary file is removed if it isn't used.
+  fn prepare_voice_file(&self, path: String, generation: u64, temporary: bool) {
+    let (dry_run, backend, cache_dir) = {
+      let settings = self.settings.lock().unwrap();
+      (
+        settings.dry_run,
+        settings.backend,
+        settings.speaker_latents_cache_dir.clone(),
+      )
+    };
+    if !dry_run && backend == Backend::Local {
+      let result = self.with_synth(|s| {
+        if supports_conditioning_latents(s) {
+          self
+            .speaker_latents(s, &path, cache_dir.as_deref())
+            .map(|_| ())
+            .map_err(|e| python::format_traceback(s.py(), &e))
+        }
+        else {
+          Ok(())
+        }
+      });
+      let error = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(traceback)) => Some(traceback),
+        Err(e) => Some(e.to_string()),
+      };
+      if let Some(error) = error {
+        gstreamer::element_imp_warning!(
+          self,
+          gstreamer::LibraryError::Failed,
+          (
+            "Failed to prepare voice file {}, keeping the current voice",
+            path
+          ),
+          ["{}", error]
+        );
+        if temporary {
+          remove_voice_buffer_file(&path);
+        }
+        return;
+      }
+    }
+    if self.state.lock().unwrap().voice_file_generation != generation {
+      gstreamer::debug!(CAT, "prepare_voice_file(): {} superseded", path);
+      if temporary {
+        remove_voice_buffer_file(&path);
+      }
+      return;
+    }
+    gstreamer::debug!(
+      CAT,
+      "prepare_voice_file(): switching to voice file {}",
+      path
+    );
+    self.settings.lock().unwrap().voice_cloning_input_file = Some(path.clone());
+    self.voice_file_switched(Some(&path), temporary);
+    self.obj().notify("voice-cloning-input-file");
+  }
+
+  /// Records the voice cloning input file now in use, removing the temporary file of an earlier
+  /// `coquitts-set-voice-file` event's buffer once it has been replaced.
+  fn voice_file_switched(&self, file: Option<&str>, temporary: bool) {
+    let mut voice_buffer_file = self.voice_buffer_file.lock().unwrap();
+    let previous = voice_buffer_file.take();
+    if temporary {
+      *voice_buffer_file = file.map(str::to_owned);
+    }
+    match previous {
+      Some(previous) if Some(previous.as_str()) == file => *voice_buffer_file = Some(previous),
+      Some(previous) => remove_voice_buffer_file(&previous),
+      None => (),
+    }
+  }
+  /// Polls `voice-cloning-input-file` until the element stops, preparing the voice again whenever
+  /// the file is modified.
+  fn watch_voice_file(&self) {
+    let mut watched: Option<(String, SystemTime)> = None;
+    loop {
+      {
+        let queue = self.queue.lock().unwrap();
+        let (queue, _) = self
+          .queue_cond
+          .wait_timeout_while(queue, VOICE_FILE_POLL_INTERVAL, |queue| !queue.shutdown)
+          .unwrap();
+        if queue.shutdown {
+          return;
+        }
+      }
+      let path = match self.settings.lock().unwrap().voice_cloning_input_file.clone() {
+        Some(path) => path,
+        None => {
+          watched = None;
+          continue;
+        },
+      };
+      // The file may be missing for a moment while it's being replaced.
+      let modified = match fs::metadata(&path).and_then(|m| m.modified()) {
+        Ok(modified) => modified,
+        Err(_) => continue,
+      };
+      let changed = match watched.replace((path.clone(), modified)) {
+        Some((watched_path, watched_modified)) => {
+          watched_path == path && watched_modified != modified
+        },
+        // A newly set file is prepared by whoever set it.
+        None => false,
+      };
+      if !changed {
+        continue;
+      }
+      gstreamer::debug!(CAT, "watch_voice_file(): {} changed", path);
+      self
+        .speaker_latents
+        .lock()
+        .unwrap()
+        .retain(|key, _| key.split('\0').nth(1) != Some(path.as_str()));
+      let generation = {
+        let mut state = self.state.lock().unwrap();
+        state.voice_file_generation += 1;
+        state.voice_file_generation
+      };
+      self.prepare_voice_file(path, generation, false);
+    }
+  }
+
+  /// Takes text buffers from the queue, synthesises them and pushes the audio downstream, until
+  /// the element is stopped.
+  fn run_worker(&self) {
+    gstreamer::debug!(CAT, "run_worker(): started");
+    // When the worker last finished with text, or `None` once its model has been unloaded for
+    // `unload-after-idle-seconds`.
+    let mut idle_since = Some(Instant::now());
+    loop {
+      let (buffer, epoch, sequence, backlog) = {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+          if queue.shutdown {
+            gstreamer::debug!(CAT, "run_worker(): stopped");
+            return;
+          }
+          if !queue.flushing {
+            if let Some(buffer) = queue.buffers.pop_front() {
+              queue.busy += 1;
+              queue.next_sequence += 1;
+              break (
+                buffer,
+                queue.epoch,
+                queue.next_sequence - 1,
+                queue.buffers.len(),
+              );
+            }
+          }
+          let idle_seconds = self.settings.lock().unwrap().unload_after_idle_seconds;
+          let timeout = idle_since.filter(|_| idle_seconds > 0).map(|since| {
+            Duration::from_secs(idle_seconds as u64).saturating_sub(since.elapsed())
+          });
+          match timeout {
+            Some(timeout) if timeout.is_zero() => {
+              drop(queue);
+              let mut synths: Vec<_> = self.synth().lock().unwrap().take().into_iter().collect();
+              synths.extend(
+                self.parked_synths[WORKER_INDEX.with(Cell::get)]
+                  .lock()
+                  .unwrap()
+                  .drain(),
+              );
+              if !synths.is_empty() {
+                gstreamer::debug!(
+                  CAT,
+                  "run_worker(): idle for {}s, unloading {} models",
+                  idle_seconds,
+                  synths.len()
+                );
+                self.free_models(synths);
+              }
+              idle_since = None;
+              queue = self.queue.lock().unwrap();
+            },
+            Some(timeout) => queue = self.queue_cond.wait_timeout(queue, timeout).unwrap().0,
+            None => queue = self.queue_cond.wait(queue).unwrap(),
+          }
+        }
+      };
+      // There's space in the queue again.
+      self.queue_cond.notify_all();
+      self.adapt(backlog);
+      let offset = buffer.offset();
+      if self
+        .progress
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|progress| progress.is_done(offset))
+      {
+        gstreamer::debug!(
+          CAT,
+          "run_worker(): buffer {} already done, skipping",
+          offset
+        );
+        self.wait_for_turn(sequence, epoch);
+        self.end_turn(epoch, Ok(FlowSuccess::Ok));
+        continue;
+      }
+      let (gapless, live_scheduling) = {
+        let settings = self.settings.lock().unwrap();
+        (settings.gapless, settings.live_scheduling)
+      };
+      if gapless || live_scheduling {
+        // The timestamp depends on where the audio before it ends, so synthesis can't run ahead.
+        self.wait_for_turn(sequence, epoch);
+      }
+      let buffer = if gapless {
+        self.stamp_gapless(buffer)
+      }
+      else {
+        buffer
+      };
+      if let Some(lateness) = self.lateness(&buffer) {
+        let (processed, dropped) = {
+          let mut queue = self.queue.lock().unwrap();
+          queue.dropped += 1;
+          (queue.processed, queue.dropped)
+        };
+        gstreamer::debug!(
+          CAT,
+          "run_worker(): skipping text buffer {}ns behind downstream",
+          lateness
+        );
+        self.post_dropped(&buffer, lateness, processed, dropped);
+        self.wait_for_turn(sequence, epoch);
+        self.end_turn(epoch, Ok(FlowSuccess::Ok));
+        continue;
+      }
+      if !self.throttle(&buffer, epoch) {
+        // The text may have been waiting when a flush discarded it, which isn't a drop.
+        if self.wait_for_turn(sequence, epoch) {
+          let (processed, dropped) = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.dropped += 1;
+            (queue.processed, queue.dropped)
+          };
+          gstreamer::debug!(
+            CAT,
+            "run_worker(): skipping text buffer beyond max-chars-per-minute"
+          );
+          self.post_dropped(&buffer, 0, processed, dropped);
+        }
+        self.end_turn(epoch, Ok(FlowSuccess::Ok));
+        continue;
+      }
+      if let Some(bytes) = text_bytes(&buffer) {
+        let text = String::from_utf8_lossy(&bytes).into_owned();
+        self.queue.lock().unwrap().in_flight.insert((epoch, sequence), text);
+      }
+      let split = self.split_for_latency(&buffer);
+      let result = match &split {
+        Some((first, _)) => self.process_profiled(first, Part::FirstClause),
+        None => self.process_profiled(&buffer, Part::Whole),
+      };
+      // Whether the text was synthesised or deliberately skipped, rather than failing.
+      let result = result.and_then(|processed| {
+        if !self.wait_for_turn(sequence, epoch) {
+          gstreamer::debug!(CAT, "run_worker(): flushed, discarding output");
+          return Err(FlowError::Flushing);
+        }
+        let mut done = true;
+        let mut end = None;
+        match processed {
+          Processed::Synthesised(outputs) => {
+            end = audio_end(&outputs);
+            self.push_outputs(outputs, epoch)?;
+          },
+          Processed::Skipped => (),
+          Processed::Failed => done = false,
+        }
+        if let Some((first, mut rest)) = split {
+          // The rest starts where the first clause's audio ends.
+          rest.get_mut().unwrap().set_pts(end.or(first.pts()));
+          match self.process_profiled(&rest, Part::Rest)? {
+            Processed::Synthesised(outputs) => {
+              self.push_outputs(outputs, epoch)?;
+            },
+            Processed::Skipped => (),
+            Processed::Failed => done = false,
+          }
+        }
+        Ok(done)
+      });
+      // Failed texts aren't recorded, so that resuming has another go at them.
+      if result == Ok(true) {
+        if let Some(progress) = self.progress.lock().unwrap().as_mut() {
+          if let Err(e) = progress.mark_done(offset) {
+            gstreamer::element_imp_warning!(
+              self,
+              gstreamer::ResourceError::Write,
+              ("Failed to record progress"),
+              ["{}", e]
+            );
+          }
+        }
+      }
+      if result.is_err() {
+        // Let the buffers after this one have their turn anyway.
+        self.wait_for_turn(sequence, epoch);
+      }
+      #[cfg(feature = "metrics")]
+      match result {
+        Ok(true) => self.metrics.lock().unwrap().record_utterance(),
+        Err(FlowError::Flushing) => (),
+        Ok(false) | Err(_) => self.metrics.lock().unwrap().record_error(),
+      }
+      let result = result.map(|_| FlowSuccess::Ok);
+      {
+        let mut queue = self.queue.lock().unwrap();
+        queue.processed += 1;
+        queue.in_flight.remove(&(epoch, sequence));
+      }
+      self.end_turn(epoch, result);
+      idle_since = Some(Instant::now());
+    }
+  }
+
+  /// Synthesises a text buffer, logging where the time went if `profile` is on.
+  fn process_profiled(&self, buffer: &Buffer, part: Part) -> Result<Processed, FlowError> {
+    if self.settings.lock().unwrap().profile {
+      profile::start();
+    }
+    let result = self.process(buffer, part);
+    if let Some(profile) = profile::finish() {
+      gstreamer::info!(
+        PROFILE_CAT,
+        "process_profiled(): buffer {} at {}: {}",
+        buffer.offset(),
+        buffer.pts().display(),
+        profile.to_structure()
+      );
+    }
+    result
+  }
+
+  /// Pushes the outputs for a text buffer, unless there's a flush in the meantime.
+  fn push_outputs(&self, outputs: Vec<Output>, epoch: u64) -> Result<FlowSuccess, FlowError> {
+    let start_stream = std::mem::take(&mut self.state.lock().unwrap().start_stream);
+    if start_stream && self.obj().src_pad().current_caps().is_none() {
+      let sample_rate = outputs.iter().find_map(|output| match output {
+        Output::Rate(sample_rate) => Some(*sample_rate),
+        _ => None,
+      });
+      self.start_stream(sample_rate)?;
+    }
+    // Downstream may have sent a reconfigure event, e.g. because its caps changed. Buffers are
+    // pushed from the workers rather than the base class, so it has to be handled here.
+    if !self.obj().reconfigure() {
+      let resample = self.settings.lock().unwrap().resample;
+      gstreamer::element_imp_error!(
+        self,
+        gstreamer::CoreError::Negotiation,
+        ("Downstream no longer accepts the audio"),
+        [
+          "Renegotiating after downstream asked to reconfigure failed{}",
+          if resample {
+            ""
+          }
+          else {
+            ". Set `resample=true` to resample to the rate it wants"
+          }
+        ]
+      );
+      return Err(FlowError::NotNegotiated);
+    }
+    for output in outputs {
+      if self.queue.lock().unwrap().epoch != epoch {
+        gstreamer::debug!(CAT, "push_outputs(): flushed, discarding output");
+        return Err(FlowError::Flushing);
+      }
+      match output {
+        Output::Buffer(buffer) => {
+          let (pts, duration) = (buffer.pts(), buffer.duration());
+          self.obj().src_pad().push(buffer)?;
+          self.record_output(pts, duration);
+        },
+        Output::Event(event) => {
+          self.obj().src_pad().push_event(event);
+        },
+        Output::Rate(sample_rate) => self.renegotiate(sample_rate)?,
+        Output::Marks(marks) => {
+          self.push_marks(marks)?;
+        },
+      }
+    }
+    Ok(FlowSuccess::Ok)
+  }
+
+  /// Splits a text buffer after its first clause for `low-latency-split`, returning a buffer for
+  /// the first clause and one for the rest, whose timestamp is only known once the first clause
+  /// has been synthesised. Text that's fitted to a duration or isn't plain text isn't split.
+  fn split_for_latency(&self, buffer: &Buffer) -> Option<(Buffer, Buffer)> {
+    {
+      let settings = self.settings.lock().unwrap();
+      // Sidecar files describe whole utterances.
+      if !settings.low_latency_split
+        || settings.ssml
+        || settings.sidecar_dir.is_some()
+        || (settings.fit_duration && buffer.duration().is_some())
+      {
+        return None;
+      }
+    }
+    {
+      let state = self.state.lock().unwrap();
+      if state.json_input || state.phoneme_input || state.csv_input {
+        return None;
+      }
+    }
+    if target_duration(buffer).is_some() {
+      return None;
+    }
+    let bytes = text_bytes(buffer)?;
+    let text = str::from_utf8(&bytes).ok()?;
+    // A clause could end inside a tone directive.
+    if tone::split(text).len() > 1 {
+      return None;
+    }
+    // The rest would lose the speaker label.
+    if self
+      .settings
+      .lock()
+      .unwrap()
+      .speaker_map
+      .apply(text)
+      .is_some()
+    {
+      return None;
+    }
+    let (first, rest) = split_first_clause(text)?;
+    gstreamer::debug!(
+      CAT,
+      "split_for_latency(): synthesising {:?} before the rest",
+      first
+    );
+    let id = request_id(buffer);
+    let text_buffer = |text: &str| {
+      let mut part = Buffer::from_slice(text.as_bytes().to_vec());
+      {
+        let part = part.get_mut().unwrap();
+        part.set_pts(buffer.pts());
+        part.set_offset(buffer.offset());
+        set_request_id(part, id.as_deref());
+      }
+      part
+    };
+    Some((text_buffer(first), text_buffer(rest)))
+  }
+
+  /// Adds a measured synthesis time to the running average, asking the pipeline to recalculate
+  /// its latency if the average has grown well beyond what was last reported.
+  fn record_latency(&self, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    self.metrics.lock().unwrap().record_latency(elapsed);
+    let elapsed = ClockTime::from_nseconds(elapsed.as_nanos() as u64);
+    let grown = {
+      let mut state = self.state.lock().unwrap();
+      let average = match state.average_latency {
+        Some(average) => ClockTime::from_nseconds(
+          (average.nseconds() as f64 * (1.0 - LATENCY_SMOOTHING)
+            + elapsed.nseconds() as f64 * LATENCY_SMOOTHING) as u64,
+        ),
+        None => elapsed,
+      };
+      state.average_latency = Some(average);
+      state.reported_latency.is_some_and(|reported| {
+        average.nseconds() as f64 > reported.nseconds() as f64 * LATENCY_GROWTH_THRESHOLD
+      })
+    };
+    gstreamer::trace!(CAT, "record_latency(): synthesis took {}", elapsed);
+    if grown && self.settings.lock().unwrap().max_latency == 0 {
+      gstreamer::debug!(
+        CAT,
+        "record_latency(): latency grew, posting latency message"
+      );
+      let _ = self.obj().post_message(
+        gstreamer::message::Latency::builder()
+          .src(&*self.obj())
+          .build(),
+      );
+    }
+  }
+
+  /// Starts serving metrics on `metrics-port`, if it's set.
+  #[cfg(feature = "metrics")]
+  fn start_metrics_server(&self) -> Result<(), ErrorMessage> {
+    let port = self.settings.lock().unwrap().metrics_port;
+    if port == 0 {
+      return Ok(());
+    }
+    let element = self.obj().downgrade();
+    let server = metrics::Server::start(port as u16, move || {
+      let element = element.upgrade()?;
+      let queue_depth = element.imp().queue.lock().unwrap().buffers.len();
+      let metrics = element.imp().metrics.lock().unwrap();
+      Some(metrics.render(&element.name(), queue_depth))
+    })
+    .map_err(|e| {
+      gstreamer::error_msg!(
+        gstreamer::ResourceError::OpenRead,
+        ("Failed to serve metrics on port {}", port),
+        ["{}", e]
+      )
+    })?;
+    gstreamer::debug!(
+      CAT,
+      "start_metrics_server(): serving metrics on port {}",
+      port
+    );
+    *self.metrics_server.lock().unwrap() = Some(server);
+    Ok(())
+  }
+
+  /// The latency to add in latency queries.
+  fn latency(&self) -> ClockTime {
+    let max_latency = self.settings.lock().unwrap().max_latency;
+    if max_latency > 0 {
+      return ClockTime::from_nseconds(max_latency);
+    }
+    let mut state = self.state.lock().unwrap();
+    let latency = state.average_latency.unwrap_or(ClockTime::ZERO);
+    state.reported_latency = Some(latency);
+    latency
+  }
+
+  /// Forgets what was learnt from the previous input stream when a new one starts.
+  fn reset_stream_state(&self) {
+    let mut state = self.state.lock().unwrap();
+    state.last_detected_language = None;
+    state.tag_language = None;
+    state.qos_earliest_time = None;
+    state.output_position = None;
+    state.gapless_offset = 0;
+    state.output_duration = None;
+  }
+
+  /// Tracks how far the audio pushed downstream has got, for position and duration queries.
+  fn record_output(&self, pts: Option<ClockTime>, duration: Option<ClockTime>) {
+    let reverse = self.obj().segment().rate() < 0.0;
+    let mut state = self.state.lock().unwrap();
+    if let Some(pts) = pts {
+      // In reverse playback the position moves back to the start of each buffer.
+      state.output_position = Some(match duration {
+        Some(duration) if !reverse => pts + duration,
+        _ => pts,
+      });
+    }
+    if let Some(duration) = duration {
+      state.output_duration = Some(state.output_duration.unwrap_or(ClockTime::ZERO) + duration);
+    }
+  }
+
+  /// Blocks until the output of the buffer with the given sequence number is next to be pushed.
+  /// Returns false if it was flushed or a previous buffer failed in the meantime, so its output
+  /// should be discarded.
+  fn wait_for_turn(&self, sequence: u64, epoch: u64) -> bool {
+    let mut queue = self.queue.lock().unwrap();
+    while queue.epoch == epoch && queue.next_push != sequence && !queue.shutdown {
+      queue = self.queue_cond.wait(queue).unwrap();
+    }
+    queue.epoch == epoch && !queue.shutdown && queue.flow_error.is_none()
+  }
+
+  /// Finishes with a buffer taken from the queue, passing the turn to push output to the next
+  /// one.
+  fn end_turn(&self, epoch: u64, result: Result<FlowSuccess, FlowError>) {
+    let mut queue = self.queue.lock().unwrap();
+    queue.busy -= 1;
+    if queue.epoch == epoch {
+      queue.next_push += 1;
+      if let Err(e) = result {
+        if e != FlowError::Flushing && queue.flow_error.is_none() {
+          gstreamer::debug!(CAT, "end_turn(): stopping on flow error: {:?}", e);
+          queue.flow_error = Some(e);
+          queue.buffers.clear();
+        }
+      }
+    }
+    self.queue_cond.notify_all();
+  }
+
+  /// Blocks until the workers have synthesised and pushed every queued buffer, so that a serialized
+  /// event isn't forwarded ahead of audio for text that preceded it.
+  fn wait_for_idle(&self) {
+    let mut queue = self.queue.lock().unwrap();
+    while (queue.busy > 0 || !queue.buffers.is_empty())
+      && !queue.flushing
+      && !queue.shutdown
+      && queue.flow_error.is_none()
+    {
+      queue = self.queue_cond.wait(queue).unwrap();
+    }
+  }
+
+  /// Synthesises the speech of a message's text, taking it from the utterance cache if it's there
+  /// and waiting for the message's turn by priority if it isn't. In dry-run mode it's silence.
+  fn synthesise_text(
+    &self,
+    text: &str,
+    voice: Voice,
+    message: &Message,
+    fit_to: Option<ClockTime>,
+    dry_run: bool,
+  ) -> Result<Option<Utterance>, ErrorMessage> {
+    if dry_run {
+      return self.sample_rate().map(|sample_rate| {
+        // In u64, so that long texts can't overflow.
+        let duration_ms = text.chars().count() as u64 * DRY_RUN_MS_PER_CHAR as u64;
+        Some((
+          vec![vec![0.0; (duration_ms * sample_rate / 1000) as usize]],
+          sample_rate,
+        ))
+      });
+    }
+    let adaptive_speed = self.settings.lock().unwrap().adaptive_speed;
+    let speed = if self.state.lock().unwrap().adapting {
+      Some(message.speed.unwrap_or(1.0) * adaptive_speed)
+    }
+    else {
+      message.speed
+    };
+    let cache_key = self.cache_key(text, voice, speed, fit_to);
+    if let Some(audio) = self.cached_utterance(&cache_key) {
+      return Ok(Some(audio));
+    }
+    let priority = message
+      .priority
+      .unwrap_or_else(|| self.settings.lock().unwrap().priority);
+    gstreamer::debug!(
+      CAT,
+      "synthesise_text(): waiting for {:?} priority turn",
+      priority
+    );
+    let permit = scheduler::acquire(priority);
+    let maybe_audio = match self.settings.lock().unwrap().backend {
+      Backend::Local => self.sample_rate().and_then(|sample_rate| {
+        profile::time(Stage::Inference, || {
+          self.with_synth(|s| {
+            self
+              .synthesise_to_fit(s, text, voice, speed, fit_to, sample_rate)
+              .map(|audio| (audio, sample_rate))
+          })
+        })
+      }),
+      Backend::Server => {
+        if speed.is_some() || fit_to.is_some() {
+          gstreamer::debug!(
+            CAT,
+            "synthesise_text(): the server can't change the speed of speech"
+          );
+        }
+        profile::time(Stage::Inference, || self.synthesise_remote(text, voice))
+          .map(|(audio, sample_rate)| Some((vec![audio], sample_rate)))
+      },
+      Backend::Onnx => {
+        let speaker = self.settings.lock().unwrap().speaker_index.max(0) as i64;
+        profile::time(Stage::Inference, || {
+          self.with_onnx_model(|model| {
+            model
+              .synthesise(text, speed.unwrap_or(1.0), speaker)
+              .map(|audio| Some((vec![audio], model.sample_rate())))
+          })
+        })
+        .and_then(|result| {
+          result.map_err(|e| {
+            gstreamer::error_msg!(
+              gstreamer::LibraryError::Failed,
+              ("Failed to synthesise with the ONNX model"),
+              ["{}", e]
+            )
+          })
+        })
+      },
+    };
+    drop(permit);
+    if let Ok(Some((audio, sample_rate))) = &maybe_audio {
+      if audio.iter().any(|sentence| !sentence.is_empty()) {
+        self.cache_utterance(&cache_key, audio, *sample_rate);
+      }
+    }
+    maybe_audio
+  }
+
+  /// Posts the `coquitts-utterance` message for the audio of a text with an id.
+  fn post_utterance(&self, id: &str, buffers: &[Buffer]) {
+    let duration = buffers
+      .iter()
+      .filter_map(|buffer| buffer.duration())
+      .fold(ClockTime::ZERO, |total, duration| total + duration);
+    let mut structure = gstreamer::Structure::builder(UTTERANCE_MESSAGE)
+      .field("id", id)
+      .field("duration", duration)
+      .build();
+    if let Some(timestamp) = buffers.first().and_then(|buffer| buffer.pts()) {
+      structure.set("timestamp", timestamp);
+    }
+    gstreamer::debug!(CAT, "post_utterance(): {}", structure);
+    let _ = self.obj().post_message(
+      gstreamer::message::Element::builder(structure)
+        .src(&*self.obj())
+        .build(),
+    );
+  }
+
+  /// Applies `empty-output-policy` when the model returns no audio for text.
+  fn empty_output(&self, text: &str) -> Result<(), FlowError> {
+    if text.trim().is_empty() {
+      // Expected with `empty-text-policy=synthesize`.
+      gstreamer::debug!(CAT, "empty_output(): no audio for empty text");
+      return Ok(());
+    }
+    match self.settings.lock().unwrap().empty_output_policy {
+      EmptyOutputPolicy::Skip => {
+        gstreamer::element_imp_warning!(
+          self,
+          gstreamer::LibraryError::Failed,
+          ("The model returned no audio, skipping text"),
+          ["{:?}", text]
+        );
+        Ok(())
+      },
+      EmptyOutputPolicy::Error => {
+        gstreamer::element_imp_error!(
+          self,
+          gstreamer::LibraryError::Failed,
+          ("The model returned no audio"),
+          ["{:?}", text]
+        );
+        Err(FlowError::Error)
+      },
+    }
+  }
+
+  /// Applies `segment-stop-policy` to audio starting at `pts` that runs past the stop of the
+  /// segment.
+  fn apply_segment_stop_policy(
+    &self,
+    mut sentences: Vec<Vec<f32>>,
+    pts: Option<ClockTime>,
+    sample_rate: u64,
+  ) -> Result<Vec<Vec<f32>>, FlowError> {
+    let segment = self.obj().segment();
+    let stop = segment
+      .downcast_ref::<ClockTime>()
+      .and_then(|segment| segment.stop());
+    let (pts, stop) = match (pts, stop) {
+      (Some(pts), Some(stop)) => (pts, stop),
+      _ => return Ok(sentences),
+    };
+    let room = stop.saturating_sub(pts).nseconds();
+    let available = (room.saturating_mul(sample_rate) / 1_000_000_000) as usize;
+    let samples: usize = sentences.iter().map(Vec::len).sum();
+    if samples <= available {
+      return Ok(sentences);
+    }
+    match self.settings.lock().unwrap().segment_stop_policy {
+      SegmentStopPolicy::Push => Ok(sentences),
+      SegmentStopPolicy::Clip => {
+        gstreamer::debug!(
+          CAT,
+          "apply_segment_stop_policy(): clipping {} of {} samples at {}",
+          samples - available,
+          samples,
+          stop
+        );
+        let mut remaining = available;
+        for sentence in &mut sentences {
+          sentence.truncate(remaining);
+          remaining -= sentence.len();
+        }
+        sentences.retain(|sentence| !sentence.is_empty());
+        Ok(sentences)
+      },
+      SegmentStopPolicy::Error => {
+        gstreamer::element_imp_error!(
+          self,
+          gstreamer::StreamError::Failed,
+          ("Synthesised audio runs past the end of the segment"),
+          [
+            "{} samples starting at {} don't fit before the segment's stop at {}",
+            samples,
+            pts,
+            stop
+          ]
+        );
+        Err(FlowError::Error)
+      },
+    }
+  }
+
+  /// What a message's speech is synthesised from, for the `CoquittsSourceMeta` on its buffers.
+  fn source(&self, message: &Message, voice: Voice) -> gstreamer::Structure {
+    let model = self.active_model();
+    let settings = self.settings.lock().unwrap();
+    let model = match settings.backend {
+      Backend::Local => Some(model),
+      Backend::Server => Some(settings.server_url.clone()),
+      Backend::Onnx => settings.onnx_model_path.clone(),
+    };
+    let mut source = gstreamer::Structure::builder(SOURCE_META_NAME)
+      .field("text", &message.text)
+      .field("backend", settings.backend)
+      .field("pitch-semitones", settings.pitch_semitones)
+      .field("gain-db", settings.gain_db)
+      .build();
+    if let Some(model) = model {
+      source.set("model", model);
+    }
+    if let Some(speaker) = voice.speaker {
+      source.set("speaker", speaker);
+    }
+    if let Some(language) = voice.language {
+      source.set("language", language);
+    }
+    if let Some(speed) = message.speed {
+      source.set("speed", speed);
+    }
+    if let Some(id) = &message.id {
+      source.set("id", id);
+    }
+    source
+  }
+
+  /// Synthesises text with the `server` backend. Audio is resampled to the rate of the first
+  /// audio the server sent, which the output has been negotiated at.
+  fn synthesise_remote(&self, text: &str, voice: Voice) -> Result<(Vec<f32>, u64), ErrorMessage> {
+    let url = self.settings.lock().unwrap().server_url.clone();
+    gstreamer::debug!(
+      CAT,
+      "synthesise_remote(): requesting {:?} from {}",
+      text,
+      url
+    );
+    let request = server::Request {
+      text,
+      speaker: voice.speaker,
+      language: voice.language,
+    };
+    let (audio, rate) = server::synthesise(&url, request).map_err(|e| {
+      gstreamer::error_msg!(
+        gstreamer::ResourceError::Read,
+        ("Failed to synthesise with the server at {}", url),
+        ["{}", e]
+      )
+    })?;
+    let mut server_rate = self.server_rate.lock().unwrap();
+    match *server_rate {
+      Some(known) if known != rate => Ok((
+        dsp::resample_linear(&audio, rate as u32, known as u32),
+        known,
+      )),
+      _ => {
+        *server_rate = Some(rate);
+        Ok((audio, rate))
+      },
+    }
+  }
+
+  /// Runs `f` with the `onnx` backend's model, loading it from `onnx-model-path` first if it
+  /// hasn't been loaded yet.
+  fn with_onnx_model<R>(&self, f: impl FnOnce(&OnnxModel) -> R) -> Result<R, ErrorMessage> {
+    // Setting `onnx-model-path` locks the model with the settings locked.
+    let path = self.settings.lock().unwrap().onnx_model_path.clone();
+    let mut model = self.onnx_model.lock().unwrap();
+    let loaded = model.is_none();
+    if loaded {
+      let path = path.ok_or_else(|| {
+        gstreamer::error_msg!(
+          gstreamer::LibraryError::Settings,
+          ["onnx-model-path must be set to use the onnx backend"]
+        )
+      })?;
+      let started = Instant::now();
+      *model = Some(OnnxModel::load(Path::new(&path)).map_err(|e| {
+        gstreamer::error_msg!(
+          gstreamer::LibraryError::Init,
+          ("Failed to load ONNX model {}", path),
+          ["{}", e]
+        )
+      })?);
+      gstreamer::debug!(
+        CAT,
+        "with_onnx_model(): loaded {} in {:?}",
+        path,
+        started.elapsed()
+      );
+    }
+    let result = f(model.as_ref().unwrap());
+    drop(model);
+    if loaded {
+      self.obj().notify("is-loaded");
+      self.obj().notify("effective-settings");
+    }
+    Ok(result)
+  }
+
+  /// The sample rate of the `server` backend's audio, asking the server for a short utterance to
+  /// find it out the first time.
+  fn server_sample_rate(&self) -> Result<u64, ErrorMessage> {
+    if let Some(rate) = *self.server_rate.lock().unwrap() {
+      return Ok(rate);
+    }
+    let (speaker, language) = {
+      let settings = self.settings.lock().unwrap();
+      (
+        settings.speaker.clone(),
+        settings
+          .language
+          .clone()
+          .filter(|language| language != AUTO_LANGUAGE),
+      )
+    };
+    let voice = Voice {
+      language: language.as_deref(),
+      speaker: speaker.as_deref(),
+    };
+    self
+      .synthesise_remote(SERVER_PROBE_TEXT, voice)
+      .map(|(_, rate)| rate)
+  }
+
+  /// Synthesises an utterance, resynthesising it faster if it's longer than `fit_to`.
+  fn synthesise_to_fit(
+    &self,
+    s: &PyAny,
+    text: &str,
+    voice: Voice,
+    speed: Option<f64>,
+    fit_to: Option<ClockTime>,
+    sample_rate: u64,
+  ) -> Option<Vec<Vec<f32>>> {
+    let audio = self.synthesise_utterance(s, text, voice, speed)?;
+    let (padding_start_ms, padding_end_ms, pitch_semitones) = {
+      let settings = self.settings.lock().unwrap();
+      (
+        settings.silence_padding_start_ms,
+        settings.silence_padding_end_ms,
+        settings.pitch_semitones,
+      )
+    };
+    let padding = ClockTime::from_mseconds(padding_start_ms as u64 + padding_end_ms as u64);
+    let duration = match fit_to {
+      Some(duration) if duration > padding => duration,
+      _ => return Some(audio),
+    };
+    let samples: usize = audio.iter().map(Vec::len).sum();
+    // The pitch shift changes the duration too.
+    let audio_duration = samples as f64 / sample_rate as f64 / dsp::pitch_ratio(pitch_semitones);
+    let target_duration = (duration - padding).nseconds() as f64 / 1_000_000_000.0;
+    if audio_duration <= target_duration {
+      return Some(audio);
+    }
+    let speed = speed.unwrap_or(1.0) * audio_duration / target_duration;
+    gstreamer::debug!(
+      CAT,
+      "synthesise_to_fit(): {:.3}s of audio exceeds buffer duration {:.3}s, resynthesising with speed {:.3}",
+      audio_duration,
+      target_duration,
+      speed
+    );
+    Some(
+      self
+        .synthesise_utterance(s, text, voice, Some(speed))
+        .unwrap_or(audio),
+    )
+  }
+
+  /// Identifies an utterance by its text and everything else that affects the synthesised audio,
+  /// for the utterance cache.
+  fn cache_key(
+    &self,
+    text: &str,
+    voice: Voice,
+    speed: Option<f64>,
+    fit_to: Option<ClockTime>,
+  ) -> CacheKey {
+    let mut key = CacheKey::default();
+    key.add("model", self.active_model());
+    key.add("phoneme-input", self.state.lock().unwrap().phoneme_input);
+    {
+      let settings = self.settings.lock().unwrap();
+      key.add_some(
+        "voice-cloning-input-file",
+        settings.voice_cloning_input_file.as_ref(),
+      );
+      // The file may have been overwritten with another voice.
+      key.add_some(
+        "voice-cloning-input-file-modified",
+        settings
+          .voice_cloning_input_file
+          .as_ref()
+          .and_then(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+          .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+          .map(|modified| modified.as_nanos()),
+      );
+      key.add_some(
+        "speaker-embedding",
+        settings.speaker_embedding.as_ref().map(|embedding| {
+          embedding
+            .iter()
+            .map(f32::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+        }),
+      );
+      key.add_some("extra-kwargs", settings.extra_kwargs.as_ref());
+      key.add(
+        "split-sentences",
+        format_args!("{:?}", settings.split_sentences),
+      );
+      key.add("backend", format_args!("{:?}", settings.backend));
+      match settings.backend {
+        Backend::Local => (),
+        Backend::Server => key.add("server-url", &settings.server_url),
+        Backend::Onnx => {
+          key.add_some("onnx-model-path", settings.onnx_model_path.as_ref());
+          key.add("speaker-index", settings.speaker_index);
+        },
+      }
+      if fit_to.is_some() {
+        // These decide whether the speech is sped up to fit.
+        key.add("pitch-semitones", settings.pitch_semitones);
+        key.add(
+          "silence-padding-start-ms",
+          settings.silence_padding_start_ms,
+        );
+        key.add("silence-padding-end-ms", settings.silence_padding_end_ms);
+      }
+    }
+    key.add("text", text);
+    key.add_some("speaker", voice.speaker);
+    key.add_some("language", voice.language);
+    key.add_some("speed", speed);
+    key.add_some("fit-to", fit_to.map(ClockTime::nseconds));
+    key
+  }
+
+  /// Writes a JSON file describing an utterance to `sidecar-dir`, if it's set, named after the
+  /// offset of its text buffer.
+  fn write_sidecar(&self, offset: u64, text: &str, voice: Voice, buffers: &[Buffer]) {
+    let dir = match self.settings.lock().unwrap().sidecar_dir.clone() {
+      Some(dir) => dir,
+      None => return,
+    };
+    let mut sidecar = utterance_timing(text, buffers);
+    sidecar["id"] = offset.into();
+    sidecar["model"] = self.active_model().into();
+    sidecar["speaker"] = voice.speaker.into();
+    sidecar["language"] = voice.language.into();
+    let path = Path::new(&dir).join(format!("{:06}.json", offset));
+    gstreamer::debug!(
+      CAT,
+      "write_sidecar(): writing {}",
+      path.display()
+    );
+    if let Err(e) = fs::create_dir_all(&dir).and_then(|()| fs::write(&path, sidecar.to_string())) {
+      gstreamer::element_imp_warning!(
+        self,
+        gstreamer::ResourceError::Write,
+        ("Failed to write sidecar file {}", path.display()),
+        ["{}", e]
+      );
+    }
+  }
+
+  fn disk_cache(&self) -> Option<DiskCache> {
+    let (dir, max_bytes) = {
+      let settings = self.settings.lock().unwrap();
+      (settings.cache_dir.clone()?, settings.cache_max_bytes)
+    };
+    DiskCache::open(&dir, max_bytes)
+      .map_err(|e| {
+        gstreamer::warning!(CAT, "disk_cache(): can't open cache in {}: {}", dir, e);
+      })
+      .ok()
+  }
+
+  /// Returns a previously synthesised utterance, and its sample rate, from the memory cache or
+  /// else the disk cache.
+  fn cached_utterance(&self, key: &CacheKey) -> Option<Utterance> {
+    if let Some(audio) = self.utterances.lock().unwrap().get(key) {
+      gstreamer::debug!(CAT, "cached_utterance(): memory hit for {}", key.digest());
+      return Some(audio);
+    }
+    let cache = self.disk_cache()?;
+    match cache.get(key) {
+      Ok(Some((audio, sample_rate))) => {
+        gstreamer::debug!(CAT, "cached_utterance(): disk hit for {}", key.digest());
+        self.cache_in_memory(key, &audio, sample_rate);
+        Some((audio, sample_rate))
+      },
+      Ok(None) => None,
+      Err(e) => {
+        gstreamer::warning!(
+          CAT,
+          "cached_utterance(): failed to read {}: {}",
+          key.digest(),
+          e
+        );
+        None
+      },
+    }
+  }
+
+  fn cache_utterance(&self, key: &CacheKey, audio: &[Vec<f32>], sample_rate: u64) {
+    self.cache_in_memory(key, audio, sample_rate);
+    if let Some(cache) = self.disk_cache() {
+      if let Err(e) = cache.put(key, audio, sample_rate) {
+        gstreamer::element_imp_warning!(
+          self,
+          gstreamer::ResourceError::Write,
+          ("Failed to cache synthesised audio"),
+          ["{}", e]
+        );
+      }
+    }
+  }
+
+  fn cache_in_memory(&self, key: &CacheKey, audio: &[Vec<f32>], sample_rate: u64) {
+    let capacity = self.settings.lock().unwrap().memory_cache_utterances as usize;
+    if capacity > 0 {
+      self
+        .utterances
+        .lock()
+        .unwrap()
+        .put(key, audio, sample_rate, capacity);
+    }
+  }
+
+  /// Pushes speech marks on the `marks` pad if it has been requested, starting its stream and
+  /// following the audio's segment first if necessary.
+  fn push_marks(&self, marks: Buffer) -> Result<FlowSuccess, FlowError> {
+    let pad = match self.marks_pad.lock().unwrap().clone() {
+      Some(pad) => pad,
+      None => return Ok(FlowSuccess::Ok),
+    };
+    if pad
+      .sticky_event::<gstreamer::event::StreamStart>(0)
+      .is_none()
+    {
+      let stream_id = pad.create_stream_id(&*self.obj(), Some("marks"));
+      pad.push_event(gstreamer::event::StreamStart::new(&stream_id));
+      pad.push_event(gstreamer::event::Caps::new(&MARKS_CAPS));
+    }
+    let segment = self.obj().segment();
+    let current = pad.sticky_event::<gstreamer::event::Segment>(0);
+    if current.is_none_or(|current| *current.segment() != segment) {
+      pad.push_event(gstreamer::event::Segment::new(&segment));
+    }
+    match pad.push(marks) {
+      // Nobody listening for marks is no reason to stop the audio.
+      Err(FlowError::NotLinked) => Ok(FlowSuccess::Ok),
+      result => result,
+    }
+  }
+
+  /// Switches the output caps to a new sample rate before audio at that rate is pushed, e.g. after
+  /// the model has been switched mid-stream.
+  fn renegotiate(&self, sample_rate: u64) -> Result<(), FlowError> {
+    let src_pad = self.obj().src_pad().clone();
+    let current = match src_pad.current_caps() {
+      Some(current) => current,
+      // Not negotiated yet, which happens with the rate of the current model anyway.
+      None => return Ok(()),
+    };
+    let current_rate = current.structure(0).and_then(|s| s.get::<i32>("rate").ok());
+    if current_rate == Some(sample_rate as i32) {
+      return Ok(());
+    }
+    let mut caps = current.copy();
+    caps.make_mut().set("rate", sample_rate as i32);
+    gstreamer::debug!(
+      CAT,
+      "renegotiate(): sample rate changed from {:?} to {}, renegotiating",
+      current_rate,
+      sample_rate
+    );
+    if !src_pad.peer_query_accept_caps(&caps) {
+      gstreamer::element_imp_error!(
+        self,
+        gstreamer::CoreError::Negotiation,
+        (
+          "Downstream doesn't accept the new sample rate {}",
+          sample_rate
+        ),
+        ["{}", caps]
+      );
+      return Err(FlowError::NotNegotiated);
+    }
+    // The base class sends the caps, and the state derived from them is brought up to date, as
+    // set_caps() isn't called for caps the element changes itself.
+    if let Err(e) = self.obj().update_src_caps(&caps) {
+      gstreamer::debug!(CAT, "renegotiate(): {}", e);
+      return Err(FlowError::NotNegotiated);
+    }
+    self.state.lock().unwrap().output_rate = Some(sample_rate);
+    Ok(())
+  }
+
+  /// The rate to push audio of `sample_rate` at: the negotiated rate with `resample`, or else the
+  /// audio's own.
+  fn output_rate(&self, sample_rate: u64) -> u64 {
+    if !self.settings.lock().unwrap().resample {
+      return sample_rate;
+    }
+    self
+      .state
+      .lock()
+      .unwrap()
+      .output_rate
+      .unwrap_or(sample_rate)
+  }
+
+  /// The output sample rate: the native rate of the model, or the configured rate in dry-run
+  /// mode.
+  fn sample_rate(&self) -> Result<u64, ErrorMessage> {
+    let (dry_run, dry_run_sample_rate, backend) = {
+      let settings = self.settings.lock().unwrap();
+      (
+        settings.dry_run,
+        settings.dry_run_sample_rate,
+        settings.backend,
+      )
+    };
+    if dry_run {
+      Ok(dry_run_sample_rate as u64)
+    }
+    else {
+      match backend {
+        Backend::Local => self.with_synth(python::output_sample_rate)?.map_err(|e| {
+          e.to_error_message(
+            gstreamer::LibraryError::Failed,
+            "Failed to get the model's sample rate",
+          )
+        }),
+        Backend::Server => self.server_sample_rate(),
+        Backend::Onnx => self.with_onnx_model(OnnxModel::sample_rate),
+      }
+    }
+  }
+
+  /// The models listed in `model-candidates`, or none in dry-run mode or with another backend
+  /// than `local` since they can't be loaded.
+  fn model_candidates(&self) -> Vec<String> {
+    let settings = self.settings.lock().unwrap();
+    if settings.dry_run || settings.backend != Backend::Local {
+      vec![]
+    }
+    else {
+      settings.model_candidates.clone()
+    }
+  }
+
+  /// Fails negotiation if the input is phonemes but the model can't take them.
+  fn check_phoneme_input(&self) -> Result<(), LoggableError> {
+    if !self.state.lock().unwrap().phoneme_input || self.settings.lock().unwrap().dry_run {
+      return Ok(());
+    }
+    if self.settings.lock().unwrap().backend != Backend::Local {
+      return Err(gstreamer::loggable_error!(
+        CAT,
+        "Only the local backend can accept {}",
+        PHONEMES_CAPS_NAME
+      ));
+    }
+    match self.with_synth(supports_phoneme_input) {
+      Ok(true) => Ok(()),
+      Ok(false) => Err(gstreamer::loggable_error!(
+        CAT,
+        "Model {} doesn't use phonemes, so can't accept {}",
+        self.active_model(),
+        PHONEMES_CAPS_NAME
+      )),
+      Err(e) => Err(gstreamer::loggable_error!(
+        CAT,
+        "Failed to load model: {}",
+        e
+      )),
+    }
+  }
+
+  /// Handles the `synthesize` action signal, queueing text as if it had arrived on the sink pad.
+  /// Returns false if the element isn't running, or if the queue is full and not leaky, as the
+  /// application's thread isn't blocked to wait for space.
+  fn synthesize(&self, text: String) -> bool {
+    if self.workers.lock().unwrap().is_empty() {
+      gstreamer::warning!(CAT, "synthesize(): element is not running");
+      return false;
+    }
+    if self.obj().src_pad().current_caps().is_none() {
+      // Nothing has arrived on the sink pad, so the output stream has to be started, but only
+      // the worker pushing the audio can send events downstream in order with it.
+      self.state.lock().unwrap().start_stream = true;
+    }
+    match self.queue_text(Buffer::from_slice(text.into_bytes()), false) {
+      Ok(true) => true,
+      Ok(false) => {
+        gstreamer::warning!(CAT, "synthesize(): queue is full");
+        false
+      },
+      Err(_) => false,
+    }
+  }
+
+  /// Starts the output stream for text from the `synthesize` signal, with caps for audio at
+  /// `sample_rate`, or the model's rate if it isn't known.
+  fn start_stream(&self, sample_rate: Option<u64>) -> Result<(), FlowError> {
+    let sample_rate = match sample_rate.map(Ok).unwrap_or_else(|| self.sample_rate()) {
+      Ok(sample_rate) => sample_rate,
+      Err(e) => {
+        self.post_error_message(e);
+        return Err(FlowError::NotNegotiated);
+      },
+    };
+    gstreamer::debug!(
+      CAT,
+      "start_stream(): starting stream with sample rate {}",
+      sample_rate
+    );
+    let src_pad = self.obj().src_pad().clone();
+    let stream_id = src_pad.create_stream_id(&*self.obj(), Some("synthesize"));
+    let caps = src_caps_builder()
+      .format(AUDIO_FORMAT_F32)
+      .layout(AudioLayout::Interleaved)
+      .rate(sample_rate as i32)
+      .build();
+    let segment = gstreamer::FormattedSegment::<ClockTime>::new();
+    src_pad.push_event(gstreamer::event::StreamStart::new(&stream_id));
+    // As in renegotiate(), the base class sends the caps.
+    if let Err(e) = self.obj().update_src_caps(&caps) {
+      gstreamer::debug!(CAT, "start_stream(): {}", e);
+      return Err(FlowError::NotNegotiated);
+    }
+    self.state.lock().unwrap().output_rate = Some(sample_rate);
+    src_pad.push_event(gstreamer::event::Segment::new(&segment));
+    Ok(())
+  }
+
+  /// Adds a text buffer to the queue, applying the `max-queue-buffers` and `leaky` policy.
+  fn enqueue(&self, buffer: Buffer) -> Result<FlowSuccess, FlowError> {
+    self.queue_text(buffer, true).map(|_| FlowSuccess::Ok)
+  }
+
+  /// Adds a text buffer to the queue like `enqueue()`. When the queue is full and not leaky, it
+  /// waits for space if `wait` is set, or else returns false without queueing the buffer.
+  fn queue_text(&self, mut buffer: Buffer, wait: bool) -> Result<bool, FlowError> {
+    let mut queue = self.queue.lock().unwrap();
+    let (full, leaky) = loop {
+      if queue.flushing {
+        return Err(FlowError::Flushing);
+      }
+      if let Some(e) = queue.flow_error {
+        return Err(e);
+      }
+      let (max_queue_buffers, leaky) = {
+        let settings = self.settings.lock().unwrap();
+        (settings.max_queue_buffers as usize, settings.leaky)
+      };
+      let full = max_queue_buffers > 0 && queue.buffers.len() >= max_queue_buffers;
+      if !full || leaky != Leaky::None {
+        break (full, leaky);
+      }
+      if !wait {
+        return Ok(false);
+      }
+      queue = self.queue_cond.wait(queue).unwrap();
+    };
+    // The text is accepted now, even if it's then dropped, so it's numbered. Text refused above
+    // isn't, so that it doesn't leave a gap in the offsets.
+    if buffer.offset() == gstreamer::ffi::GST_BUFFER_OFFSET_NONE {
+      buffer.make_mut().set_offset(queue.input_count);
+    }
+    queue.input_count += 1;
+    if self.is_duplicate(&buffer) {
+      return Ok(true);
+    }
+    let mut dropped = None;
+    if full {
+      queue.dropped += 1;
+      if leaky == Leaky::Upstream {
+        let (processed, dropped) = (queue.processed, queue.dropped);
+        drop(queue);
+        self.post_dropped(&buffer, 0, processed, dropped);
+        return Ok(true);
+      }
+      dropped = queue.buffers.pop_front();
+    }
+    queue.buffers.push_back(buffer);
+    let (processed, dropped_count) = (queue.processed, queue.dropped);
+    self.queue_cond.notify_all();
+    drop(queue);
+    if let Some(dropped) = dropped {
+      self.post_dropped(&dropped, 0, processed, dropped_count);
+    }
+    Ok(true)
+  }
+
+  /// Returns whether a text buffer repeats the last text within `dedupe-window`, remembering it
+  /// as the last text otherwise.
+  fn is_duplicate(&self, buffer: &Buffer) -> bool {
+    let window = self.settings.lock().unwrap().dedupe_window;
+    if window == 0 {
+      return false;
+    }
+    let text = match text_bytes(buffer) {
+      Some(bytes) => String::from_utf8_lossy(&bytes).trim().to_owned(),
+      None => return false,
+    };
+    let now = Instant::now();
+    let mut state = self.state.lock().unwrap();
+    if let Some((last_text, arrived)) = &state.last_text {
+      if *last_text == text && now.duration_since(*arrived) < Duration::from_nanos(window) {
+        gstreamer::debug!(
+          CAT,
+          "is_duplicate(): skipping {:?}, a repeat after {:?}",
+          text,
+          now.duration_since(*arrived)
+        );
+        return true;
+      }
+    }
+    state.last_text = Some((text, now));
+    false
+  }
+
+  /// Splits `text/csv` input into rows, queueing each as a text buffer of its own. A row at the
+  /// end of the buffer without a newline waits for the rest of it in the next buffer.
+  fn enqueue_rows(&self, buffer: &Buffer) -> Result<FlowSuccess, FlowError> {
+    let bytes = text_bytes(buffer).ok_or(FlowError::Error)?;
+    let rows = {
+      let mut state = self.state.lock().unwrap();
+      state.csv_remainder.extend_from_slice(&bytes);
+      match state.csv_remainder.iter().rposition(|byte| *byte == b'\n') {
+        Some(end) => {
+          let remainder = state.csv_remainder.split_off(end + 1);
+          std::mem::replace(&mut state.csv_remainder, remainder)
+        },
+        None => return Ok(FlowSuccess::Ok),
+      }
+    };
+    self.enqueue_lines(&rows)
+  }
+
+  /// Queues what's left of `text/csv` input at EOS as the last row.
+  fn enqueue_last_row(&self) -> Result<FlowSuccess, FlowError> {
+    let rows = std::mem::take(&mut self.state.lock().unwrap().csv_remainder);
+    self.enqueue_lines(&rows)
+  }
+
+  fn enqueue_lines(&self, lines: &[u8]) -> Result<FlowSuccess, FlowError> {
+    for line in lines.split(|byte| *byte == b'\n') {
+      let line = line.strip_suffix(b"\r").unwrap_or(line);
+      if !line.iter().all(u8::is_ascii_whitespace) {
+        self.enqueue(Buffer::from_slice(line.to_vec()))?;
+      }
+    }
+    Ok(FlowSuccess::Ok)
+  }
+
+  /// Adds a piece of a partial message to the text gathered so far, and queues the sentences that
+  /// are complete. The last sentence is held back, as the next piece could still continue it.
+  fn enqueue_partial(&self, buffer: &Buffer) -> Result<FlowSuccess, FlowError> {
+    let bytes = text_bytes(buffer).ok_or(FlowError::Error)?;
+    let (sentences, pts, id) = {
+      let mut state = self.state.lock().unwrap();
+      if state.partial_text.is_empty() {
+        state.partial_pts = buffer.pts();
+      }
+      if let Some(id) = request_id(buffer) {
+        state.partial_id = Some(id);
+      }
+      state.partial_text.extend_from_slice(&bytes);
+      // A piece can end part way through a character.
+      let text = match str::from_utf8(&state.partial_text) {
+        Ok(text) => text,
+        Err(e) => str::from_utf8(&state.partial_text[..e.valid_up_to()]).unwrap(),
+      };
+      let sentences = segment::sentences(text);
+      let complete = match sentences.len().checked_sub(1) {
+        Some(complete) if complete > 0 => complete,
+        _ => return Ok(FlowSuccess::Ok),
+      };
+      let last = sentences[complete - 1];
+      let end = last.as_ptr() as usize - text.as_ptr() as usize + last.len();
+      let sentences = sentences[..complete]
+        .iter()
+        .map(|sentence| sentence.to_string())
+        .collect::<Vec<_>>();
+      let next = state.partial_text[end..]
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .map_or(state.partial_text.len(), |start| end + start);
+      state.partial_text.drain(..next);
+      let pts = state.partial_pts;
+      state.partial_pts = buffer.pts();
+      (sentences, pts, state.partial_id.clone())
+    };
+    gstreamer::debug!(
+      CAT,
+      "enqueue_partial(): {} complete sentences",
+      sentences.len()
+    );
+    for (i, sentence) in sentences.into_iter().enumerate() {
+      let mut sentence = Buffer::from_slice(sentence.into_bytes());
+      {
+        let sentence = sentence.get_mut().unwrap();
+        if i == 0 {
+          sentence.set_pts(pts);
+        }
+        set_request_id(sentence, id.as_deref());
+      }
+      self.enqueue(sentence)?;
+    }
+    Ok(FlowSuccess::Ok)
+  }
+
+  /// Queues what's left of a partial message once it has ended, together with the buffer that
+  /// ended it if there is one.
+  fn enqueue_partial_remainder(&self, buffer: Option<Buffer>) -> Result<FlowSuccess, FlowError> {
+    let (remainder, pts, id) = {
+      let mut state = self.state.lock().unwrap();
+      (
+        std::mem::take(&mut state.partial_text),
+        state.partial_pts.take(),
+        state.partial_id.take(),
+      )
+    };
+    if remainder.iter().all(u8::is_ascii_whitespace) {
+      return match buffer {
+        Some(buffer) => self.enqueue(buffer),
+        None => Ok(FlowSuccess::Ok),
+      };
+    }
+    let mut text = remainder;
+    if let Some(buffer) = &buffer {
+      text.extend_from_slice(&text_bytes(buffer).ok_or(FlowError::Error)?);
+    }
+    let pts = pts.or_else(|| buffer.as_ref().and_then(|buffer| buffer.pts()));
+    let id = id.or_else(|| buffer.as_deref().and_then(request_id));
+    let mut message = Buffer::from_slice(text);
+    {
+      let message = message.get_mut().unwrap();
+      message.set_pts(pts);
+      set_request_id(message, id.as_deref());
+    }
+    self.enqueue(message)
+  }
+
+  /// Synthesises `initial-text` once, as if it had come from upstream. Without upstream, the
+  /// stream is started for it and ended after it. With upstream, it waits for upstream's stream.
+  fn push_initial_text(&self, standalone: bool) {
+    let text = match self.settings.lock().unwrap().initial_text.clone() {
+      Some(text) => text,
+      None => return,
+    };
+    {
+      let mut state = self.state.lock().unwrap();
+      if state.initial_text_pushed {
+        return;
+      }
+      state.initial_text_pushed = true;
+    }
+    if !standalone {
+      // Text can't go downstream until upstream has started its stream, so it waits in the
+      // meantime and is queued ahead of the text that follows.
+      let text = {
+        let mut state = self.state.lock().unwrap();
+        if !state.upstream_segment {
+          gstreamer::debug!(CAT, "push_initial_text(): waiting for upstream's segment");
+          state.pending_initial_text = Some(text);
+          return;
+        }
+        text
+      };
+      let element = self.obj().clone();
+      let spawned = thread::Builder::new()
+        .name("coquitts-initial-text".into())
+        .spawn(move || element.imp().enqueue_initial_text(text));
+      if let Err(e) = spawned {
+        self.state.lock().unwrap().initial_text_pushed = false;
+        gstreamer::element_imp_warning!(
+          self,
+          gstreamer::ResourceError::Failed,
+          ("Failed to synthesise the initial text"),
+          ["Failed to start thread: {}", e]
+        );
+      }
+      return;
+    }
+    let element = self.obj().clone();
+    let spawned = thread::Builder::new()
+      .name("coquitts-initial-text".into())
+      .spawn(move || {
+        let sink_pad = element.sink_pad();
+        let stream_id = format!("{}/initial-text", element.name());
+        sink_pad.send_event(gstreamer::event::StreamStart::new(&stream_id));
+        let caps = Caps::builder("text/x-raw").field("format", "utf8").build();
+        sink_pad.send_event(gstreamer::event::Caps::new(&caps));
+        let segment = gstreamer::FormattedSegment::<ClockTime>::new();
+        sink_pad.send_event(gstreamer::event::Segment::new(&segment));
+        let result = sink_pad.chain(Buffer::from_slice(text.into_bytes()));
+        gstreamer::debug!(CAT, "push_initial_text(): {:?}", result);
+        match result {
+          Ok(_) => {
+            sink_pad.send_event(gstreamer::event::Eos::new());
+          },
+          Err(e) => element.imp().initial_text_failed(e),
+        }
+      });
+    if let Err(e) = spawned {
+      self.state.lock().unwrap().initial_text_pushed = false;
+      gstreamer::element_imp_warning!(
+        self,
+        gstreamer::ResourceError::Failed,
+        ("Failed to synthesise the initial text"),
+        ["Failed to start thread: {}", e]
+      );
+    }
+  }
+
+  /// Queues `initial-text` once upstream has started its stream.
+  fn enqueue_initial_text(&self, text: String) {
+    let result = self.enqueue(Buffer::from_slice(text.into_bytes()));
+    gstreamer::debug!(CAT, "enqueue_initial_text(): {:?}", result);
+    if let Err(e) = result {
+      self.initial_text_failed(e);
+    }
+  }
+
+  /// Warns that `initial-text` couldn't be synthesised, leaving it to be tried again the next
+  /// time the element starts playing.
+  fn initial_text_failed(&self, e: FlowError) {
+    self.state.lock().unwrap().initial_text_pushed = false;
+    gstreamer::element_imp_warning!(
+      self,
+      gstreamer::StreamError::Failed,
+      ("Failed to synthesise the initial text"),
+      ["{:?}", e]
+    );
+  }
+
+  /// For `gapless` mode, timestamps a text buffer with where the audio pushed so far ends, or the
+  /// start of the segment.
+  fn stamp_gapless(&self, mut buffer: Buffer) -> Buffer {
+    let pts = self.state.lock().unwrap().output_position.or_else(|| {
+      let segment = self.obj().segment();
+      segment
+        .downcast_ref::<ClockTime>()
+        .and_then(|segment| segment.start())
+    });
+    gstreamer::trace!(CAT, "stamp_gapless(): timestamping at {}", pts.display());
+    buffer.make_mut().set_pts(pts);
+    buffer
+  }
+
+  /// For `live-scheduling`, timestamps a text buffer with the running time it arrived at, so that
+  /// sinks play its speech the reported latency later, however upstream timestamped it.
+  fn schedule_live(&self, mut buffer: Buffer) -> Buffer {
+    if !self.settings.lock().unwrap().live_scheduling {
+      return buffer;
+    }
+    let obj = self.obj();
+    let running_time = match (obj.clock(), obj.base_time()) {
+      (Some(clock), Some(base_time)) => clock.time().and_then(|now| now.checked_sub(base_time)),
+      _ => None,
+    };
+    let segment = obj.segment();
+    let pts = segment
+      .downcast_ref::<ClockTime>()
+      .zip(running_time)
+      .and_then(|(segment, running_time)| segment.position_from_running_time(running_time));
+    match pts {
+      Some(pts) => {
+        gstreamer::debug!(
+          CAT,
+          "schedule_live(): text arrived at running time {}, timestamping at {} instead of {}",
+          running_time.display(),
+          pts,
+          buffer.pts().display()
+        );
+        buffer.make_mut().set_pts(pts);
+      },
+      None => gstreamer::debug!(CAT, "schedule_live(): no clock, keeping the timestamp"),
+    }
+    buffer
+  }
+
+  /// Returns how far behind downstream a text buffer is, if that's more than `max-lateness`.
+  fn lateness(&self, buffer: &Buffer) -> Option<i64> {
+    let max_lateness = self.settings.lock().unwrap().max_lateness;
+    if max_lateness < 0 {
+      return None;
+    }
+    let earliest_time = self.state.lock().unwrap().qos_earliest_time?;
+    let segment = self.obj().segment();
+    let running_time = segment
+      .downcast_ref::<ClockTime>()?
+      .to_running_time(buffer.pts())?;
+    let lateness = earliest_time.nseconds() as i64 - running_time.nseconds() as i64;
+    (lateness > max_lateness).then_some(lateness)
+  }
+
+  /// Posts a QoS message for a text buffer that was dropped, either because the queue was full or
+  /// because it was too late.
+  fn post_dropped(&self, buffer: &Buffer, jitter: i64, processed: u64, dropped: u64) {
+    gstreamer::debug!(
+      CAT,
+      "post_dropped(): dropping text buffer with pts {:?}",
+      buffer.pts()
+    );
+    let segment = self.obj().segment();
+    let segment = segment.downcast_ref::<ClockTime>();
+    let message = gstreamer::message::Qos::builder(false)
+      .running_time(segment.and_then(|segment| segment.to_running_time(buffer.pts())))
+      .stream_time(segment.and_then(|segment| segment.to_stream_time(buffer.pts())))
+      .timestamp(buffer.pts())
+      .duration(buffer.duration())
+      .values(jitter, 1.0, 1_000_000)
+      .stats(
+        gstreamer::format::Buffers::from_u64(processed),
+        gstreamer::format::Buffers::from_u64(dropped),
+      )
+      .src(&*self.obj())
+      .build();
+    let _ = self.obj().post_message(message);
+  }
+
+  /// Applies `max-chars-per-minute` to a text buffer, waiting until it's within the limit with
+  /// the `delay` policy, and returning whether to synthesise it. A warning is posted when
+  /// throttling starts, but not again until text has gone through without being held up.
+  fn throttle(&self, buffer: &Buffer, epoch: u64) -> bool {
+    let (per_minute, policy) = {
+      let settings = self.settings.lock().unwrap();
+      (settings.max_chars_per_minute, settings.throttle_policy)
+    };
+    if per_minute == 0 {
+      return true;
+    }
+    let chars = match text_bytes(buffer) {
+      Some(bytes) => String::from_utf8_lossy(&bytes).chars().count(),
+      None => return true,
+    };
+    let mut throttled = false;
+    loop {
+      let wait = self
+        .throttle
+        .lock()
+        .unwrap()
+        .acquire(chars, per_minute, Instant::now());
+      let wait = match wait {
+        Some(wait) => wait,
+        None => break,
+      };
+      if !throttled {
+        throttled = true;
+        if !std::mem::replace(&mut self.throttle.lock().unwrap().engaged, true) {
+          gstreamer::element_imp_warning!(
+            self,
+            gstreamer::ResourceError::Busy,
+            (
+              "Throttling synthesis to {} characters per minute",
+              per_minute
+            ),
+            ["throttle-policy is {:?}", policy]
+          );
+        }
+      }
+      if policy == ThrottlePolicy::Drop {
+        return false;
+      }
+      gstreamer::debug!(
+        CAT,
+        "throttle(): waiting {:?} for {} characters",
+        wait,
+        chars
+      );
+      // Flushing or stopping ends the wait, and the text that was waiting is discarded.
+      let queue = self.queue.lock().unwrap();
+      if queue.shutdown || queue.epoch != epoch {
+        return false;
+      }
+      let (queue, _) = self.queue_cond.wait_timeout(queue, wait).unwrap();
+      if queue.shutdown || queue.epoch != epoch {
+        return false;
+      }
+    }
+    if !throttled {
+      self.throttle.lock().unwrap().engaged = false;
+    }
+    true
+  }
+
+  /// Applies `max-chars` to text, returning the text to synthesise or `None` if it's rejected.
+  fn limit_length(&self, mut text: String) -> Option<String> {
+    let (max_chars, policy) = {
+      let settings = self.settings.lock().unwrap();
+      (settings.max_chars, settings.long_text_policy)
+    };
+    if max_chars == 0 {
+      return Some(text);
+    }
+    let end = match text.char_indices().nth(max_chars as usize) {
+      Some((end, _)) => end,
+      None => return Some(text),
+    };
+    let chars = text.chars().count();
+    match policy {
+      LongTextPolicy::Truncate => {
+        gstreamer::element_imp_warning!(
+          self,
+          gstreamer::StreamError::Format,
+          ("Truncating text of {} characters to {}", chars, max_chars)
+        );
+        text.truncate(end);
+        Some(text)
+      },
+      LongTextPolicy::Reject => {
+        gstreamer::element_imp_warning!(
+          self,
+          gstreamer::StreamError::Format,
+          (
+            "Skipping text of {} characters, more than {}",
+            chars,
+            max_chars
+          )
+        );
+        None
+      },
+    }
+  }
+
+  /// Synthesises a text buffer, returning one audio buffer per sentence when splitting sentences.
+  fn process(&self, buffer: &Buffer, part: Part) -> Result<Processed, FlowError> {
+    let bytes = text_bytes(buffer).ok_or(FlowError::Error)?;
+    let text = str::from_utf8(&bytes).map_err(|_| FlowError::Error)?;
+    let (json_input, csv_input, phoneme_input) = {
+      let state = self.state.lock().unwrap();
+      (state.json_input, state.csv_input, state.phoneme_input)
+    };
+    let normalize_unicode = self.settings.lock().unwrap().normalize_unicode;
+    let text = if normalize_unicode {
+      normalize::strip_byte_order_mark(text)
+    }
+    else {
+      text
+    };
+    let mut message = if csv_input {
+      match Message::row(text) {
+        Ok(message) => message,
+        Err(e) => {
+          gstreamer::element_imp_warning!(
+            self,
+            gstreamer::StreamError::Decode,
+            ("Skipping invalid CSV row"),
+            ["{}", e]
+          );
+          return Ok(Processed::Failed);
+        },
+      }
+    }
+    else if json_input {
+      match Message::parse(text) {
+        Ok(message) => message,
+        Err(e) => {
+          gstreamer::element_imp_warning!(
+            self,
+            gstreamer::StreamError::Decode,
+            ("Skipping invalid JSON message"),
+            ["{}", e]
+          );
+          return Ok(Processed::Failed);
+        },
+      }
+    }
+    else {
+      Message::text(text)
+    };
+    message.id = message.id.or_else(|| request_id(buffer));
+    // Phonemes are left alone, as composing them could turn them into symbols the model lacks.
+    if normalize_unicode && !phoneme_input {
+      message.text = normalize::normalize(&message.text);
+    }
+    let ssml_marks = if self.settings.lock().unwrap().ssml {
+      let document = ssml::parse(&message.text);
+      message.text = document.text;
+      document.marks
+    }
+    else {
+      vec![]
+    };
+    if !phoneme_input && self.settings.lock().unwrap().strip_stage_directions {
+      message.text = directions::strip(&message.text);
+    }
+    if !phoneme_input {
+      let label = self
+        .settings
+        .lock()
+        .unwrap()
+        .speaker_map
+        .apply(&message.text)
+        .map(|(speaker, text)| (speaker.to_owned(), text.to_owned()));
+      if let Some((speaker, text)) = label {
+        gstreamer::debug!(CAT, "process(): speaker label for {}", speaker);
+        // A speaker given in a JSON message still takes precedence.
+        message.speaker = message.speaker.or(Some(speaker));
+        message.text = text;
+      }
+    }
+    message.text = match self.limit_length(message.text) {
+      Some(text) => text,
+      None => return Ok(Processed::Skipped),
+    };
+    let text = message.text.as_str();
+    if text.trim().is_empty() {
+      let (policy, gap_ms) = {
+        let settings = self.settings.lock().unwrap();
+        (settings.empty_text_policy, settings.empty_text_gap_ms)
+      };
+      match policy {
+        EmptyTextPolicy::Synthesize => (),
+        EmptyTextPolicy::Skip => {
+          gstreamer::debug!(CAT, "process(): skipping empty text");
+          return Ok(Processed::Skipped);
+        },
+        EmptyTextPolicy::Gap => {
+          if let Some(pts) = buffer.pts() {
+            let duration = buffer
+              .duration()
+              .unwrap_or_else(|| ClockTime::from_mseconds(gap_ms as u64));
+            gstreamer::debug!(
+              CAT,
+              "process(): empty text, pushing gap at {} for {}",
+              pts,
+              duration
+            );
+            return Ok(Processed::Synthesised(vec![Output::Event(
+              gstreamer::event::Gap::builder(pts)
+                .duration(duration)
+                .build(),
+            )]));
+          }
+          return Ok(Processed::Skipped);
+        },
+      }
+    }
+    let (language, segments) = if phoneme_input {
+      let language = message
+        .language
+        .clone()
+        .or_else(|| self.settings.lock().unwrap().language.clone())
+        .or_else(|| self.state.lock().unwrap().caps_language.clone())
+        .or_else(|| self.state.lock().unwrap().tag_language.clone());
+      (
+        language.filter(|language| language != AUTO_LANGUAGE),
+        vec![Segment::Text(text.to_owned())],
+      )
+    }
+    else {
+      profile::time(Stage::Preprocess, || {
+        let language = match message.language.clone() {
+          Some(language) if language != AUTO_LANGUAGE => Some(language),
+          _ => self.resolve_language(text),
+        };
+        // Tone directives are split out first so that preprocessing leaves them alone.
+        let segments = tone::split(text)
+          .into_iter()
+          .map(|segment| match segment {
+            Segment::Text(text) => {
+              Segment::Text(self.preprocess_text(&text, language.as_deref()))
+            },
+            tone => tone,
+          })
+          .collect::<Vec<_>>();
+        (language, segments)
+      })
+    };
+    let mapped_voice = language.as_deref().and_then(|language| {
+      self
+        .settings
+        .lock()
+        .unwrap()
+        .voice_map
+        .get(language)
+        .cloned()
+    });
+    if let Some(mapped_voice) = &mapped_voice {
+      gstreamer::debug!(
+        CAT,
+        "process(): voice for {:?}: {:?}",
+        language,
+        mapped_voice
+      );
+    }
+    let backend = self.settings.lock().unwrap().backend;
+    let _mapped_model = MappedModel::select(
+      mapped_voice
+        .as_ref()
+        .and_then(|voice| voice.model.clone())
+        .filter(|_| backend == Backend::Local),
+    );
+    let speaker = message
+      .speaker
+      .clone()
+      .or_else(|| mapped_voice.and_then(|voice| voice.speaker))
+      .or_else(|| self.settings.lock().unwrap().speaker.clone())
+      .or_else(|| self.state.lock().unwrap().caps_speaker.clone())
+      .or_else(|| self.indexed_speaker());
+    let voice = Voice {
+      language: language.as_deref(),
+      speaker: speaker.as_deref(),
+    };
+    gstreamer::debug!(CAT, "process(): synthesising: {:?}", segments);
+    let (fit_duration, padding_start_ms, padding_end_ms, dry_run, pitch_semitones, gain_db) = {
+      let settings = self.settings.lock().unwrap();
+      (
+        settings.fit_duration,
+        settings.silence_padding_start_ms,
+        settings.silence_padding_end_ms,
+        settings.dry_run,
+        settings.pitch_semitones,
+        settings.gain_db,
+      )
+    };
+    let padding_start_ms = if part == Part::Rest {
+      0
+    }
+    else {
+      padding_start_ms
+    };
+    let padding_end_ms = if part == Part::FirstClause {
+      0
+    }
+    else {
+      padding_end_ms
+    };
+    // Only speech on its own is fitted to the text's duration, as tones have a fixed length.
+    let fit_to = buffer
+      .duration()
+      .filter(|_| fit_duration && segments.len() == 1);
+    let started = Instant::now();
+    let mut sentences = vec![];
+    // Whether each sentence is a tone, which mustn't be pitch-shifted.
+    let mut tones = vec![];
+    let mut sample_rate = 0;
+    // Whether text that should have had speech got none.
+    let mut failed = false;
+    for segment in &segments {
+      let maybe_audio = match segment {
+        Segment::Text(text) => self.synthesise_text(text, voice, &message, fit_to, dry_run),
+        Segment::Tone(tone) => self
+          .sample_rate()
+          .map(|sample_rate| Some((vec![tone.render(sample_rate)], sample_rate))),
+      };
+      let maybe_audio = maybe_audio.map_err(|e| {
+        self.post_error_message(e);
+        FlowError::Error
+      })?;
+      let (audio, rate) = match maybe_audio {
+        Some(audio) => audio,
+        None => return Ok(Processed::Failed),
+      };
+      // Failed inferences can return no samples, for some sentences or all of them.
+      let audio: Vec<Vec<f32>> = audio
+        .into_iter()
+        .filter(|sentence| !sentence.is_empty())
+        .collect();
+      if audio.is_empty() {
+        if let Segment::Text(text) = segment {
+          self.empty_output(text)?;
+          failed |= !text.trim().is_empty();
+        }
+        continue;
+      }
+      tones.extend(std::iter::repeat_n(
+        matches!(segment, Segment::Tone(_)),
+        audio.len(),
+      ));
+      sentences.extend(audio);
+      sample_rate = rate;
+    }
+    if !dry_run {
+      self.record_latency(started.elapsed());
+    }
+    if sentences.is_empty() {
+      return Ok(if failed {
+        Processed::Failed
+      }
+      else {
+        Processed::Skipped
+      });
+    }
+    profile::time(Stage::Dsp, || {
+      for (sentence, tone) in sentences.iter_mut().zip(&tones) {
+        if !tone {
+          *sentence = dsp::pitch_shift(sentence, pitch_semitones);
+        }
+        dsp::apply_gain(sentence, gain_db);
+      }
+      if let Some(target) = target_duration(buffer) {
+        let padding = ClockTime::from_mseconds(padding_start_ms as u64 + padding_end_ms as u64);
+        let target_samples =
+          (target.saturating_sub(padding).nseconds() * sample_rate / 1_000_000_000) as usize;
+        let samples: usize = sentences.iter().map(Vec::len).sum();
+        gstreamer::debug!(
+          CAT,
+          "process(): time-stretching {} samples to {} for duration {}",
+          samples,
+          target_samples,
+          target
+        );
+        // Stretch every sentence by the same ratio, with the last one taking up the rounding.
+        let mut remaining = target_samples;
+        let count = sentences.len();
+        for (i, sentence) in sentences.iter_mut().enumerate() {
+          let len = if i + 1 == count {
+            remaining
+          }
+          else {
+            (sentence.len() * target_samples / samples.max(1)).min(remaining)
+          };
+          *sentence = dsp::time_stretch(sentence, len, sample_rate);
+          remaining -= len;
+        }
+      }
+    });
+    if let Some(first) = sentences.first_mut() {
+      let padding = ms_to_samples(padding_start_ms, sample_rate);
+      first.splice(0..0, std::iter::repeat_n(0.0, padding));
+    }
+    if let Some(last) = sentences.last_mut() {
+      let padding = ms_to_samples(padding_end_ms, sample_rate);
+      last.resize(last.len() + padding, 0.0);
+    }
+    // Mixed in after the padding, so that the ambience carries on through it.
+    self.mix_room_tone(&mut sentences, sample_rate);
+    let watermark_key = {
+      let settings = self.settings.lock().unwrap();
+      settings.watermark.then_some(settings.watermark_key)
+    };
+    if let Some(key) = watermark_key {
+      profile::time(Stage::Dsp, || {
+        for sentence in &mut sentences {
+          dsp::watermark(sentence, sample_rate, key);
+        }
+      });
+    }
+    let output_rate = self.output_rate(sample_rate);
+    if output_rate != sample_rate {
+      gstreamer::debug!(
+        CAT,
+        "process(): resampling from {} to {}",
+        sample_rate,
+        output_rate
+      );
+      profile::time(Stage::Dsp, || {
+        for sentence in &mut sentences {
+          *sentence = dsp::resample_linear(sentence, sample_rate as u32, output_rate as u32);
         }
-        if settings.speaker.is_none()
-          && synth
-            .getattr("is_multi_speaker")
-            .unwrap()
-            .downcast::<PyBool>()
-            .unwrap()
-            .is_true()
+      });
+      sample_rate = output_rate;
+    }
+    let (block_samples, pad_last_block) = {
+      let settings = self.settings.lock().unwrap();
+      (settings.output_block_samples, settings.pad_last_block)
+    };
+    let sentences = if block_samples > 0 {
+      into_blocks(sentences, block_samples as usize, pad_last_block)
+    }
+    else {
+      sentences
+    };
+    let (gapless, live_scheduling) = {
+      let settings = self.settings.lock().unwrap();
+      (settings.gapless, settings.live_scheduling)
+    };
+    // Gapless text is already timestamped where the audio before it ends, and its buffers' offsets
+    // count samples.
+    let offset = gapless.then(|| self.state.lock().unwrap().gapless_offset);
+    let mut pts = buffer.pts();
+    if !gapless {
+      // Start early by the leading silence, so that speech lines up with the text's timestamp.
+      pts = pts.map(|pts| pts.saturating_sub(ClockTime::from_mseconds(padding_start_ms as u64)));
+      if live_scheduling {
+        // Speech scheduled by arrival mustn't overlap the speech before it, which the worker has
+        // waited to be pushed.
+        let end = self.state.lock().unwrap().output_position;
+        pts = pts.map(|pts| end.map_or(pts, |end| pts.max(end)));
+      }
+    }
+    let sentences = self.apply_segment_stop_policy(sentences, pts, sample_rate)?;
+    let (allocation, audio_meta, f64_output, s16_output, non_interleaved) = {
+      let state = self.state.lock().unwrap();
+      (
+        state.allocation.clone(),
+        state.audio_meta,
+        state.f64_output,
+        state.s16_output,
+        state.non_interleaved,
+      )
+    };
+    let dither = self.settings.lock().unwrap().dither;
+    let format = if f64_output {
+      AUDIO_FORMAT_F64
+    }
+    else if s16_output {
+      AUDIO_FORMAT_S16
+    }
+    else {
+      AUDIO_FORMAT_F32
+    };
+    let layout = if non_interleaved {
+      AudioLayout::NonInterleaved
+    }
+    else {
+      AudioLayout::Interleaved
+    };
+    let source = self.source(&message, voice);
+    let info = AudioInfo::builder(format, sample_rate as u32, 1)
+      .layout(layout)
+      .build()
+      .map_err(|_| FlowError::NotNegotiated)?;
+    // Timestamps are worked out from the number of samples before each buffer, so that rounding
+    // doesn't add up over an utterance's buffers.
+    let mut position = 0;
+    sentences
+      .into_iter()
+      .map(|audio| {
+        let samples = audio.len();
+        let start = position;
+        position += samples as u64;
+        let begin = ClockTime::from_nseconds(start * 1_000_000_000 / sample_rate);
+        let duration = ClockTime::from_nseconds(position * 1_000_000_000 / sample_rate) - begin;
+        let mut output = profile::time(Stage::BufferCopy, || {
+          if f64_output {
+            let audio: Vec<f64> = audio.into_iter().map(f64::from).collect();
+            audio_buffer(audio, allocation.as_ref())
+          }
+          else if s16_output {
+            let audio = {
+              let mut state = self.state.lock().unwrap();
+              dsp::to_s16(&audio, dither, &mut state.dither_state)
+            };
+            audio_buffer(audio, allocation.as_ref())
+          }
+          else {
+            audio_buffer(audio, allocation.as_ref())
+          }
+        })?;
         {
-          panic!("This model is multi-speaker and requires specifying the `speaker` property");
+          let output = output.get_mut().unwrap();
+          output.set_pts(pts.map(|pts| pts + begin));
+          output.set_duration(duration);
+          if let Some(offset) = offset {
+            output.set_offset(offset + start);
+            output.set_offset_end(offset + position);
+          }
+          // Non-interleaved audio has to say where each channel's samples are.
+          if audio_meta || non_interleaved {
+            AudioMeta::add(output, &info, samples, &[]).map_err(|_| FlowError::Error)?;
+          }
+          if let Some(id) = message.id.as_ref().filter(|_| csv_input) {
+            let mut meta = gstreamer::meta::CustomMeta::add(output, ROW_META_NAME)
+              .map_err(|_| FlowError::Error)?;
+            meta.mut_structure().set("id", id);
+          }
+          let mut meta = gstreamer::meta::CustomMeta::add(output, SOURCE_META_NAME)
+            .map_err(|_| FlowError::Error)?;
+          for (name, value) in source.iter() {
+            meta.mut_structure().set_value(name, value.clone());
+          }
+        }
+        Ok(output)
+      })
+      .collect::<Result<Vec<_>, _>>()
+      .map(|buffers| {
+        if let Some(offset) = offset {
+          self.state.lock().unwrap().gapless_offset = offset + position;
+        }
+        let marks = if self.marks_pad.lock().unwrap().is_some() {
+          Some(speech_marks(&message.text, &buffers))
+        }
+        else {
+          None
+        };
+        self.write_sidecar(buffer.offset(), &message.text, voice, &buffers);
+        if let Some(id) = &message.id {
+          self.post_utterance(id, &buffers);
+        }
+        let mut events = mark_events(
+          &ssml_marks,
+          message.text.chars().count(),
+          &buffers,
+          ClockTime::from_mseconds(padding_start_ms as u64),
+          ClockTime::from_mseconds(padding_end_ms as u64),
+        );
+        let mut outputs = vec![Output::Rate(sample_rate)];
+        if self.obj().segment().rate() < 0.0 {
+          // In reverse playback buffers go downstream last first, each marked discontinuous,
+          // and the audio sink plays the samples within each of them backwards, so it reaches
+          // the marks at the start of a buffer after playing it.
+          outputs.extend(events.pop().into_iter().flatten().map(Output::Event));
+          for (mut buffer, events) in buffers.into_iter().zip(events).rev() {
+            buffer.make_mut().set_flags(BufferFlags::DISCONT);
+            outputs.push(Output::Buffer(buffer));
+            outputs.extend(events.into_iter().map(Output::Event));
+          }
         }
+        else {
+          let mut events = events.into_iter();
+          for (buffer, events) in buffers.into_iter().zip(events.by_ref()) {
+            outputs.extend(events.into_iter().map(Output::Event));
+            outputs.push(Output::Buffer(buffer));
+          }
+          outputs.extend(events.flatten().map(Output::Event));
+        }
+        outputs.extend(marks.map(Output::Marks));
+        Processed::Synthesised(outputs)
+      })
+  }
+}
+
+/// The nick of an enum property's value, as it's written in pipeline descriptions.
+fn enum_nick(value: impl ToValue) -> String {
+  glib::EnumValue::from_value(&value.to_value())
+    .map(|(_, value)| value.nick().to_owned())
+    .unwrap_or_default()
+}
+
+/// The sample rate of a Coqui model's audio, from the `config.json` files of the model and its
+/// vocoder, if it has one. The vocoder's comes last, and decides the rate.
+fn config_sample_rate(paths: &[String]) -> Result<u64, String> {
+  let path = paths.last().ok_or("the model has no config")?;
+  let config: serde_json::Value = fs::read_to_string(path)
+    .map_err(|e| e.to_string())
+    .and_then(|config| serde_json::from_str(&config).map_err(|e| e.to_string()))
+    .map_err(|e| format!("failed to read {}: {}", path, e))?;
+  let audio = &config["audio"];
+  audio["output_sample_rate"]
+    .as_u64()
+    .or_else(|| audio["sample_rate"].as_u64())
+    .ok_or_else(|| format!("{} has no sample rate", path))
+}
+
+/// Joins the audio of an utterance's sentences and cuts it into blocks of `len` samples, padding
+/// the last block with silence if `pad` is set.
+fn into_blocks(sentences: Vec<Vec<f32>>, len: usize, pad: bool) -> Vec<Vec<f32>> {
+  let audio = sentences.concat();
+  audio
+    .chunks(len)
+    .map(|block| {
+      let mut block = block.to_vec();
+      if pad {
+        block.resize(len, 0.0);
       }
-      synth.into()
-    });
-    gstreamer::debug!(CAT, "init_synth(): released GIL");
-    result
+      block
+    })
+    .collect()
+}
+
+/// Where the audio among `outputs` ends.
+fn audio_end(outputs: &[Output]) -> Option<ClockTime> {
+  outputs
+    .iter()
+    .filter_map(|output| match output {
+      Output::Buffer(buffer) => buffer
+        .pts()
+        .zip(buffer.duration())
+        .map(|(pts, duration)| pts + duration),
+      _ => None,
+    })
+    .max()
+}
+
+/// Splits text after its first clause, at a comma or the end of a sentence, returning the first
+/// clause and the rest. Returns `None` if there's nothing after the first clause.
+fn split_first_clause(text: &str) -> Option<(&str, &str)> {
+  let mut chars = text.char_indices().peekable();
+  while let Some((i, c)) = chars.next() {
+    // Full-width punctuation isn't followed by a space.
+    let full_width = matches!(c, '，' | '、' | '；' | '：' | '。' | '！' | '？');
+    let ascii = matches!(c, ',' | ';' | ':' | '.' | '!' | '?')
+      && chars.peek().is_some_and(|(_, next)| next.is_whitespace());
+    if full_width || ascii {
+      let (first, rest) = text.split_at(i + c.len_utf8());
+      let rest = rest.trim_start();
+      return (!first.trim().is_empty() && !rest.trim().is_empty()).then_some((first, rest));
+    }
   }
+  None
+}
 
-  fn with_synth<R, F: FnOnce(&PyAny) -> R>(&self, f: F) -> R {
-    gstreamer::debug!(CAT, "with_synth(): locking synth");
-    let mut synth = self.synth.lock().unwrap();
-    if synth.is_none() {
-      gstreamer::debug!(CAT, "with_synth(): no synth, will init");
-      *synth = Some(self.init_synth());
+/// Describes the audio buffers synthesised for a text as a JSON buffer for the `marks` pad.
+fn speech_marks(text: &str, buffers: &[Buffer]) -> Buffer {
+  let start = buffers.first().and_then(|buffer| buffer.pts());
+  let duration: ClockTime = buffers.iter().filter_map(|buffer| buffer.duration()).sum();
+  let marks = utterance_timing(text, buffers);
+  let mut buffer = Buffer::from_mut_slice(marks.to_string().into_bytes());
+  {
+    let buffer = buffer.get_mut().unwrap();
+    buffer.set_pts(start);
+    buffer.set_duration(duration);
+  }
+  buffer
+}
+
+/// Describes the audio buffers synthesised for a text as JSON, with the start and duration of the
+/// utterance and of each sentence in nanoseconds.
+fn utterance_timing(text: &str, buffers: &[Buffer]) -> serde_json::Value {
+  let start = buffers.first().and_then(|buffer| buffer.pts());
+  let duration: ClockTime = buffers.iter().filter_map(|buffer| buffer.duration()).sum();
+  let sentences: Vec<_> = buffers
+    .iter()
+    .map(|buffer| {
+      serde_json::json!({
+        "start": buffer.pts().map(ClockTime::nseconds),
+        "duration": buffer.duration().map(ClockTime::nseconds),
+      })
+    })
+    .collect();
+  serde_json::json!({
+    "text": text,
+    "start": start.map(ClockTime::nseconds),
+    "duration": duration.nseconds(),
+    "sentences": sentences,
+  })
+}
+
+/// Custom downstream events for SSML marks in an utterance, grouped by the buffer they're to be
+/// pushed before, with those at the very end after the last buffer. Where a mark falls in the
+/// audio is estimated from how far through the text it is.
+fn mark_events(
+  marks: &[ssml::Mark],
+  text_chars: usize,
+  buffers: &[Buffer],
+  padding_start: ClockTime,
+  padding_end: ClockTime,
+) -> Vec<Vec<Event>> {
+  let mut events = vec![vec![]; buffers.len() + 1];
+  let start = buffers.first().and_then(|buffer| buffer.pts());
+  let durations: Vec<ClockTime> = buffers
+    .iter()
+    .map(|buffer| buffer.duration().unwrap_or(ClockTime::ZERO))
+    .collect();
+  let speech = durations
+    .iter()
+    .copied()
+    .sum::<ClockTime>()
+    .saturating_sub(padding_start + padding_end);
+  for mark in marks {
+    let fraction = mark.offset.min(text_chars) as f64 / text_chars.max(1) as f64;
+    let position =
+      padding_start + ClockTime::from_nseconds((speech.nseconds() as f64 * fraction) as u64);
+    let mut end = ClockTime::ZERO;
+    let index = durations
+      .iter()
+      .position(|duration| {
+        end += *duration;
+        position < end
+      })
+      .unwrap_or(buffers.len());
+    gstreamer::debug!(
+      CAT,
+      "mark_events(): mark {} at {} into the utterance",
+      mark.name,
+      position
+    );
+    let mut structure = gstreamer::Structure::builder(MARK_EVENT).field("name", &mark.name);
+    if let Some(start) = start {
+      structure = structure.field("timestamp", start + position);
     }
-    gstreamer::debug!(CAT, "with_synth(): acquiring GIL");
-    let result = Python::with_gil(move |py| {
-      let result = f(synth.as_ref().unwrap().as_ref(py));
-      drop(synth);
-      gstreamer::debug!(CAT, "with_synth(): unlocked synth");
-      result
+    events[index].push(gstreamer::event::CustomDownstream::new(structure.build()));
+  }
+  events
+}
+
+/// Describes what a loaded model supports, for the `model-capabilities` property.
+fn model_capabilities(s: &PyAny, model: &str) -> PyResult<gstreamer::Structure> {
+  let synthesizer = s.getattr("synthesizer")?;
+  let has_speaker_encoder = synthesizer
+    .getattr("tts_model")
+    .and_then(|tts_model| tts_model.getattr("speaker_manager"))
+    .and_then(|speaker_manager| speaker_manager.getattr("encoder"))
+    .is_ok_and(|encoder| !encoder.is_none());
+  let languages: Vec<String> = s
+    .getattr("languages")
+    .ok()
+    .filter(|languages| !languages.is_none())
+    .map(|languages| languages.extract())
+    .transpose()?
+    .unwrap_or_default();
+  let speakers = speakers(s)?;
+  Ok(
+    gstreamer::Structure::builder("model-capabilities")
+      .field("model", model)
+      .field(
+        "is-multi-speaker",
+        s.getattr("is_multi_speaker")?.is_true()?,
+      )
+      .field(
+        "is-multi-lingual",
+        s.getattr("is_multi_lingual")?.is_true()?,
+      )
+      .field(
+        "supports-voice-cloning",
+        supports_conditioning_latents(s) || has_speaker_encoder,
+      )
+      .field("supports-phoneme-input", supports_phoneme_input(s))
+      .field(
+        "rate",
+        synthesizer
+          .getattr("output_sample_rate")?
+          .extract::<i32>()?,
+      )
+      .field("languages", gstreamer::Array::new(languages))
+      .field("speakers", gstreamer::Array::new(speakers))
+      .build(),
+  )
+}
+
+/// The names of a model's speakers, in the order `speaker-index` counts them.
+fn speakers(s: &PyAny) -> PyResult<Vec<String>> {
+  Ok(
+    s.getattr("speakers")
+      .ok()
+      .filter(|speakers| !speakers.is_none())
+      .map(|speakers| speakers.extract())
+      .transpose()?
+      .unwrap_or_default(),
+  )
+}
+
+/// Describes a model's speakers for the `speakers-detailed` property.
+///
+/// Coqui models don't carry speaker metadata as such, but some speaker names encode it, as
+/// YourTTS's `female-en-5` does, and single-language models have the language in their name, as in
+/// `tts_models/en/vctk/vits`.
+fn speakers_detailed(model: &str, speakers: &gstreamer::Array) -> gstreamer::Structure {
+  let model_locale = match model.split('/').collect::<Vec<_>>()[..] {
+    ["tts_models", language, ..] if language != "multilingual" => Some(language),
+    _ => None,
+  };
+  let speakers = speakers
+    .iter()
+    .filter_map(|speaker| speaker.get::<&str>().ok())
+    .enumerate()
+    .map(|(index, name)| {
+      let name = name.trim();
+      let parts: Vec<String> = name.split(['-', '_']).map(str::to_lowercase).collect();
+      let gender = parts
+        .iter()
+        .find(|part| matches!(part.as_str(), "female" | "male"));
+      let locale = parts
+        .iter()
+        .skip(1)
+        .find(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_alphabetic()))
+        .map(String::as_str)
+        .filter(|_| gender.is_some())
+        .or(model_locale);
+      let mut speaker = gstreamer::Structure::builder("speaker")
+        .field("name", name)
+        .field("index", index as i32)
+        .build();
+      if let Some(gender) = gender {
+        speaker.set("gender", gender);
+      }
+      if let Some(locale) = locale {
+        speaker.set("locale", locale);
+      }
+      speaker.to_send_value()
     });
-    gstreamer::debug!(CAT, "with_synth(): released GIL");
-    result
+  gstreamer::Structure::builder("speakers-detailed")
+    .field("speakers", gstreamer::Array::from_values(speakers))
+    .build()
+}
+
+fn split_into_sentences(s: &PyAny, text: &str) -> PyResult<Vec<String>> {
+  s.getattr("synthesizer")?
+    .call_method1("split_into_sentences", (text,))?
+    .extract()
+}
+
+/// Synthesises with a precomputed d-vector, bypassing `TTS.tts()` since it can only take speaker
+/// names or reference audio.
+fn synthesise_with_embedding(
+  s: &PyAny,
+  text: &str,
+  language: Option<&str>,
+  embedding: &[f32],
+) -> PyResult<Vec<f32>> {
+  let py = s.py();
+  let synthesizer = s.getattr("synthesizer")?;
+  if !synthesizer.getattr("vocoder_model")?.is_none() {
+    return Err(PyValueError::new_err(
+      "speaker embeddings are only supported by models without a separate vocoder",
+    ));
+  }
+  let tts_model = synthesizer.getattr("tts_model")?;
+  let language_manager = tts_model
+    .getattr("language_manager")
+    .ok()
+    .filter(|language_manager| !language_manager.is_none());
+  let language_id = match (language, language_manager) {
+    (Some(language), Some(language_manager)) => {
+      Some(language_manager.getattr("name_to_id")?.get_item(language)?)
+    },
+    _ => None,
+  };
+  let kwargs = python::Kwargs::new(py)
+    .set("model", tts_model)?
+    .set("text", text)?
+    .set("CONFIG", synthesizer.getattr("tts_config")?)?
+    .set("use_cuda", synthesizer.getattr("use_cuda")?)?
+    .set(
+      "d_vector",
+      PyModule::import(py, "numpy")?.call_method1("array", (embedding.to_vec(), "float32"))?,
+    )?
+    .set_some("language_id", language_id)?;
+  let audio = PyModule::import(py, "TTS.tts.utils.synthesis")?
+    .call_method("synthesis", (), Some(kwargs.into_dict()))?
+    .get_item("wav")?;
+  profile::time(Stage::Conversion, || python::extract_audio(audio))
+}
+
+fn supports_phoneme_input(s: &PyAny) -> bool {
+  s.getattr("synthesizer")
+    .and_then(|synthesizer| synthesizer.getattr("tts_model"))
+    .and_then(|tts_model| tts_model.getattr("tokenizer"))
+    .and_then(|tokenizer| tokenizer.getattr("use_phonemes"))
+    .and_then(|use_phonemes| use_phonemes.extract())
+    .unwrap_or(false)
+}
+
+/// Runs `f` with the model's tokenizer treating its input as phonemes, by turning off text
+/// cleaning and phonemization for the duration of the call.
+fn with_phoneme_input<R>(s: &PyAny, f: impl FnOnce() -> PyResult<R>) -> PyResult<R> {
+  let tokenizer = s
+    .getattr("synthesizer")?
+    .getattr("tts_model")?
+    .getattr("tokenizer")?;
+  let text_cleaner = tokenizer.getattr("text_cleaner")?;
+  tokenizer.setattr("use_phonemes", false)?;
+  tokenizer.setattr("text_cleaner", s.py().None())?;
+  let result = f();
+  tokenizer.setattr("use_phonemes", true)?;
+  tokenizer.setattr("text_cleaner", text_cleaner)?;
+  result
+}
+
+fn supports_conditioning_latents(s: &PyAny) -> bool {
+  s.getattr("synthesizer")
+    .and_then(|synthesizer| synthesizer.getattr("tts_model"))
+    .and_then(|tts_model| tts_model.hasattr("get_conditioning_latents"))
+    .unwrap_or(false)
+}
+
+/// Loads the conditioning latents for `file` from the on-disk cache if present, otherwise
+/// computes them and stores them in the cache.
+fn load_or_compute_latents(
+  tts_model: &PyAny,
+  file: &str,
+  key: &str,
+  cache_dir: Option<&str>,
+) -> PyResult<Py<PyAny>> {
+  let py = tts_model.py();
+  // Named by a hash that's the same for every build, so the cache survives upgrades.
+  let cache_path =
+    cache_dir.map(|dir| Path::new(dir).join(format!("{}.pth", sha256_hex(key.as_bytes()))));
+  if let Some(path) = cache_path.as_ref().filter(|path| path.is_file()) {
+    gstreamer::debug!(
+      CAT,
+      "load_or_compute_latents(): loading latents for {} from {}",
+      file,
+      path.display()
+    );
+    let kwargs =
+      python::Kwargs::new(py).set_some("map_location", tts_model.getattr("device").ok())?;
+    return Ok(
+      PyModule::import(py, "torch")?
+        .call_method(
+          "load",
+          (path.to_string_lossy().as_ref(),),
+          Some(kwargs.into_dict()),
+        )?
+        .into(),
+    );
+  }
+  gstreamer::debug!(
+    CAT,
+    "load_or_compute_latents(): computing latents for {}",
+    file
+  );
+  let kwargs = python::Kwargs::new(py).set("audio_path", vec![file])?;
+  let latents = tts_model.call_method("get_conditioning_latents", (), Some(kwargs.into_dict()))?;
+  if let Some(path) = cache_path {
+    let result = fs::create_dir_all(path.parent().unwrap())
+      .map_err(PyErr::from)
+      .and_then(|_| {
+        PyModule::import(py, "torch")?
+          .call_method1("save", (latents, path.to_string_lossy().as_ref()))
+          .map(|_| ())
+      });
+    if let Err(e) = result {
+      gstreamer::warning!(
+        CAT,
+        "load_or_compute_latents(): failed to cache latents at {}: {}",
+        path.display(),
+        e
+      );
+    }
+  }
+  Ok(latents.into())
+}
+
+/// The exact duration a text buffer's audio has to have, from its `CoquittsDurationMeta`.
+fn target_duration(buffer: &Buffer) -> Option<ClockTime> {
+  gstreamer::meta::CustomMeta::from_buffer(buffer, DURATION_META_NAME)
+    .ok()
+    .and_then(|meta| meta.structure().get::<u64>("duration").ok())
+    .map(ClockTime::from_nseconds)
+}
+
+/// The `id` of a text buffer's `CoquittsRequestMeta`.
+fn request_id(buffer: &BufferRef) -> Option<String> {
+  gstreamer::meta::CustomMeta::from_buffer(buffer, REQUEST_META_NAME)
+    .ok()
+    .and_then(|meta| meta.structure().get::<String>("id").ok())
+    .filter(|id| !id.is_empty())
+}
+
+/// Carries a request's id over to a text buffer made from its text.
+fn set_request_id(buffer: &mut BufferRef, id: Option<&str>) {
+  if let Some(id) = id {
+    if let Ok(mut meta) = gstreamer::meta::CustomMeta::add(buffer, REQUEST_META_NAME) {
+      meta.mut_structure().set("id", id);
+    }
+  }
+}
+
+/// The contents of a text buffer. Rather than mapping the buffer, which merges the memories of
+/// fragmented buffers into a new one, each memory is copied out in turn, so a character split
+/// across memories is put back together.
+fn text_bytes(buffer: &BufferRef) -> Option<Vec<u8>> {
+  let mut bytes = Vec::with_capacity(buffer.size());
+  for memory in buffer.iter_memories() {
+    bytes.extend_from_slice(&memory.map_readable().ok()?);
+  }
+  Some(bytes)
+}
+
+/// Writes reference audio received in a buffer to a temporary file, since Coqui reads it from a
+/// path. The file is created exclusively, under a random name, so that other users of a shared
+/// temporary directory can't put a file or symlink there first.
+fn write_voice_buffer(buffer: &Buffer) -> std::io::Result<String> {
+  let map = buffer
+    .map_readable()
+    .map_err(|e| std::io::Error::other(e.to_string()))?;
+  loop {
+    // Every RandomState has random keys, so hashing nothing with one gives a random number.
+    let name = format!(
+      "coquitts-voice-{:016x}.wav",
+      RandomState::new().build_hasher().finish()
+    );
+    let path = env::temp_dir().join(name);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = match options.open(&path) {
+      Ok(file) => file,
+      Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+      Err(e) => return Err(e),
+    };
+    let path = path.to_string_lossy().into_owned();
+    if let Err(e) = file.write_all(map.as_slice()) {
+      remove_voice_buffer_file(&path);
+      return Err(e);
+    }
+    return Ok(path);
+  }
+}
+
+fn remove_voice_buffer_file(path: &str) {
+  gstreamer::debug!(CAT, "remove_voice_buffer_file(): removing {}", path);
+  if let Err(e) = fs::remove_file(path) {
+    gstreamer::warning!(
+      CAT,
+      "remove_voice_buffer_file(): failed to remove {}: {}",
+      path,
+      e
+    );
   }
 }
 
+fn ms_to_samples(ms: u32, sample_rate: u64) -> usize {
+  (ms as u64 * sample_rate / 1000) as usize
+}
+
 impl BaseTransformImpl for CoquittsFilter {
   const MODE: BaseTransformMode = BaseTransformMode::NeverInPlace;
   const PASSTHROUGH_ON_SAME_CAPS: bool = false;
@@ -261,14 +5544,319 @@ impl BaseTransformImpl for CoquittsFilter {
 
   fn start(&self) -> Result<(), ErrorMessage> {
     gstreamer::debug!(CAT, "start()");
+    // Load the model up front so that a missing Python package or a bad model fails the state
+    // change rather than the first caps query, and likewise check the server can be reached.
+    let (dry_run, backend) = {
+      let settings = self.settings.lock().unwrap();
+      (settings.dry_run, settings.backend)
+    };
+    if !dry_run {
+      match backend {
+        // With candidates, the model isn't known until caps are negotiated, so loading `model`
+        // would be wasted.
+        Backend::Local if !self.model_candidates().is_empty() => (),
+        Backend::Local => self.with_synth(|_| ())?,
+        Backend::Server => {
+          self.server_sample_rate()?;
+        },
+        Backend::Onnx => self.with_onnx_model(|_| ())?,
+      }
+    }
+    *self.queue.lock().unwrap() = Queue::default();
+    {
+      let mut state = self.state.lock().unwrap();
+      state.initial_text_pushed = false;
+      state.pending_initial_text = None;
+      state.upstream_segment = false;
+    }
+    let progress_file = self.settings.lock().unwrap().progress_file.clone();
+    *self.progress.lock().unwrap() = match progress_file {
+      Some(path) => {
+        let progress = Progress::open(&path).map_err(|e| {
+          gstreamer::error_msg!(
+            gstreamer::ResourceError::OpenReadWrite,
+            ("Failed to open progress file {}", path),
+            ["{}", e]
+          )
+        })?;
+        gstreamer::debug!(
+          CAT,
+          "start(): {} buffers already done according to {}",
+          progress.len(),
+          path
+        );
+        Some(progress)
+      },
+      None => None,
+    };
+    let num_workers = self.settings.lock().unwrap().num_workers;
+    let mut workers = self.workers.lock().unwrap();
+    for index in 0..num_workers as usize {
+      let element = self.obj().clone();
+      let worker = thread::Builder::new()
+        .name(format!("coquitts-worker-{}", index))
+        .spawn(move || {
+          WORKER_INDEX.with(|worker_index| worker_index.set(index));
+          element.imp().run_worker()
+        })
+        .map_err(|e| {
+          gstreamer::error_msg!(
+            gstreamer::ResourceError::Failed,
+            ["Failed to start worker thread: {}", e]
+          )
+        })?;
+      workers.push(worker);
+    }
+    if self.settings.lock().unwrap().watch_voice_file {
+      let element = self.obj().clone();
+      let watcher = thread::Builder::new()
+        .name("coquitts-voice-watcher".into())
+        .spawn(move || element.imp().watch_voice_file())
+        .map_err(|e| {
+          gstreamer::error_msg!(
+            gstreamer::ResourceError::Failed,
+            ["Failed to start voice file watcher thread: {}", e]
+          )
+        })?;
+      workers.push(watcher);
+    }
+    #[cfg(feature = "metrics")]
+    self.start_metrics_server()?;
     Ok(())
   }
 
   fn stop(&self) -> Result<(), ErrorMessage> {
     gstreamer::debug!(CAT, "stop()");
+    {
+      let mut queue = self.queue.lock().unwrap();
+      queue.shutdown = true;
+      queue.buffers.clear();
+      self.queue_cond.notify_all();
+    }
+    for worker in self.workers.lock().unwrap().drain(..) {
+      let _ = worker.join();
+    }
+    #[cfg(feature = "metrics")]
+    {
+      *self.metrics_server.lock().unwrap() = None;
+    }
+    *self.state.lock().unwrap() = State::default();
+    *self.progress.lock().unwrap() = None;
+    *self.throttle.lock().unwrap() = Throttle::default();
     Ok(())
   }
 
+  fn sink_event(&self, event: Event) -> bool {
+    match event.view() {
+      EventView::FlushStart(_) => {
+        gstreamer::debug!(CAT, "sink_event(): flush, discarding queued text");
+        let mut queue = self.queue.lock().unwrap();
+        queue.flushing = true;
+        queue.epoch += 1;
+        queue.next_sequence = 0;
+        queue.next_push = 0;
+        queue.buffers.clear();
+        self.queue_cond.notify_all();
+      },
+      EventView::FlushStop(_) => {
+        let mut queue = self.queue.lock().unwrap();
+        queue.flushing = false;
+        queue.flow_error = None;
+        let mut state = self.state.lock().unwrap();
+        state.qos_earliest_time = None;
+        state.output_position = None;
+        state.gapless_offset = 0;
+        state.output_duration = None;
+        state.csv_remainder.clear();
+        state.partial_text.clear();
+        state.partial_pts = None;
+        state.partial_id = None;
+        state.last_text = None;
+      },
+      EventView::StreamStart(stream_start) => {
+        // The text before it belongs to the previous stream.
+        self.wait_for_idle();
+        gstreamer::debug!(
+          CAT,
+          "sink_event(): stream {} started in group {:?}, resetting stream state",
+          stream_start.stream_id(),
+          stream_start.group_id()
+        );
+        self.reset_stream_state();
+      },
+      EventView::Tag(tag) if tag.tag().scope() == gstreamer::TagScope::Stream => {
+        self.wait_for_idle();
+        if let Some(code) = tag.tag().get::<gstreamer::tags::LanguageCode>() {
+          let language = langdetect::from_tag(code.get());
+          gstreamer::debug!(
+            CAT,
+            "sink_event(): language tag {} is language {:?}",
+            code.get(),
+            language
+          );
+          if language.is_some() {
+            self.state.lock().unwrap().tag_language = language;
+            self.obj().notify("effective-settings");
+          }
+        }
+        let mut tags = tag.tag().to_owned();
+        {
+          let tags = tags.get_mut().unwrap();
+          // The audio stream carries the rest of the text stream's tags, such as its language.
+          tags.remove::<gstreamer::tags::SubtitleCodec>();
+          tags.set_scope(gstreamer::TagScope::Stream);
+        }
+        gstreamer::debug!(CAT, "sink_event(): forwarding stream tags {:?}", tags);
+        return self.parent_sink_event(gstreamer::event::Tag::new(tags));
+      },
+      EventView::CustomDownstream(custom)
+        if custom
+          .structure()
+          .is_some_and(|structure| structure.name() == SET_VOICE_FILE_EVENT) =>
+      {
+        // Switch voices after the text that came before the event.
+        self.wait_for_idle();
+        self.set_voice_file(custom.structure().unwrap());
+        return true;
+      },
+      EventView::CustomDownstream(custom)
+        if custom
+          .structure()
+          .is_some_and(|structure| structure.name() == FLUSH_TEXT_QUEUE_EVENT) =>
+      {
+        let mut queue = self.queue.lock().unwrap();
+        gstreamer::debug!(
+          CAT,
+          "sink_event(): discarding {} queued texts",
+          queue.buffers.len()
+        );
+        queue.buffers.clear();
+        // Upstream may be waiting for room in the queue.
+        self.queue_cond.notify_all();
+        return true;
+      },
+      EventView::Eos(_) => {
+        // A last row without a newline is complete now, as is an unfinished partial message.
+        let _ = self.enqueue_last_row();
+        let _ = self.enqueue_partial_remainder(None);
+        gstreamer::debug!(CAT, "sink_event(): waiting for queued text before EOS");
+        self.wait_for_idle();
+      },
+      _ if event.is_serialized() => {
+        gstreamer::debug!(
+          CAT,
+          "sink_event(): waiting for queued text before forwarding {:?}",
+          event.type_()
+        );
+        self.wait_for_idle();
+      },
+      _ => (),
+    }
+    if matches!(
+      event.type_(),
+      EventType::FlushStart | EventType::FlushStop | EventType::Eos
+    ) {
+      let marks_pad = self.marks_pad.lock().unwrap().clone();
+      if let Some(pad) = marks_pad.filter(|pad| {
+        pad
+          .sticky_event::<gstreamer::event::StreamStart>(0)
+          .is_some()
+      }) {
+        pad.push_event(event.clone());
+      }
+    }
+    let segment = event.type_() == EventType::Segment;
+    let handled = self.parent_sink_event(event);
+    if segment && handled {
+      let initial_text = {
+        let mut state = self.state.lock().unwrap();
+        state.upstream_segment = true;
+        state.pending_initial_text.take()
+      };
+      if let Some(text) = initial_text {
+        gstreamer::debug!(
+          CAT,
+          "sink_event(): upstream's stream started, queueing initial text"
+        );
+        self.enqueue_initial_text(text);
+      }
+    }
+    handled
+  }
+
+  fn query(&self, direction: PadDirection, query: &mut QueryRef) -> bool {
+    if direction != PadDirection::Src {
+      return BaseTransformImplExt::parent_query(self, direction, query);
+    }
+    match query.view_mut() {
+      QueryViewMut::Position(q) if q.format() == Format::Time => {
+        let position = self.state.lock().unwrap().output_position;
+        let segment = self.obj().segment();
+        let position = position.and_then(|position| {
+          segment
+            .downcast_ref::<ClockTime>()
+            .and_then(|segment| segment.to_stream_time(position))
+        });
+        match position {
+          Some(position) => {
+            gstreamer::trace!(CAT, "query(): position {}", position);
+            q.set(position);
+            true
+          },
+          None => BaseTransformImplExt::parent_query(self, direction, query),
+        }
+      },
+      QueryViewMut::Duration(q) if q.format() == Format::Time => {
+        if BaseTransformImplExt::parent_query(self, direction, query) {
+          return true;
+        }
+        // Upstream doesn't know how long the text is, so report how much audio there is so far.
+        match self.state.lock().unwrap().output_duration {
+          Some(duration) => {
+            if let QueryViewMut::Duration(q) = query.view_mut() {
+              q.set(duration);
+            }
+            true
+          },
+          None => false,
+        }
+      },
+      QueryViewMut::Latency(_) => {
+        if !BaseTransformImplExt::parent_query(self, direction, query) {
+          return false;
+        }
+        if let QueryViewMut::Latency(q) = query.view_mut() {
+          let (live, min, max) = q.result();
+          let latency = self.latency();
+          gstreamer::debug!(CAT, "query(): adding latency {}", latency);
+          q.set(live, min + latency, max.map(|max| max + latency));
+        }
+        true
+      },
+      _ => BaseTransformImplExt::parent_query(self, direction, query),
+    }
+  }
+
+  fn src_event(&self, event: Event) -> bool {
+    if event.type_() == EventType::Reconfigure {
+      gstreamer::debug!(CAT, "src_event(): downstream asked to renegotiate");
+    }
+    if let EventView::Qos(qos) = event.view() {
+      let (_, _, diff, timestamp) = qos.get();
+      if let Some(timestamp) = timestamp {
+        let earliest_time = if diff > 0 {
+          timestamp + ClockTime::from_nseconds(diff as u64)
+        }
+        else {
+          timestamp.saturating_sub(ClockTime::from_nseconds(diff.unsigned_abs()))
+        };
+        gstreamer::debug!(CAT, "src_event(): QoS earliest time: {}", earliest_time);
+        self.state.lock().unwrap().qos_earliest_time = Some(earliest_time);
+      }
+    }
+    self.parent_src_event(event)
+  }
+
   fn transform_caps(
     &self,
     direction: PadDirection,
@@ -279,98 +5867,252 @@ impl BaseTransformImpl for CoquittsFilter {
       SINK_CAPS.clone()
     }
     else {
-      let sample_rate = self.with_synth(|s| {
-        s.getattr("synthesizer")
-          .unwrap()
-          .getattr("output_sample_rate")
-          .unwrap()
-          .extract::<u64>()
-          .unwrap()
-      });
-      gstreamer::debug!(CAT, "transform_caps(): using sample rate: {}", sample_rate);
-      src_caps_builder().rate(sample_rate as i32).build()
+      let candidates = self.model_candidates();
+      if candidates.is_empty() {
+        let sample_rate = match self.sample_rate() {
+          Ok(sample_rate) => sample_rate,
+          Err(e) => {
+            self.post_error_message(e);
+            return None;
+          },
+        };
+        gstreamer::debug!(CAT, "transform_caps(): using sample rate: {}", sample_rate);
+        src_caps_builder().rate(sample_rate as i32).build()
+      }
+      else {
+        let mut sample_rates = vec![];
+        for (_, sample_rate) in self.candidate_rates(&candidates) {
+          if !sample_rates.contains(&(sample_rate as i32)) {
+            sample_rates.push(sample_rate as i32);
+          }
+        }
+        if sample_rates.is_empty() {
+          self.post_error_message(gstreamer::error_msg!(
+            gstreamer::LibraryError::Init,
+            ("None of the candidate models could be loaded")
+          ));
+          return None;
+        }
+        gstreamer::debug!(
+          CAT,
+          "transform_caps(): candidate sample rates: {:?}",
+          sample_rates
+        );
+        src_caps_builder().rate_list(sample_rates).build()
+      }
     };
+    if direction == PadDirection::Sink && self.settings.lock().unwrap().resample {
+      // The model's rates are still preferred.
+      caps.merge(src_caps_builder().rate_range(1..=i32::MAX).build());
+    }
     if let Some(filter) = maybe_filter {
       caps = filter.intersect_with_mode(&caps, CapsIntersectMode::First);
     }
     Some(caps)
   }
 
-  fn generate_output(&self) -> Result<GenerateOutputSuccess, FlowError> {
-    if let Some(buffer) = self.take_queued_buffer() {
-      let buffer_reader = buffer
-        .as_ref()
-        .map_readable()
-        .map_err(|_| FlowError::Error)?;
-      let text = str::from_utf8(buffer_reader.as_slice()).map_err(|_| FlowError::Error)?;
-      gstreamer::debug!(CAT, "generate_output(): synthesising: {}", text);
-      let maybe_audio = self.with_synth(|s| {
-        let kwargs = {
-          let settings = self.settings.lock().unwrap();
-          let d = PyDict::new(s.py());
-          d.set_item("text", text).unwrap();
-          if let Some(speaker) = settings.speaker.as_ref() {
-            d.set_item("speaker", speaker).unwrap();
-          }
-          if let Some(language) = settings.language.as_ref() {
-            d.set_item("language", language).unwrap();
-          }
-          if let Some(file) = settings.voice_cloning_input_file.as_ref() {
-            d.set_item("speaker_wav", file).unwrap();
-          }
-          d
-        };
-        match s.call_method("tts", (), Some(kwargs)) {
-          Ok(any) => Some(
-            any
-              .downcast::<PyList>()
-              .unwrap()
-              .extract::<Vec<f32>>()
-              .unwrap(),
-          ),
-          Err(e) => {
-            gstreamer::debug!(
-              CAT,
-              "generate_output(): failed to synthesise samples: {:?}",
-              e
-            );
-            e.print(s.py());
-            None
-          },
-        }
+  fn set_caps(&self, incaps: &Caps, outcaps: &Caps) -> Result<(), LoggableError> {
+    let phoneme_input = incaps
+      .structure(0)
+      .is_some_and(|s| s.name() == PHONEMES_CAPS_NAME);
+    let json_input = incaps.structure(0).is_some_and(|s| {
+      s.name() == "text/x-raw" && s.get::<&str>("format").is_ok_and(|format| format == "json")
+    });
+    let csv_input = incaps
+      .structure(0)
+      .is_some_and(|s| s.name() == CSV_CAPS_NAME);
+    let out_info = AudioInfo::from_caps(outcaps).ok();
+    let f64_output = out_info
+      .as_ref()
+      .is_some_and(|info| info.format() == AUDIO_FORMAT_F64);
+    let s16_output = out_info
+      .as_ref()
+      .is_some_and(|info| info.format() == AUDIO_FORMAT_S16);
+    let non_interleaved = out_info
+      .as_ref()
+      .is_some_and(|info| info.layout() == AudioLayout::NonInterleaved);
+    let output_rate = out_info.as_ref().map(|info| info.rate() as u64);
+    let caps_field = |name| {
+      incaps
+        .structure(0)
+        .and_then(|s| s.get::<String>(name).ok())
+        .filter(|value| !value.is_empty())
+    };
+    let (caps_speaker, caps_language) = (caps_field("tts-speaker"), caps_field("tts-language"));
+    gstreamer::debug!(
+      CAT,
+      "set_caps(): speaker {:?} and language {:?} from caps",
+      caps_speaker,
+      caps_language
+    );
+    {
+      let mut state = self.state.lock().unwrap();
+      state.phoneme_input = phoneme_input;
+      state.json_input = json_input;
+      state.csv_input = csv_input;
+      state.f64_output = f64_output;
+      state.s16_output = s16_output;
+      state.non_interleaved = non_interleaved;
+      state.output_rate = output_rate;
+      state.caps_speaker = caps_speaker;
+      state.caps_language = caps_language;
+    }
+    self.obj().notify("effective-settings");
+    let candidates = self.model_candidates();
+    if candidates.is_empty() {
+      return self.check_phoneme_input();
+    }
+    let sample_rate = outcaps
+      .structure(0)
+      .and_then(|s| s.get::<i32>("rate").ok())
+      .ok_or_else(|| gstreamer::loggable_error!(CAT, "No rate in output caps"))?;
+    let model = self
+      .candidate_rates(&candidates)
+      .into_iter()
+      .find(|(_, rate)| *rate == sample_rate as u64)
+      .map(|(model, _)| model)
+      .ok_or_else(|| {
+        gstreamer::loggable_error!(CAT, "No candidate model has sample rate {}", sample_rate)
+      })?;
+    gstreamer::debug!(
+      CAT,
+      "set_caps(): using model {} for sample rate {}",
+      model,
+      sample_rate
+    );
+    // Other candidates loaded to find their rates won't be used.
+    self
+      .candidate_synths
+      .lock()
+      .unwrap()
+      .retain(|candidate, _| *candidate == model);
+    self.state.lock().unwrap().selected_model = Some(model);
+    self.check_phoneme_input()
+  }
+
+  fn decide_allocation(&self, query: &mut Allocation) -> Result<(), LoggableError> {
+    self.parent_decide_allocation(query)?;
+    // Hand over synthesised samples without copying, unless downstream wants memory from a
+    // particular allocator or aligned more strictly than floats are. The alignment is a mask.
+    let allocation = query
+      .allocation_params()
+      .into_iter()
+      .next()
+      .filter(|(allocator, params)| {
+        allocator.is_some() || params.align() >= std::mem::align_of::<f32>()
       });
-      if let Some(audio) = maybe_audio {
-        gstreamer::debug!(
-          CAT,
-          "generate_output(): synthesised {} samples",
-          audio.len()
-        );
-        gstreamer::debug!(
-          CAT,
-          "generate_output(): first 32 samples: {:?}",
-          &audio[..32]
-        );
-        let audio_bytes = audio.as_byte_slice();
-        gstreamer::debug!(
-          CAT,
-          "generate_output(): synthesised {} bytes",
-          audio_bytes.len()
-        );
-        let mut buffer = Buffer::with_size(audio_bytes.len()).map_err(|_| FlowError::Error)?;
-        buffer
-          .get_mut()
-          .unwrap()
-          .copy_from_slice(0, audio_bytes)
-          .map_err(|_| FlowError::Error)?;
-        Ok(GenerateOutputSuccess::Buffer(buffer))
-      }
-      else {
-        Ok(GenerateOutputSuccess::NoOutput)
+    gstreamer::debug!(
+      CAT,
+      "decide_allocation(): copying into downstream's memory: {}",
+      allocation.is_some()
+    );
+    let audio_meta = query.find_allocation_meta::<AudioMeta>().is_some();
+    gstreamer::debug!(
+      CAT,
+      "decide_allocation(): adding audio meta: {}",
+      audio_meta
+    );
+    let mut state = self.state.lock().unwrap();
+    state.allocation = allocation;
+    state.audio_meta = audio_meta;
+    Ok(())
+  }
+
+  fn propose_allocation(
+    &self,
+    decide_query: Option<&Allocation>,
+    query: &mut Allocation,
+  ) -> Result<(), LoggableError> {
+    self.parent_propose_allocation(decide_query, query)?;
+    // Advertise the custom metas understood on text buffers, so that producers such as
+    // applications pushing into appsrc can find out which are supported.
+    for name in [DURATION_META_NAME, PARTIAL_META_NAME, REQUEST_META_NAME] {
+      // Custom metas have no Rust API type, only the one registered under their name.
+      // SAFETY: `name` is a valid NUL-terminated string for the duration of the call, and a
+      // non-null `GstMetaInfo` is registered for the life of the process, so reading its `api` is
+      // sound. `query` is a writable allocation query, as this vfunc is given, and a null params
+      // structure is allowed.
+      unsafe {
+        let info = gstreamer::ffi::gst_meta_get_info(name.to_glib_none().0);
+        if !info.is_null() {
+          gstreamer::ffi::gst_query_add_allocation_meta(
+            query.as_mut_ptr(),
+            (*info).api,
+            std::ptr::null(),
+          );
+        }
       }
     }
+    Ok(())
+  }
+
+  fn submit_input_buffer(
+    &self,
+    _is_discont: bool,
+    buffer: Buffer,
+  ) -> Result<FlowSuccess, FlowError> {
+    let buffer = self.schedule_live(buffer);
+    let (csv_input, json_input) = {
+      let state = self.state.lock().unwrap();
+      (state.csv_input, state.json_input)
+    };
+    if csv_input {
+      self.enqueue_rows(&buffer)
+    }
+    else if json_input {
+      self.enqueue(buffer)
+    }
+    else if gstreamer::meta::CustomMeta::from_buffer(&buffer, PARTIAL_META_NAME).is_ok() {
+      self.enqueue_partial(&buffer)
+    }
     else {
-      gstreamer::debug!(CAT, "generate_output(): no queued buffers to take");
-      Ok(GenerateOutputSuccess::NoOutput)
+      self.enqueue_partial_remainder(Some(buffer))
     }
   }
 }
+
+/// Synthesised samples owned by an output buffer, so they don't need copying into it.
+struct Samples<T>(Vec<T>);
+
+impl<T: ToMutByteSlice> AsMut<[u8]> for Samples<T> {
+  fn as_mut(&mut self) -> &mut [u8] {
+    self.0.as_mut_byte_slice()
+  }
+}
+
+fn audio_buffer<T: ToByteSlice + ToMutByteSlice + fmt::Debug + Send + 'static>(
+  audio: Vec<T>,
+  allocation: Option<&(Option<Allocator>, AllocationParams)>,
+) -> Result<Buffer, FlowError> {
+  gstreamer::debug!(CAT, "audio_buffer(): synthesised {} samples", audio.len());
+  gstreamer::debug!(
+    CAT,
+    "audio_buffer(): first samples: {:?}",
+    &audio[..audio.len().min(32)]
+  );
+  let (allocator, params) = match allocation {
+    Some(allocation) => allocation,
+    None => return Ok(Buffer::from_mut_slice(Samples(audio))),
+  };
+  let audio_bytes = audio.as_byte_slice();
+  gstreamer::debug!(
+    CAT,
+    "audio_buffer(): copying {} bytes into downstream's memory",
+    audio_bytes.len()
+  );
+  let memory = match allocator {
+    Some(allocator) => allocator
+      .alloc(audio_bytes.len(), Some(params))
+      .map_err(|_| FlowError::Error)?,
+    None => Memory::with_size_and_params(audio_bytes.len(), params),
+  };
+  let mut buffer = Buffer::new();
+  {
+    let buffer = buffer.get_mut().unwrap();
+    buffer.append_memory(memory);
+    buffer
+      .copy_from_slice(0, audio_bytes)
+      .map_err(|_| FlowError::Error)?;
+  }
+  Ok(buffer)
+}
@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+/// The longest speaker label recognised, in characters.
+const MAX_LABEL_CHARS: usize = 32;
+
+/// Maps the speaker labels that start lines of scripts and subtitles, such as `JOHN:`, to the
+/// model's speakers.
+///
+/// The map is parsed from a JSON object of labels to speakers, e.g.
+/// `{"JOHN": "p225", "Mary Ann": "p226"}`. Labels are matched case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct SpeakerMap {
+  source: Option<String>,
+  speakers: HashMap<String, String>,
+}
+
+impl SpeakerMap {
+  pub fn parse(source: &str) -> Result<Self, String> {
+    let speakers: HashMap<String, String> =
+      serde_json::from_str(source).map_err(|e| format!("invalid speaker map JSON: {}", e))?;
+    Ok(Self {
+      source: Some(source.to_owned()),
+      speakers: speakers
+        .into_iter()
+        .map(|(label, speaker)| (label.trim().to_lowercase(), speaker))
+        .collect(),
+    })
+  }
+
+  pub fn source(&self) -> Option<&str> {
+    self.source.as_deref()
+  }
+
+  /// Splits a leading label that's in the map off text, returning its speaker and the text after
+  /// it. A dialogue dash before the label, as in subtitles, is removed too. Text that doesn't
+  /// start with a known label gives `None`.
+  pub fn apply<'a>(&self, text: &'a str) -> Option<(&str, &'a str)> {
+    if self.speakers.is_empty() {
+      return None;
+    }
+    let text = text.trim_start();
+    let text = text
+      .strip_prefix(['-', '–', '—'])
+      .map(str::trim_start)
+      .unwrap_or(text);
+    let (label, rest) = text.split_once(':')?;
+    if label.chars().count() > MAX_LABEL_CHARS
+      || !label
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '.' | '\'' | '-' | '_'))
+      || !(rest.is_empty() || rest.starts_with(char::is_whitespace))
+    {
+      return None;
+    }
+    let speaker = self.speakers.get(&label.trim().to_lowercase())?;
+    Some((speaker.as_str(), rest.trim_start()))
+  }
+}
@@ -0,0 +1,116 @@
+use once_cell::sync::Lazy;
+use whatlang::{Detector, Lang};
+
+/// Languages that can be detected, with the identifiers Coqui's multi-lingual models use for
+/// them.
+const LANGUAGES: [(Lang, &str); 17] = [
+  (Lang::Eng, "en"),
+  (Lang::Spa, "es"),
+  (Lang::Fra, "fr"),
+  (Lang::Deu, "de"),
+  (Lang::Ita, "it"),
+  (Lang::Por, "pt"),
+  (Lang::Pol, "pl"),
+  (Lang::Tur, "tr"),
+  (Lang::Rus, "ru"),
+  (Lang::Nld, "nl"),
+  (Lang::Ces, "cs"),
+  (Lang::Ara, "ar"),
+  (Lang::Cmn, "zh-cn"),
+  (Lang::Jpn, "ja"),
+  (Lang::Hun, "hu"),
+  (Lang::Kor, "ko"),
+  (Lang::Hin, "hi"),
+];
+
+/// ISO 639-2 bibliographic codes, which language tags may use, and the ISO 639-3 codes for the
+/// same languages.
+const BIBLIOGRAPHIC_CODES: [(&str, &str); 5] = [
+  ("fre", "fra"),
+  ("ger", "deu"),
+  ("dut", "nld"),
+  ("cze", "ces"),
+  ("chi", "cmn"),
+];
+
+/// Texts with fewer words than this are too short to detect reliably. Each Chinese or Japanese
+/// character counts as a word.
+const MIN_WORDS: usize = 3;
+const MIN_CONFIDENCE: f64 = 0.1;
+
+static DETECTOR: Lazy<Detector> =
+  Lazy::new(|| Detector::with_allowlist(LANGUAGES.iter().map(|(lang, _)| *lang).collect()));
+
+/// Detects the language of `text`, returning a Coqui language identifier if the text is long
+/// enough and the detection is confident enough.
+pub fn detect(text: &str) -> Option<&'static str> {
+  if word_count(text) < MIN_WORDS {
+    return None;
+  }
+  let info = DETECTOR.detect(text)?;
+  if info.confidence() < MIN_CONFIDENCE {
+    return None;
+  }
+  LANGUAGES
+    .iter()
+    .find(|(lang, _)| *lang == info.lang())
+    .map(|(_, code)| *code)
+}
+
+/// Converts the ISO 639-1 or ISO 639-2 code of a `language-code` tag to a Coqui language
+/// identifier. Two-letter codes of languages that can't be detected are passed through, as the
+/// model may still support them.
+pub fn from_tag(code: &str) -> Option<String> {
+  let code = code.trim().to_lowercase();
+  match code.len() {
+    2 => Some(
+      LANGUAGES
+        .iter()
+        .map(|(_, identifier)| *identifier)
+        .find(|identifier| identifier.split('-').next() == Some(code.as_str()))
+        .map(str::to_owned)
+        .unwrap_or(code),
+    ),
+    3 => {
+      let code = BIBLIOGRAPHIC_CODES
+        .iter()
+        .find(|(bibliographic, _)| *bibliographic == code)
+        .map_or(code.as_str(), |(_, terminological)| terminological);
+      let lang = Lang::from_code(code)?;
+      LANGUAGES
+        .iter()
+        .find(|(known, _)| *known == lang)
+        .map(|(_, identifier)| (*identifier).to_owned())
+    },
+    _ => None,
+  }
+}
+
+/// Counts the words of `text`. Chinese and Japanese are written without spaces between words, so
+/// each of their characters counts as one, and the rest of a word mixing them with another script
+/// counts as another.
+fn word_count(text: &str) -> usize {
+  text
+    .split_whitespace()
+    .map(|word| {
+      let ideographs = word.chars().filter(|c| is_cjk(*c)).count();
+      if ideographs == word.chars().count() {
+        ideographs
+      }
+      else {
+        ideographs + 1
+      }
+    })
+    .sum()
+}
+
+/// Whether a character is a kana or a CJK ideograph.
+fn is_cjk(c: char) -> bool {
+  matches!(
+    c,
+    '\u{3040}'..='\u{30ff}'
+      | '\u{3400}'..='\u{4dbf}'
+      | '\u{4e00}'..='\u{9fff}'
+      | '\u{f900}'..='\u{faff}'
+  )
+}
@@ -0,0 +1,103 @@
+use std::{
+  collections::HashMap,
+  fs, io,
+  path::{Path, PathBuf},
+  time::SystemTime,
+};
+
+/// A pronunciation lexicon mapping words to replacement text.
+///
+/// Lexicon files are either JSON objects (`{"word": "replacement"}`), when the file name ends
+/// in `.json`, or TSV files with one `word<TAB>replacement` entry per line. Words are matched
+/// case-insensitively against whole words in the text.
+#[derive(Debug)]
+pub struct Lexicon {
+  path: PathBuf,
+  modified: Option<SystemTime>,
+  entries: HashMap<String, String>,
+}
+
+impl Lexicon {
+  pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let path = path.as_ref().to_path_buf();
+    let modified = fs::metadata(&path)?.modified().ok();
+    let entries = parse(&path, &fs::read_to_string(&path)?)?;
+    Ok(Self {
+      path,
+      modified,
+      entries,
+    })
+  }
+
+  /// Reloads the lexicon if the file has been modified since it was last loaded. Returns
+  /// whether the lexicon was reloaded.
+  pub fn reload_if_changed(&mut self) -> io::Result<bool> {
+    let modified = fs::metadata(&self.path)?.modified().ok();
+    if modified.is_some() && modified == self.modified {
+      return Ok(false);
+    }
+    self.entries = parse(&self.path, &fs::read_to_string(&self.path)?)?;
+    self.modified = modified;
+    Ok(true)
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn apply(&self, text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut word_start = None;
+    for (i, c) in text.char_indices() {
+      if c.is_alphanumeric() || c == '\'' || c == '-' {
+        word_start.get_or_insert(i);
+      }
+      else {
+        if let Some(start) = word_start.take() {
+          self.push_word(&mut output, &text[start..i]);
+        }
+        output.push(c);
+      }
+    }
+    if let Some(start) = word_start {
+      self.push_word(&mut output, &text[start..]);
+    }
+    output
+  }
+
+  fn push_word(&self, output: &mut String, word: &str) {
+    match self.entries.get(&word.to_lowercase()) {
+      Some(replacement) => output.push_str(replacement),
+      None => output.push_str(word),
+    }
+  }
+}
+
+fn parse(path: &Path, contents: &str) -> io::Result<HashMap<String, String>> {
+  let entries: HashMap<String, String> = if path.extension().is_some_and(|ext| ext == "json") {
+    serde_json::from_str(contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+  }
+  else {
+    contents
+      .lines()
+      .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+      .map(|line| {
+        line
+          .split_once('\t')
+          .map(|(word, replacement)| (word.trim().to_owned(), replacement.trim().to_owned()))
+          .ok_or_else(|| {
+            io::Error::new(
+              io::ErrorKind::InvalidData,
+              format!("lexicon line is missing a tab separator: {}", line),
+            )
+          })
+      })
+      .collect::<io::Result<_>>()?
+  };
+  Ok(
+    entries
+      .into_iter()
+      .map(|(word, replacement)| (word.to_lowercase(), replacement))
+      .collect(),
+  )
+}
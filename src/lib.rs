@@ -1,9 +1,40 @@
 use gstreamer::glib;
 
+mod cache;
+mod directions;
+mod dsp;
 mod filter;
+mod labels;
+mod langdetect;
+mod lexicon;
+mod message;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod mixer;
+mod normalize;
+mod onnx;
+mod profile;
+mod progress;
+mod punctuate;
+mod python;
+mod roomtone;
+mod rules;
+mod scheduler;
+mod segment;
+mod server;
+mod ssml;
+mod throttle;
+mod tone;
+mod translit;
+mod vc;
+mod verbalize;
+mod voicemap;
+mod wav;
 
 fn plugin_init(plugin: &gstreamer::Plugin) -> Result<(), glib::BoolError> {
   filter::register(plugin)?;
+  mixer::register(plugin)?;
+  vc::register(plugin)?;
   Ok(())
 }
 
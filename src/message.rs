@@ -0,0 +1,94 @@
+use serde_json::Value;
+
+use crate::filter::Priority;
+
+/// A text buffer in the JSON input format: an object with the text to synthesise, optional
+/// per-message voice overrides, an optional `priority` and an optional `id` to correlate the
+/// message with its audio, e.g.
+/// `{"text": "Hello", "speaker": "p225", "language": "en", "speed": 1.2, "id": "42"}`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Message {
+  pub text: String,
+  pub speaker: Option<String>,
+  pub language: Option<String>,
+  pub speed: Option<f64>,
+  pub priority: Option<Priority>,
+  /// The id of a JSON message or of a row of `text/csv` input.
+  pub id: Option<String>,
+}
+
+impl Message {
+  pub fn parse(json: &str) -> Result<Self, String> {
+    let value: Value =
+      serde_json::from_str(json).map_err(|e| format!("invalid JSON message: {}", e))?;
+    let object = value
+      .as_object()
+      .ok_or_else(|| "JSON message is not an object".to_owned())?;
+    let string = |key: &str| match object.get(key) {
+      None | Some(Value::Null) => Ok(None),
+      Some(Value::String(value)) => Ok(Some(value.clone())),
+      Some(_) => Err(format!("`{}` in JSON message is not a string", key)),
+    };
+    let speed = match object.get("speed") {
+      None | Some(Value::Null) => None,
+      Some(speed) => Some(
+        speed
+          .as_f64()
+          .filter(|speed| *speed > 0.0)
+          .ok_or_else(|| "`speed` in JSON message is not a positive number".to_owned())?,
+      ),
+    };
+    let priority = match string("priority")?.as_deref() {
+      None => None,
+      Some("batch") => Some(Priority::Batch),
+      Some("normal") => Some(Priority::Normal),
+      Some("interactive") => Some(Priority::Interactive),
+      Some(priority) => {
+        return Err(format!(
+          "unknown `priority` {} in JSON message, expected batch, normal or interactive",
+          priority
+        ))
+      },
+    };
+    // Ids are often numbers.
+    let id = match object.get("id") {
+      Some(Value::Number(id)) => Some(id.to_string()),
+      _ => string("id")?,
+    };
+    Ok(Self {
+      text: string("text")?.ok_or_else(|| "JSON message has no `text`".to_owned())?,
+      speaker: string("speaker")?,
+      language: string("language")?,
+      speed,
+      priority,
+      id: id.filter(|id| !id.is_empty()),
+    })
+  }
+
+  /// Parses a row of `text/csv` input, an id and the text to synthesise separated by a tab, or by
+  /// a comma if there's no tab. The text may be in double quotes, with `""` for a quote.
+  pub fn row(row: &str) -> Result<Self, String> {
+    let (id, text) = row
+      .split_once('\t')
+      .or_else(|| row.split_once(','))
+      .ok_or_else(|| format!("row has no id: {}", row))?;
+    let text = text.trim();
+    let text = match text.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+      Some(quoted) => quoted.replace("\"\"", "\""),
+      None => text.to_owned(),
+    };
+    Ok(Self {
+      text,
+      id: Some(id.trim().to_owned()),
+      ..Self::default()
+    })
+  }
+
+  /// A plain text message, using the element's voice settings.
+  pub fn text(text: &str) -> Self {
+    Self {
+      text: text.to_owned(),
+      ..Self::default()
+    }
+  }
+}
@@ -0,0 +1,178 @@
+//! Counters for monitoring a fleet of TTS pipelines, served over HTTP in the Prometheus text
+//! format.
+
+use std::{
+  fmt::Write as _,
+  io::{self, Read, Write},
+  net::{TcpListener, TcpStream},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  thread::{self, JoinHandle},
+  time::Duration,
+};
+
+/// The upper bounds in seconds of the latency histogram's buckets.
+const LATENCY_BUCKETS: [f64; 9] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+/// How often the listener checks whether it's been stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_REQUEST_LEN: usize = 8192;
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+  utterances: u64,
+  errors: u64,
+  /// How many latencies were within each of `LATENCY_BUCKETS`, cumulatively.
+  latency_buckets: [u64; LATENCY_BUCKETS.len()],
+  latency_sum: f64,
+  latency_count: u64,
+}
+
+impl Metrics {
+  pub fn record_utterance(&mut self) {
+    self.utterances += 1;
+  }
+
+  pub fn record_error(&mut self) {
+    self.errors += 1;
+  }
+
+  pub fn record_latency(&mut self, latency: Duration) {
+    let seconds = latency.as_secs_f64();
+    for (bucket, le) in self.latency_buckets.iter_mut().zip(LATENCY_BUCKETS) {
+      if seconds <= le {
+        *bucket += 1;
+      }
+    }
+    self.latency_sum += seconds;
+    self.latency_count += 1;
+  }
+
+  /// Formats the metrics of an element, labelled with its name, along with the number of texts
+  /// in its queue.
+  pub fn render(&self, element: &str, queue_depth: usize) -> String {
+    let label = format!("element=\"{}\"", escape_label(element));
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+      let _ = writeln!(text, "# HELP {} {}", name, help);
+      let _ = writeln!(text, "# TYPE {} {}", name, kind);
+      let _ = writeln!(text, "{}{{{}}} {}", name, label, value);
+    };
+    metric(
+      "coquitts_utterances_total",
+      "counter",
+      "Texts synthesised.",
+      self.utterances.to_string(),
+    );
+    metric(
+      "coquitts_errors_total",
+      "counter",
+      "Texts that failed to be synthesised.",
+      self.errors.to_string(),
+    );
+    metric(
+      "coquitts_queue_depth",
+      "gauge",
+      "Texts waiting to be synthesised.",
+      queue_depth.to_string(),
+    );
+    let name = "coquitts_latency_seconds";
+    let _ = writeln!(text, "# HELP {} How long synthesising a text took.", name);
+    let _ = writeln!(text, "# TYPE {} histogram", name);
+    for (count, le) in self.latency_buckets.iter().zip(LATENCY_BUCKETS) {
+      let _ = writeln!(text, "{}_bucket{{{},le=\"{}\"}} {}", name, label, le, count);
+    }
+    let _ = writeln!(
+      text,
+      "{}_bucket{{{},le=\"+Inf\"}} {}",
+      name, label, self.latency_count
+    );
+    let _ = writeln!(text, "{}_sum{{{}}} {}", name, label, self.latency_sum);
+    let _ = writeln!(text, "{}_count{{{}}} {}", name, label, self.latency_count);
+    text
+  }
+}
+
+fn escape_label(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
+
+/// An HTTP listener answering every request with the metrics, until it's dropped.
+#[derive(Debug)]
+pub struct Server {
+  shutdown: Arc<AtomicBool>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Server {
+  /// Listens on `port` on all interfaces, calling `render` for the body of each response. It
+  /// returns `None` once there's nothing to report.
+  pub fn start<F>(port: u16, render: F) -> io::Result<Self>
+  where
+    F: Fn() -> Option<String> + Send + 'static,
+  {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    // Accepting without blocking lets the thread notice when it's stopped.
+    listener.set_nonblocking(true)?;
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let thread = {
+      let shutdown = shutdown.clone();
+      thread::Builder::new()
+        .name("coquitts-metrics".into())
+        .spawn(move || {
+          while !shutdown.load(Ordering::Relaxed) {
+            match listener.accept() {
+              Ok((stream, _)) => {
+                let _ = respond(stream, &render);
+              },
+              Err(_) => thread::sleep(POLL_INTERVAL),
+            }
+          }
+        })?
+    };
+    Ok(Self {
+      shutdown,
+      thread: Some(thread),
+    })
+  }
+}
+
+impl Drop for Server {
+  fn drop(&mut self) {
+    self.shutdown.store(true, Ordering::Relaxed);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// Reads the head of a request and answers it with the metrics, whatever its path.
+fn respond<F: Fn() -> Option<String>>(mut stream: TcpStream, render: &F) -> io::Result<()> {
+  stream.set_nonblocking(false)?;
+  stream.set_read_timeout(Some(READ_TIMEOUT))?;
+  let mut request = vec![0; MAX_REQUEST_LEN];
+  let mut len = 0;
+  while len < request.len() {
+    let read = stream.read(&mut request[len..])?;
+    len += read;
+    if read == 0 || request[..len].windows(4).any(|window| window == b"\r\n\r\n") {
+      break;
+    }
+  }
+  let (status, body) = match render() {
+    Some(body) => ("200 OK", body),
+    None => ("503 Service Unavailable", String::new()),
+  };
+  write!(
+    stream,
+    "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    status,
+    body.len(),
+    body
+  )
+}
@@ -0,0 +1,20 @@
+mod imp;
+
+use gstreamer::{glib, prelude::StaticType, Rank};
+
+glib::wrapper! {
+  pub struct CoquittsMixer(ObjectSubclass<imp::CoquittsMixer>) @extends gstreamer_base::Aggregator, gstreamer::Element, gstreamer::Object, @implements gstreamer::ChildProxy;
+}
+
+glib::wrapper! {
+  pub struct CoquittsMixerPad(ObjectSubclass<imp::CoquittsMixerPad>) @extends gstreamer_base::AggregatorPad, gstreamer::Pad, gstreamer::Object;
+}
+
+pub fn register(plugin: &gstreamer::Plugin) -> Result<(), glib::BoolError> {
+  gstreamer::Element::register(
+    Some(plugin),
+    "coquittsmixer",
+    Rank::None,
+    CoquittsMixer::static_type(),
+  )
+}
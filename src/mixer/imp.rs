@@ -0,0 +1,579 @@
+use std::{str, sync::Mutex};
+
+use byte_slice_cast::AsByteSlice;
+use gstreamer::{
+  glib::{self, ParamSpec, Value},
+  param_spec::GstParamSpecBuilderExt,
+  prelude::{
+    Cast, ChildProxyExt, ElementExt, ElementExtManual, GstObjectExt, ParamSpecBuilderExt,
+    StaticType, ToValue,
+  },
+  subclass::{
+    prelude::{
+      ChildProxyImpl, ElementImpl, ElementImplExt, GstObjectImpl, ObjectImpl, ObjectSubclass,
+      ObjectSubclassExt, ObjectSubclassIsExt, PadImpl,
+    },
+    ElementMetadata,
+  },
+  Buffer, Caps, ClockTime, DebugCategory, ErrorMessage, FlowError, FlowSuccess, PadDirection,
+  PadPresence, PadTemplate,
+};
+use gstreamer_audio::{AudioCapsBuilder, AUDIO_FORMAT_F32};
+use gstreamer_base::{
+  prelude::{AggregatorExtManual, AggregatorPadExt, AggregatorPadExtManual},
+  subclass::prelude::{AggregatorImpl, AggregatorPadImpl},
+  Aggregator, AggregatorPad,
+};
+use once_cell::sync::Lazy;
+use pyo3::{Py, PyAny};
+
+use crate::{
+  dsp,
+  filter::GpuMode,
+  python::{self, PyBridgeError},
+};
+
+const DEFAULT_MODEL: &str = "tts_models/en/ljspeech/glow-tts";
+const DEFAULT_SAMPLE_RATE: u32 = 22050;
+const DEFAULT_GPU: GpuMode = GpuMode::Off;
+
+/// The longest buffer of mixed audio pushed at once, in seconds.
+const MAX_OUTPUT_SECONDS: u64 = 1;
+
+static CAT: Lazy<DebugCategory> = Lazy::new(|| {
+  DebugCategory::new(
+    "coquittsmixer",
+    gstreamer::DebugColorFlags::empty(),
+    Some("Multi-voice text to speech mixer using Coqui"),
+  )
+});
+
+static SRC_CAPS: Lazy<Caps> = Lazy::new(|| {
+  AudioCapsBuilder::new()
+    .format(AUDIO_FORMAT_F32)
+    .channels(1)
+    .build()
+});
+
+static SINK_CAPS: Lazy<Caps> =
+  Lazy::new(|| Caps::builder("text/x-raw").field("format", "utf8").build());
+
+#[derive(Debug, Clone)]
+struct Settings {
+  sample_rate: u32,
+  gpu: GpuMode,
+}
+
+#[derive(Debug, Default)]
+struct State {
+  /// The number of samples pushed so far.
+  position: u64,
+}
+
+pub struct CoquittsMixer {
+  settings: Mutex<Settings>,
+  state: Mutex<State>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for CoquittsMixer {
+  type Interfaces = (gstreamer::ChildProxy,);
+  type ParentType = Aggregator;
+  type Type = super::CoquittsMixer;
+
+  const NAME: &'static str = "GstCoquittsMixer";
+
+  fn new() -> Self {
+    Self {
+      settings: Mutex::new(Settings {
+        sample_rate: DEFAULT_SAMPLE_RATE,
+        gpu: DEFAULT_GPU,
+      }),
+      state: Mutex::new(State::default()),
+    }
+  }
+}
+
+impl ObjectImpl for CoquittsMixer {
+  fn properties() -> &'static [ParamSpec] {
+    static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
+      vec![
+      glib::ParamSpecUInt::builder("sample-rate")
+        .nick("Sample rate")
+        .blurb(&format!("The sample rate of the mixed output. Each pad's audio is resampled to it. Defaults to {}", DEFAULT_SAMPLE_RATE))
+        .minimum(1)
+        .default_value(DEFAULT_SAMPLE_RATE)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecEnum::builder_with_default("use-gpu", DEFAULT_GPU)
+        .nick("Use GPU")
+        .blurb("Whether to run the model on a GPU. In auto mode the best device available is picked.")
+        .mutable_ready()
+        .build(),
+    ]
+    });
+    PROPERTIES.as_ref()
+  }
+
+  fn set_property(&self, _id: usize, value: &Value, pspec: &ParamSpec) {
+    let mut settings = self.settings.lock().unwrap();
+    match pspec.name() {
+      "sample-rate" => {
+        settings.sample_rate = value.get().unwrap();
+      },
+      "use-gpu" => {
+        settings.gpu = value.get().unwrap();
+      },
+      other => panic!("no such property: {}", other),
+    }
+  }
+
+  fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
+    let settings = self.settings.lock().unwrap();
+    match pspec.name() {
+      "sample-rate" => settings.sample_rate.to_value(),
+      "use-gpu" => settings.gpu.to_value(),
+      other => panic!("no such property: {}", other),
+    }
+  }
+}
+
+impl GstObjectImpl for CoquittsMixer {}
+
+impl ElementImpl for CoquittsMixer {
+  fn metadata() -> Option<&'static ElementMetadata> {
+    static ELEMENT_METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+      ElementMetadata::new(
+        "Coqui TTS mixer",
+        "Converter/Text/Audio",
+        "Synthesises several text streams with separate voices using Coqui and mixes them",
+        "Jasper Hugo <jasper@avstack.io>",
+      )
+    });
+
+    Some(&*ELEMENT_METADATA)
+  }
+
+  fn pad_templates() -> &'static [PadTemplate] {
+    static PAD_TEMPLATES: Lazy<Vec<PadTemplate>> = Lazy::new(|| {
+      let src_pad_template = PadTemplate::with_gtype(
+        "src",
+        PadDirection::Src,
+        PadPresence::Always,
+        &SRC_CAPS,
+        AggregatorPad::static_type(),
+      )
+      .unwrap();
+
+      let sink_pad_template = PadTemplate::with_gtype(
+        "sink_%u",
+        PadDirection::Sink,
+        PadPresence::Request,
+        &SINK_CAPS,
+        super::CoquittsMixerPad::static_type(),
+      )
+      .unwrap();
+
+      vec![src_pad_template, sink_pad_template]
+    });
+
+    PAD_TEMPLATES.as_ref()
+  }
+
+  fn request_new_pad(
+    &self,
+    templ: &PadTemplate,
+    name: Option<&str>,
+    caps: Option<&Caps>,
+  ) -> Option<gstreamer::Pad> {
+    let pad = self.parent_request_new_pad(templ, name, caps)?;
+    self.obj().child_added(&pad, &pad.name());
+    Some(pad)
+  }
+
+  fn release_pad(&self, pad: &gstreamer::Pad) {
+    self.obj().child_removed(pad, &pad.name());
+    self.parent_release_pad(pad);
+  }
+}
+
+impl ChildProxyImpl for CoquittsMixer {
+  fn child_by_index(&self, index: u32) -> Option<glib::Object> {
+    self
+      .obj()
+      .sink_pads()
+      .into_iter()
+      .nth(index as usize)
+      .map(|pad| pad.upcast())
+  }
+
+  fn child_by_name(&self, name: &str) -> Option<glib::Object> {
+    self.obj().static_pad(name).map(|pad| pad.upcast())
+  }
+
+  fn children_count(&self) -> u32 {
+    self.obj().num_sink_pads() as u32
+  }
+}
+
+impl CoquittsMixer {
+  /// Mixes the pending audio of all pads from the current position up to `end` and pushes it.
+  fn push_until(
+    &self,
+    pads: &[super::CoquittsMixerPad],
+    end: u64,
+    sample_rate: u32,
+  ) -> Result<(), FlowError> {
+    loop {
+      let start = self.state.lock().unwrap().position;
+      if start >= end {
+        return Ok(());
+      }
+      let block_end = end.min(start + MAX_OUTPUT_SECONDS * sample_rate as u64);
+      let mut mixed = vec![0.0f32; (block_end - start) as usize];
+      for pad in pads {
+        pad.imp().mix_into(&mut mixed, start);
+      }
+
+      let audio_bytes = mixed.as_byte_slice();
+      let mut buffer = Buffer::with_size(audio_bytes.len()).map_err(|_| FlowError::Error)?;
+      {
+        let buffer = buffer.get_mut().unwrap();
+        buffer
+          .copy_from_slice(0, audio_bytes)
+          .map_err(|_| FlowError::Error)?;
+        buffer.set_pts(samples_to_time(start, sample_rate));
+        buffer.set_duration(
+          samples_to_time(block_end, sample_rate) - samples_to_time(start, sample_rate),
+        );
+        buffer.set_offset(start);
+        buffer.set_offset_end(block_end);
+      }
+      gstreamer::debug!(
+        CAT,
+        "push_until(): pushing {} samples at {}",
+        block_end - start,
+        start
+      );
+      self.state.lock().unwrap().position = block_end;
+      self.obj().set_position(samples_to_time(block_end, sample_rate));
+      self.finish_buffer(buffer)?;
+    }
+  }
+}
+
+impl AggregatorImpl for CoquittsMixer {
+  fn start(&self) -> Result<(), ErrorMessage> {
+    gstreamer::debug!(CAT, "start()");
+    *self.state.lock().unwrap() = State::default();
+    Ok(())
+  }
+
+  fn stop(&self) -> Result<(), ErrorMessage> {
+    gstreamer::debug!(CAT, "stop()");
+    for pad in self.obj().sink_pads() {
+      pad
+        .downcast_ref::<super::CoquittsMixerPad>()
+        .unwrap()
+        .imp()
+        .reset();
+    }
+    Ok(())
+  }
+
+  fn update_src_caps(&self, caps: &Caps) -> Result<Caps, FlowError> {
+    let sample_rate = self.settings.lock().unwrap().sample_rate;
+    let our_caps = AudioCapsBuilder::new()
+      .format(AUDIO_FORMAT_F32)
+      .channels(1)
+      .rate(sample_rate as i32)
+      .build();
+    let caps = caps.intersect(&our_caps);
+    if caps.is_empty() {
+      gstreamer::error!(CAT, "update_src_caps(): downstream doesn't accept {}", our_caps);
+      return Err(FlowError::NotNegotiated);
+    }
+    Ok(caps)
+  }
+
+  fn aggregate(&self, timeout: bool) -> Result<FlowSuccess, FlowError> {
+    let (sample_rate, gpu) = {
+      let settings = self.settings.lock().unwrap();
+      (settings.sample_rate, settings.gpu)
+    };
+    let pads: Vec<super::CoquittsMixerPad> = self
+      .obj()
+      .sink_pads()
+      .into_iter()
+      .map(|pad| pad.downcast().unwrap())
+      .collect();
+    let position = self.state.lock().unwrap().position;
+
+    // The next utterance to synthesise is the earliest queued one. Nothing can start before it,
+    // so all audio before it is final and can be mixed and pushed.
+    let mut earliest: Option<(&super::CoquittsMixerPad, u64)> = None;
+    let mut waiting = false;
+    for pad in &pads {
+      match pad.peek_buffer() {
+        Some(buffer) => {
+          let start = pad.imp().start_sample(&buffer, sample_rate).max(position);
+          if earliest.is_none_or(|(_, earliest_start)| start < earliest_start) {
+            earliest = Some((pad, start));
+          }
+        },
+        None if !pad.is_eos() && !timeout => waiting = true,
+        None => (),
+      }
+    }
+
+    match earliest {
+      Some((pad, start)) => {
+        if !waiting {
+          self.push_until(&pads, start, sample_rate)?;
+        }
+        let buffer = pad.pop_buffer().unwrap();
+        pad.imp().synthesise(&buffer, start, sample_rate, gpu).map_err(|e| {
+          self.post_error_message(e);
+          FlowError::Error
+        })?;
+        Ok(FlowSuccess::Ok)
+      },
+      None if waiting => Ok(FlowSuccess::Ok),
+      None => {
+        let end = pads
+          .iter()
+          .map(|pad| pad.imp().pending_end())
+          .max()
+          .unwrap_or(position);
+        self.push_until(&pads, end, sample_rate)?;
+        if pads.iter().all(|pad| pad.is_eos()) {
+          gstreamer::debug!(CAT, "aggregate(): all pads EOS");
+          Err(FlowError::Eos)
+        }
+        else {
+          Ok(FlowSuccess::Ok)
+        }
+      },
+    }
+  }
+}
+
+fn samples_to_time(samples: u64, sample_rate: u32) -> ClockTime {
+  ClockTime::from_nseconds(
+    (samples as u128 * ClockTime::SECOND.nseconds() as u128 / sample_rate as u128) as u64,
+  )
+}
+
+fn time_to_samples(time: ClockTime, sample_rate: u32) -> u64 {
+  (time.nseconds() as u128 * sample_rate as u128 / ClockTime::SECOND.nseconds() as u128) as u64
+}
+
+#[derive(Debug, Clone, Default)]
+struct PadSettings {
+  model: Option<String>,
+  speaker: Option<String>,
+  language: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct PadState {
+  /// Synthesised audio not yet mixed, starting at `pending_start` samples.
+  pending: Vec<f32>,
+  pending_start: u64,
+}
+
+pub struct CoquittsMixerPad {
+  settings: Mutex<PadSettings>,
+  state: Mutex<PadState>,
+  synth: Mutex<Option<Py<PyAny>>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for CoquittsMixerPad {
+  type ParentType = AggregatorPad;
+  type Type = super::CoquittsMixerPad;
+
+  const NAME: &'static str = "GstCoquittsMixerPad";
+
+  fn new() -> Self {
+    Self {
+      settings: Mutex::new(PadSettings::default()),
+      state: Mutex::new(PadState::default()),
+      synth: Mutex::new(None),
+    }
+  }
+}
+
+impl ObjectImpl for CoquittsMixerPad {
+  fn properties() -> &'static [ParamSpec] {
+    static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
+      vec![
+      glib::ParamSpecString::builder("model")
+        .nick("Model")
+        .blurb(&format!("The Coqui TTS model to use for this pad. Defaults to {}", DEFAULT_MODEL))
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("speaker")
+        .nick("Speaker")
+        .blurb("The speaker name to use for this pad, for multi-speaker models.")
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("language")
+        .nick("Language")
+        .blurb("The language identifier to use for this pad, for multi-language models.")
+        .mutable_playing()
+        .build(),
+    ]
+    });
+    PROPERTIES.as_ref()
+  }
+
+  fn set_property(&self, _id: usize, value: &Value, pspec: &ParamSpec) {
+    let mut settings = self.settings.lock().unwrap();
+    match pspec.name() {
+      "model" => {
+        settings.model = value.get().unwrap();
+        *self.synth.lock().unwrap() = None;
+      },
+      "speaker" => {
+        settings.speaker = value.get().unwrap();
+      },
+      "language" => {
+        settings.language = value.get().unwrap();
+      },
+      other => panic!("no such property: {}", other),
+    }
+  }
+
+  fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
+    let settings = self.settings.lock().unwrap();
+    match pspec.name() {
+      "model" => settings.model.to_value(),
+      "speaker" => settings.speaker.to_value(),
+      "language" => settings.language.to_value(),
+      other => panic!("no such property: {}", other),
+    }
+  }
+}
+
+impl GstObjectImpl for CoquittsMixerPad {}
+
+impl PadImpl for CoquittsMixerPad {}
+
+impl AggregatorPadImpl for CoquittsMixerPad {}
+
+impl CoquittsMixerPad {
+  fn reset(&self) {
+    *self.state.lock().unwrap() = PadState::default();
+  }
+
+  /// The output sample at which the utterance in `buffer` should start.
+  fn start_sample(&self, buffer: &Buffer, sample_rate: u32) -> u64 {
+    let cursor = self.pending_end();
+    let segment = self.obj().segment();
+    buffer
+      .pts()
+      .and_then(|pts| {
+        segment
+          .downcast_ref::<ClockTime>()
+          .and_then(|segment| segment.to_running_time(pts))
+      })
+      .map(|running_time| time_to_samples(running_time, sample_rate).max(cursor))
+      .unwrap_or(cursor)
+  }
+
+  fn pending_end(&self) -> u64 {
+    let state = self.state.lock().unwrap();
+    state.pending_start + state.pending.len() as u64
+  }
+
+  fn mix_into(&self, output: &mut [f32], output_start: u64) {
+    let mut state = self.state.lock().unwrap();
+    let output_end = output_start + output.len() as u64;
+    let pending_end = state.pending_start + state.pending.len() as u64;
+    let overlap_start = state.pending_start.max(output_start);
+    let overlap_end = pending_end.min(output_end);
+    if overlap_start < overlap_end {
+      let pending_offset = (overlap_start - state.pending_start) as usize;
+      let output_offset = (overlap_start - output_start) as usize;
+      let len = (overlap_end - overlap_start) as usize;
+      for (out, sample) in output[output_offset..output_offset + len]
+        .iter_mut()
+        .zip(&state.pending[pending_offset..pending_offset + len])
+      {
+        *out += sample;
+      }
+    }
+    if output_end > state.pending_start {
+      let consumed = ((output_end - state.pending_start) as usize).min(state.pending.len());
+      state.pending.drain(..consumed);
+      state.pending_start = output_end;
+    }
+  }
+
+  fn synthesise(
+    &self,
+    buffer: &Buffer,
+    start: u64,
+    sample_rate: u32,
+    gpu: GpuMode,
+  ) -> Result<(), ErrorMessage> {
+    let buffer_reader = buffer.map_readable().map_err(|_| {
+      gstreamer::error_msg!(gstreamer::StreamError::Failed, ["Failed to map buffer"])
+    })?;
+    let text = str::from_utf8(buffer_reader.as_slice()).map_err(|e| {
+      gstreamer::error_msg!(gstreamer::StreamError::Decode, ["Invalid UTF-8: {}", e])
+    })?;
+    gstreamer::debug!(
+      CAT,
+      "synthesise(): synthesising at sample {}: {}",
+      start,
+      text
+    );
+    let settings = self.settings.lock().unwrap().clone();
+
+    let (audio, model_sample_rate) = python::with_gil(|py| {
+      let mut synth = self.synth.lock().unwrap();
+      if synth.is_none() {
+        let model = settings.model.as_deref().unwrap_or(DEFAULT_MODEL);
+        gstreamer::debug!(CAT, "synthesise(): loading model {}", model);
+        let device = python::gpu_device(py, gpu)?;
+        *synth = Some(python::load_tts(py, model, device, None)?.into());
+      }
+      let synth = synth.as_ref().unwrap().as_ref(py);
+      let audio = python::Kwargs::new(py)
+        .set("text", text)
+        .and_then(|kwargs| kwargs.set_some("speaker", settings.speaker.as_deref()))
+        .and_then(|kwargs| kwargs.set_some("language", settings.language.as_deref()))
+        .and_then(|kwargs| synth.call_method("tts", (), Some(kwargs.into_dict())))
+        .and_then(python::extract_audio)
+        .map_err(|e| PyBridgeError::from_py(py, &e))?;
+      let model_sample_rate = python::output_sample_rate(synth)?;
+      Ok((audio, model_sample_rate as u32))
+    })
+    .map_err(|e: PyBridgeError| {
+      gstreamer::warning!(
+        CAT,
+        "synthesise(): failed to synthesise text:\n{}",
+        e.traceback()
+      );
+      e.to_error_message(
+        gstreamer::LibraryError::Failed,
+        &format!("Failed to synthesise text on pad {}", self.obj().name()),
+      )
+    })?;
+
+    let audio = dsp::resample_linear(&audio, model_sample_rate, sample_rate);
+    let mut state = self.state.lock().unwrap();
+    let pending_end = state.pending_start + state.pending.len() as u64;
+    if state.pending.is_empty() {
+      state.pending_start = start;
+    }
+    else if start > pending_end {
+      let len = (start - state.pending_start) as usize;
+      state.pending.resize(len, 0.0);
+    }
+    state.pending.extend_from_slice(&audio);
+    Ok(())
+  }
+}
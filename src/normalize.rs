@@ -0,0 +1,23 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// The byte order mark, which some editors and Windows tools put at the start of UTF-8 text.
+const BYTE_ORDER_MARK: char = '\u{feff}';
+
+/// Cleans up text for the model: removes byte order marks, composes characters to Unicode NFC so
+/// that e.g. an `e` followed by a combining acute accent becomes `é`, and collapses runs of
+/// whitespace, including line breaks, into single spaces.
+pub fn normalize(text: &str) -> String {
+  text
+    .chars()
+    .filter(|c| *c != BYTE_ORDER_MARK)
+    .nfc()
+    .collect::<String>()
+    .split_whitespace()
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// Removes a leading byte order mark, so that the text can be parsed as JSON or CSV.
+pub fn strip_byte_order_mark(text: &str) -> &str {
+  text.strip_prefix(BYTE_ORDER_MARK).unwrap_or(text)
+}
@@ -0,0 +1,229 @@
+//! Synthesis with VITS models exported to ONNX by Coqui, run with tract, so that a single voice
+//! can be deployed without Coqui TTS and PyTorch.
+//!
+//! Only models whose text is made of characters are supported, as phonemising text needs espeak.
+//! The model's Coqui `config.json` must be next to the `.onnx` file, for its characters and
+//! sample rate.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use serde_json::Value;
+
+/// The noise added to the VITS decoder's input, Coqui's default.
+const NOISE_SCALE: f32 = 0.667;
+/// The noise added to the VITS duration predictor's input, Coqui's default.
+const NOISE_SCALE_DURATION: f32 = 0.8;
+
+/// Turns text into the token ids the model was trained with, as Coqui's tokenizer does for
+/// models that don't use phonemes.
+#[derive(Debug)]
+struct Vocabulary {
+  ids: HashMap<char, i64>,
+  blank: Option<i64>,
+  bos_eos: Option<(i64, i64)>,
+  lowercase: bool,
+}
+
+impl Vocabulary {
+  fn from_config(config: &Value) -> Result<Self, String> {
+    if config["use_phonemes"].as_bool().unwrap_or(false) {
+      return Err("models that use phonemes aren't supported".into());
+    }
+    let characters = &config["characters"];
+    let string = |name: &str| characters[name].as_str().filter(|s| !s.is_empty());
+    let mut letters: Vec<char> = string("characters")
+      .ok_or("the config has no characters")?
+      .chars()
+      .collect();
+    if characters["is_unique"].as_bool().unwrap_or(true) {
+      letters.sort_unstable();
+      letters.dedup();
+    }
+    else if characters["is_sorted"].as_bool().unwrap_or(true) {
+      letters.sort_unstable();
+    }
+    // Coqui puts the special tokens first and the punctuation last.
+    let mut vocabulary: Vec<String> = ["pad", "eos", "bos", "blank"]
+      .into_iter()
+      .filter_map(|name| string(name).map(str::to_owned))
+      .collect();
+    vocabulary.extend(letters.into_iter().map(String::from));
+    vocabulary.extend(
+      string("punctuations")
+        .unwrap_or_default()
+        .chars()
+        .map(String::from),
+    );
+    let id = |token: Option<&str>| {
+      token.and_then(|token| {
+        vocabulary
+          .iter()
+          .position(|t| t == token)
+          .map(|id| id as i64)
+      })
+    };
+    let blank = id(string("blank")).filter(|_| config["add_blank"].as_bool().unwrap_or(false));
+    let bos_eos = match (id(string("bos")), id(string("eos"))) {
+      (Some(bos), Some(eos)) if config["enable_eos_bos_chars"].as_bool().unwrap_or(false) => {
+        Some((bos, eos))
+      },
+      _ => None,
+    };
+    let ids: HashMap<char, i64> = vocabulary
+      .iter()
+      .enumerate()
+      .filter_map(|(id, token)| {
+        let mut chars = token.chars();
+        match (chars.next(), chars.next()) {
+          (Some(c), None) => Some((c, id as i64)),
+          _ => None,
+        }
+      })
+      .collect();
+    let lowercase = !ids.keys().any(|c| c.is_uppercase());
+    Ok(Self {
+      ids,
+      blank,
+      bos_eos,
+      lowercase,
+    })
+  }
+
+  /// The token ids of text, leaving out characters the model doesn't know.
+  fn encode(&self, text: &str) -> Vec<i64> {
+    let text = if self.lowercase {
+      text.to_lowercase()
+    }
+    else {
+      text.to_owned()
+    };
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut ids: Vec<i64> = text
+      .chars()
+      .filter_map(|c| self.ids.get(&c).copied())
+      .collect();
+    if let Some(blank) = self.blank {
+      ids = std::iter::once(blank)
+        .chain(ids.into_iter().flat_map(|id| [id, blank]))
+        .collect();
+    }
+    if let Some((bos, eos)) = self.bos_eos {
+      ids.insert(0, bos);
+      ids.push(eos);
+    }
+    ids
+  }
+}
+
+pub struct OnnxModel {
+  plan: runtime::Plan,
+  /// Whether the model takes a speaker id, as multi-speaker models do.
+  multi_speaker: bool,
+  vocabulary: Vocabulary,
+  sample_rate: u64,
+}
+
+impl OnnxModel {
+  /// Loads an ONNX model along with the `config.json` in the same directory.
+  pub fn load(path: &Path) -> Result<Self, String> {
+    let config_path = path.with_file_name("config.json");
+    let config: Value = fs::read_to_string(&config_path)
+      .map_err(|e| e.to_string())
+      .and_then(|config| serde_json::from_str(&config).map_err(|e| e.to_string()))
+      .map_err(|e| format!("failed to read {}: {}", config_path.display(), e))?;
+    let vocabulary = Vocabulary::from_config(&config)?;
+    let sample_rate = config["audio"]["sample_rate"]
+      .as_u64()
+      .ok_or("the config has no sample rate")?;
+    let (plan, inputs) = runtime::load(path)?;
+    Ok(Self {
+      plan,
+      multi_speaker: inputs > 3,
+      vocabulary,
+      sample_rate,
+    })
+  }
+
+  pub fn sample_rate(&self) -> u64 {
+    self.sample_rate
+  }
+
+  /// Synthesises text at a speed relative to the model's, as `speaker` if the model has several.
+  pub fn synthesise(&self, text: &str, speed: f64, speaker: i64) -> Result<Vec<f32>, String> {
+    let ids = self.vocabulary.encode(text);
+    if ids.is_empty() {
+      return Ok(vec![]);
+    }
+    let scales = [NOISE_SCALE, (1.0 / speed) as f32, NOISE_SCALE_DURATION];
+    runtime::run(
+      &self.plan,
+      &ids,
+      scales,
+      self.multi_speaker.then_some(speaker),
+    )
+  }
+}
+
+#[cfg(feature = "onnx")]
+mod runtime {
+  use std::path::Path;
+
+  use tract_onnx::prelude::*;
+
+  pub type Plan = TypedRunnableModel<TypedModel>;
+
+  /// Loads and optimises a model, returning it and how many inputs it has.
+  pub fn load(path: &Path) -> Result<(Plan, usize), String> {
+    let model = tract_onnx::onnx()
+      .model_for_path(path)
+      .and_then(|model| model.into_optimized())
+      .map_err(|e| format!("failed to load {}: {}", path.display(), e))?;
+    let inputs = model.inputs.len();
+    let plan = model.into_runnable().map_err(|e| e.to_string())?;
+    Ok((plan, inputs))
+  }
+
+  /// Runs a model exported by Coqui's `export_onnx()` on token ids.
+  pub fn run(
+    plan: &Plan,
+    ids: &[i64],
+    scales: [f32; 3],
+    speaker: Option<i64>,
+  ) -> Result<Vec<f32>, String> {
+    let run = || -> TractResult<Vec<f32>> {
+      let mut inputs: TVec<TValue> = tvec!(
+        Tensor::from_shape(&[1, ids.len()], ids)?.into(),
+        tensor1(&[ids.len() as i64]).into(),
+        tensor1(&scales).into(),
+      );
+      if let Some(speaker) = speaker {
+        inputs.push(tensor1(&[speaker]).into());
+      }
+      let outputs = plan.run(inputs)?;
+      Ok(outputs[0].as_slice::<f32>()?.to_vec())
+    };
+    run().map_err(|e| e.to_string())
+  }
+}
+
+#[cfg(not(feature = "onnx"))]
+mod runtime {
+  use std::path::Path;
+
+  pub type Plan = ();
+
+  const NOT_BUILT: &str = "gst-coquitts was built without the `onnx` feature";
+
+  pub fn load(_path: &Path) -> Result<(Plan, usize), String> {
+    Err(NOT_BUILT.into())
+  }
+
+  pub fn run(
+    _plan: &Plan,
+    _ids: &[i64],
+    _scales: [f32; 3],
+    _speaker: Option<i64>,
+  ) -> Result<Vec<f32>, String> {
+    Err(NOT_BUILT.into())
+  }
+}
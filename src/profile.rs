@@ -0,0 +1,125 @@
+use std::{
+  cell::RefCell,
+  mem,
+  time::{Duration, Instant},
+};
+
+use gstreamer::ClockTime;
+
+/// A stage of synthesising a text buffer, timed when profiling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+  /// Language detection and text normalisation.
+  Preprocess,
+  /// Waiting to acquire the GIL.
+  GilWait,
+  /// Running the model, including loading it if necessary.
+  Inference,
+  /// Converting the model's output to samples.
+  Conversion,
+  /// Pitch shifting, gain and time stretching.
+  Dsp,
+  /// Copying samples into buffers.
+  BufferCopy,
+}
+
+impl Stage {
+  const ALL: [Stage; 6] = [
+    Stage::Preprocess,
+    Stage::GilWait,
+    Stage::Inference,
+    Stage::Conversion,
+    Stage::Dsp,
+    Stage::BufferCopy,
+  ];
+
+  fn name(self) -> &'static str {
+    match self {
+      Stage::Preprocess => "preprocess",
+      Stage::GilWait => "gil-wait",
+      Stage::Inference => "inference",
+      Stage::Conversion => "conversion",
+      Stage::Dsp => "dsp",
+      Stage::BufferCopy => "buffer-copy",
+    }
+  }
+}
+
+/// How long each stage took while synthesising one text buffer.
+///
+/// Stages are timed exclusively: time spent in a stage nested in another, such as converting the
+/// output while running the model, only counts towards the inner one.
+#[derive(Debug, Default)]
+pub struct Profile {
+  durations: [Duration; Stage::ALL.len()],
+  /// The time taken by stages nested in the one currently being timed.
+  nested: Duration,
+}
+
+impl Profile {
+  pub fn total(&self) -> Duration {
+    self.durations.iter().sum()
+  }
+
+  /// The durations as a structure, with a field of nanoseconds per stage.
+  pub fn to_structure(&self) -> gstreamer::Structure {
+    let mut structure = gstreamer::Structure::new_empty("coquitts-profile");
+    for stage in Stage::ALL {
+      structure.set(stage.name(), to_clock_time(self.durations[stage as usize]));
+    }
+    structure.set("total", to_clock_time(self.total()));
+    structure
+  }
+}
+
+thread_local! {
+  static CURRENT: RefCell<Option<Profile>> = const { RefCell::new(None) };
+}
+
+/// Starts profiling on the current thread.
+pub fn start() {
+  CURRENT.with(|current| *current.borrow_mut() = Some(Profile::default()));
+}
+
+/// Stops profiling on the current thread, returning the profile if it was started.
+pub fn finish() -> Option<Profile> {
+  CURRENT.with(|current| current.borrow_mut().take())
+}
+
+/// Runs `f`, timing it as `stage` if profiling on the current thread.
+pub fn time<R>(stage: Stage, f: impl FnOnce() -> R) -> R {
+  let outer = CURRENT.with(|current| {
+    current
+      .borrow_mut()
+      .as_mut()
+      .map(|profile| mem::take(&mut profile.nested))
+  });
+  let outer = match outer {
+    Some(outer) => outer,
+    None => return f(),
+  };
+  let started = Instant::now();
+  let result = f();
+  let elapsed = started.elapsed();
+  CURRENT.with(|current| {
+    if let Some(profile) = current.borrow_mut().as_mut() {
+      let nested = mem::replace(&mut profile.nested, outer + elapsed);
+      profile.durations[stage as usize] += elapsed.saturating_sub(nested);
+    }
+  });
+  result
+}
+
+/// Records time already spent in `stage`, for stages that can't be wrapped in a closure.
+pub fn record(stage: Stage, elapsed: Duration) {
+  CURRENT.with(|current| {
+    if let Some(profile) = current.borrow_mut().as_mut() {
+      profile.durations[stage as usize] += elapsed;
+      profile.nested += elapsed;
+    }
+  });
+}
+
+fn to_clock_time(duration: Duration) -> ClockTime {
+  ClockTime::from_nseconds(duration.as_nanos() as u64)
+}
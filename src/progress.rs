@@ -0,0 +1,63 @@
+use std::{
+  collections::HashSet,
+  fs::{File, OpenOptions},
+  io::{self, BufRead, BufReader, Write},
+  path::Path,
+};
+
+/// A record of which input buffers have been synthesised, so that an interrupted job can skip
+/// them when it is restarted.
+///
+/// The file has one buffer offset per line and is appended to as buffers are completed.
+#[derive(Debug)]
+pub struct Progress {
+  file: File,
+  done: HashSet<u64>,
+}
+
+impl Progress {
+  pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let path = path.as_ref();
+    let mut done = HashSet::new();
+    // The length of the file up to the end of its last complete line.
+    let mut complete = 0;
+    if path.exists() {
+      let mut reader = BufReader::new(File::open(path)?);
+      let mut line = String::new();
+      while reader.read_line(&mut line)? > 0 {
+        // A partially written last line from an interrupted run is ignored, as a truncated
+        // offset would still parse.
+        if !line.ends_with('\n') {
+          break;
+        }
+        complete += line.len() as u64;
+        if let Ok(offset) = line.trim().parse() {
+          done.insert(offset);
+        }
+        line.clear();
+      }
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    // Cut off the partial line, or the next offset would be appended to it.
+    if file.metadata()?.len() > complete {
+      file.set_len(complete)?;
+    }
+    Ok(Self { file, done })
+  }
+
+  pub fn len(&self) -> usize {
+    self.done.len()
+  }
+
+  pub fn is_done(&self, offset: u64) -> bool {
+    self.done.contains(&offset)
+  }
+
+  pub fn mark_done(&mut self, offset: u64) -> io::Result<()> {
+    if self.done.insert(offset) {
+      writeln!(self.file, "{}", offset)?;
+      self.file.sync_data()?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,66 @@
+/// Words that start a question in English.
+const QUESTION_WORDS: &[&str] = &[
+  "what", "where", "when", "why", "who", "whom", "whose", "which", "how", "is", "are", "am", "was",
+  "were", "does", "did", "can", "could", "would", "will", "should", "shall", "isn't", "aren't",
+  "wasn't", "weren't", "doesn't", "didn't", "can't", "couldn't", "wouldn't", "won't", "shouldn't",
+];
+/// Words that only start a question when followed by a pronoun, as "do" also starts commands.
+const AUXILIARIES: &[&str] = &["do", "don't"];
+const PRONOUNS: &[&str] = &["i", "you", "we", "they"];
+/// Interjections followed by a comma at the start of an utterance.
+const INTERJECTIONS: &[&str] = &["yes", "yeah", "okay", "ok", "oh", "hi", "hello", "hey"];
+/// Conjunctions preceded by a comma.
+const CLAUSE_CONJUNCTIONS: &[&str] = &["but", "because", "although", "however"];
+
+/// Adds punctuation to text that has none, as speech recognisers produce, so that it isn't
+/// spoken in a monotone: the first letter is capitalised and a full stop is added at the end.
+/// English text also gets a question mark instead if it starts like a question, commas after
+/// interjections and before conjunctions between clauses, and a capital "I". Text that already
+/// has any punctuation is left as it is.
+pub fn restore(text: &str, english: bool) -> String {
+  let trimmed = text.trim();
+  if trimmed.is_empty()
+    || trimmed.contains(['.', '!', '?', ',', ';', ':', '。', '？', '！', '，'])
+  {
+    return text.to_owned();
+  }
+  let words: Vec<&str> = trimmed.split_whitespace().collect();
+  let lower: Vec<String> = words.iter().map(|word| word.to_lowercase()).collect();
+  let interjection = english && words.len() > 1 && INTERJECTIONS.contains(&lower[0].as_str());
+  let mut restored = String::with_capacity(trimmed.len() + 4);
+  for (i, word) in words.iter().enumerate() {
+    if i > 0 {
+      if english && i > 1 && CLAUSE_CONJUNCTIONS.contains(&lower[i].as_str()) {
+        restored.push(',');
+      }
+      restored.push(' ');
+    }
+    if i == 0 || (english && (lower[i] == "i" || lower[i].starts_with("i'"))) {
+      restored.extend(capitalise(word));
+    }
+    else {
+      restored.push_str(word);
+    }
+    if i == 0 && interjection {
+      restored.push(',');
+    }
+  }
+  let first = if interjection { 1 } else { 0 };
+  let question = english
+    && (QUESTION_WORDS.contains(&lower[first].as_str())
+      || (AUXILIARIES.contains(&lower[first].as_str())
+        && lower
+          .get(first + 1)
+          .is_some_and(|next| PRONOUNS.contains(&next.as_str()))));
+  restored.push(if question { '?' } else { '.' });
+  restored
+}
+
+fn capitalise(word: &str) -> impl Iterator<Item = char> + '_ {
+  let mut chars = word.chars();
+  chars
+    .next()
+    .into_iter()
+    .flat_map(char::to_uppercase)
+    .chain(chars)
+}
@@ -0,0 +1,515 @@
+//! The bridge between the elements and the embedded Python interpreter running Coqui TTS.
+
+use std::{
+  env, fmt, fs,
+  path::Path,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+  },
+  time::{Duration, Instant},
+};
+
+use gstreamer::ErrorMessage;
+use pyo3::{
+  exceptions::{PyImportError, PyKeyError, PyUnicodeError},
+  types::{PyCFunction, PyDict, PyModule},
+  PyAny, PyErr, PyObject, PyResult, Python, ToPyObject,
+};
+
+use crate::filter::GpuMode;
+
+pub const CPU_DEVICE: &str = "cpu";
+pub const CUDA_DEVICE: &str = "cuda";
+pub const MPS_DEVICE: &str = "mps";
+
+/// Called with the number of bytes downloaded so far and the total, if known, while a model is
+/// being downloaded.
+pub type DownloadProgress = Box<dyn Fn(u64, Option<u64>) + Send + Sync>;
+
+/// An error from calling into Python, carrying what's needed to report it on the bus.
+#[derive(Debug, Clone)]
+pub enum PyBridgeError {
+  /// A Python package couldn't be imported.
+  Import {
+    module: String,
+    executable: String,
+    traceback: String,
+  },
+  /// Python code raised an exception.
+  Exception { traceback: String },
+  /// A Python value didn't have the type that was expected.
+  Extract {
+    what: &'static str,
+    traceback: String,
+  },
+}
+
+pub type BridgeResult<T> = Result<T, PyBridgeError>;
+
+impl PyBridgeError {
+  pub fn from_py(py: Python, e: &PyErr) -> Self {
+    Self::Exception {
+      traceback: format_traceback(py, e),
+    }
+  }
+
+  fn import(py: Python, module: &str, e: &PyErr) -> Self {
+    if !e.is_instance_of::<PyImportError>(py) {
+      return Self::from_py(py, e);
+    }
+    let executable = PyModule::import(py, "sys")
+      .and_then(|sys| sys.getattr("executable"))
+      .and_then(|executable| executable.extract::<String>())
+      .unwrap_or_else(|_| "<unknown>".into());
+    Self::Import {
+      module: module.into(),
+      executable,
+      traceback: format_traceback(py, e),
+    }
+  }
+
+  fn extract(py: Python, what: &'static str, e: &PyErr) -> Self {
+    Self::Extract {
+      what,
+      traceback: format_traceback(py, e),
+    }
+  }
+
+  pub fn traceback(&self) -> &str {
+    match self {
+      Self::Import { traceback, .. }
+      | Self::Exception { traceback }
+      | Self::Extract { traceback, .. } => traceback,
+    }
+  }
+
+  /// Converts the error to an element error, with `message` as the user-facing text unless the
+  /// error has a more helpful one of its own.
+  pub fn to_error_message(&self, error: gstreamer::LibraryError, message: &str) -> ErrorMessage {
+    match self {
+      Self::Import { .. } => gstreamer::error_msg!(
+        gstreamer::LibraryError::Init,
+        ("{}", self),
+        ["{}", self.traceback()]
+      ),
+      _ => gstreamer::error_msg!(error, ("{}", message), ["{}", self.traceback()]),
+    }
+  }
+}
+
+impl fmt::Display for PyBridgeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Import {
+        module, executable, ..
+      } => {
+        let package = module.split('.').next().unwrap_or(module);
+        write!(
+          f,
+          "Python package '{}' not found in interpreter {}",
+          package, executable
+        )?;
+        if package == "TTS" {
+          write!(f, "; install coqui-tts")?;
+        }
+        Ok(())
+      },
+      Self::Exception { traceback } => {
+        write!(f, "{}", traceback.lines().last().unwrap_or_default())
+      },
+      Self::Extract { what, traceback } => write!(
+        f,
+        "unexpected {}: {}",
+        what,
+        traceback.lines().last().unwrap_or_default()
+      ),
+    }
+  }
+}
+
+impl std::error::Error for PyBridgeError {}
+
+/// Prepares the interpreter, setting `PYTHONHOME` first if given. The interpreter is shared by
+/// the whole process, so `PYTHONHOME` can only take effect if it hasn't been initialised yet;
+/// returns false if it was ignored for that reason.
+pub fn initialise(python_home: Option<&str>) -> bool {
+  let mut applied = true;
+  if let Some(python_home) = python_home {
+    // SAFETY: Py_IsInitialized can be called at any time, even before initialisation.
+    if unsafe { pyo3::ffi::Py_IsInitialized() } == 0 {
+      env::set_var("PYTHONHOME", python_home);
+    }
+    else {
+      applied = false;
+    }
+  }
+  pyo3::prepare_freethreaded_python();
+  applied
+}
+
+/// Runs `f` holding the GIL, initialising the interpreter first if necessary.
+pub fn with_gil<R>(f: impl FnOnce(Python) -> R) -> R {
+  pyo3::prepare_freethreaded_python();
+  Python::with_gil(f)
+}
+
+/// How often an element has acquired the GIL, and how long it has spent waiting for it and
+/// holding it. Waiting a long time relative to holding means other Python users in the process,
+/// such as other elements, are slowing it down.
+#[derive(Debug, Default)]
+pub struct GilStats {
+  acquisitions: AtomicU64,
+  wait_ns: AtomicU64,
+  hold_ns: AtomicU64,
+}
+
+impl GilStats {
+  /// Runs `f` holding the GIL like `with_gil`, counting the time spent waiting for and holding it.
+  pub fn with_gil<R>(&self, f: impl FnOnce(Python) -> R) -> R {
+    let acquiring = Instant::now();
+    with_gil(|py| {
+      let acquired = Instant::now();
+      let result = f(py);
+      self.acquisitions.fetch_add(1, Ordering::Relaxed);
+      self.wait_ns.fetch_add(
+        (acquired - acquiring).as_nanos() as u64,
+        Ordering::Relaxed,
+      );
+      self
+        .hold_ns
+        .fetch_add(acquired.elapsed().as_nanos() as u64, Ordering::Relaxed);
+      result
+    })
+  }
+
+  pub fn acquisitions(&self) -> u64 {
+    self.acquisitions.load(Ordering::Relaxed)
+  }
+
+  pub fn wait(&self) -> Duration {
+    Duration::from_nanos(self.wait_ns.load(Ordering::Relaxed))
+  }
+
+  pub fn hold(&self) -> Duration {
+    Duration::from_nanos(self.hold_ns.load(Ordering::Relaxed))
+  }
+}
+
+pub fn import<'py>(py: Python<'py>, module: &str) -> BridgeResult<&'py PyModule> {
+  PyModule::import(py, module).map_err(|e| PyBridgeError::import(py, module, &e))
+}
+
+/// Adds the site-packages directories of a virtualenv to the module search path.
+pub fn add_venv(py: Python, venv: &Path) -> BridgeResult<Vec<String>> {
+  let site = import(py, "site")?;
+  let dirs = venv_site_packages(venv);
+  for dir in &dirs {
+    site
+      .call_method1("addsitedir", (dir.as_str(),))
+      .map_err(|e| PyBridgeError::from_py(py, &e))?;
+  }
+  Ok(dirs)
+}
+
+/// The directory the running interpreter was installed in, `sys.prefix`, which `PYTHONHOME`
+/// sets.
+pub fn prefix(py: Python) -> Option<String> {
+  import(py, "sys")
+    .ok()?
+    .getattr("prefix")
+    .ok()?
+    .extract()
+    .ok()
+}
+
+/// The file a module was loaded from, if it has already been imported. Imported modules are
+/// shared by everything in the process that uses Python, so whichever site-packages directory a
+/// module was first found in is the one every element gets.
+pub fn imported_from(py: Python, module: &str) -> Option<String> {
+  import(py, "sys")
+    .ok()?
+    .getattr("modules")
+    .ok()?
+    .get_item(module)
+    .ok()?
+    .getattr("__file__")
+    .ok()?
+    .extract()
+    .ok()
+}
+
+/// Finds the site-packages directories of a virtualenv, for both the Unix
+/// (`lib/pythonX.Y/site-packages`) and Windows (`Lib/site-packages`) layouts.
+fn venv_site_packages(venv: &Path) -> Vec<String> {
+  let mut dirs = vec![];
+  if let Ok(entries) = fs::read_dir(venv.join("lib")) {
+    for entry in entries.flatten() {
+      let site_packages = entry.path().join("site-packages");
+      if entry.file_name().to_string_lossy().starts_with("python") && site_packages.is_dir() {
+        dirs.push(site_packages.to_string_lossy().into_owned());
+      }
+    }
+  }
+  let site_packages = venv.join("Lib").join("site-packages");
+  if site_packages.is_dir() {
+    dirs.push(site_packages.to_string_lossy().into_owned());
+  }
+  dirs
+}
+
+/// Loads a Coqui `TTS.api.TTS` object for a TTS or voice conversion model, on a torch device
+/// such as `cpu` or `cuda`.
+pub fn load_tts<'py>(
+  py: Python<'py>,
+  model: &str,
+  device: &str,
+  progress: Option<DownloadProgress>,
+) -> BridgeResult<&'py PyAny> {
+  let api = import(py, "TTS.api")?;
+  // The progress bar is only shown if there's somewhere other than the terminal to show it.
+  let hooked = progress.and_then(|progress| hook_download_progress(py, progress).ok());
+  let kwargs = Kwargs::new(py)
+    .set("model_name", model)
+    .and_then(|kwargs| kwargs.set("progress_bar", hooked.is_some()))
+    .map_err(|e| PyBridgeError::from_py(py, &e))?;
+  let tts = api.call_method("TTS", (), Some(kwargs.into_dict()));
+  if let Some((manage, tqdm)) = hooked {
+    let _ = manage.setattr("tqdm", tqdm);
+  }
+  let tts = tts.map_err(|e| PyBridgeError::from_py(py, &e))?;
+  if device == CPU_DEVICE {
+    return Ok(tts);
+  }
+  tts
+    .call_method1("to", (device,))
+    .map_err(|e| PyBridgeError::from_py(py, &e))
+}
+
+/// Replaces the tqdm progress bar that Coqui's model manager shows while downloading with one
+/// that calls `progress`, returning the model manager module and the original to put back.
+fn hook_download_progress<'py>(
+  py: Python<'py>,
+  progress: DownloadProgress,
+) -> PyResult<(&'py PyModule, &'py PyAny)> {
+  let manage = PyModule::import(py, "TTS.utils.manage")?;
+  let tqdm = manage.getattr("tqdm")?;
+  let progress = Arc::new(progress);
+  // Called like `tqdm(total=..., unit="iB", unit_scale=True)` for each file.
+  let factory = PyCFunction::new_closure(py, None, None, move |args, kwargs| {
+    let py = args.py();
+    let total = kwargs
+      .and_then(|kwargs| kwargs.get_item("total"))
+      .and_then(|total| total.extract::<u64>().ok())
+      .filter(|total| *total > 0);
+    let downloaded = AtomicU64::new(0);
+    let progress = progress.clone();
+    let update = PyCFunction::new_closure(py, None, None, move |args, _| -> PyResult<()> {
+      let bytes: u64 = args.get_item(0)?.extract()?;
+      progress(
+        downloaded.fetch_add(bytes, Ordering::Relaxed) + bytes,
+        total,
+      );
+      Ok(())
+    })?;
+    let close = PyCFunction::new_closure(py, None, None, |_, _| ())?;
+    let bar = PyModule::import(py, "types")?
+      .getattr("SimpleNamespace")?
+      .call(
+        (),
+        Some(
+          Kwargs::new(py)
+            .set("update", update)?
+            .set("close", close)?
+            .into_dict(),
+        ),
+      )?;
+    PyResult::<PyObject>::Ok(bar.into())
+  })?;
+  manage.setattr("tqdm", factory)?;
+  Ok((manage, tqdm))
+}
+
+/// The torch device for a `use-gpu` property, the best one available in auto mode.
+pub fn gpu_device(py: Python, gpu: GpuMode) -> BridgeResult<&'static str> {
+  match gpu {
+    GpuMode::Off => Ok(CPU_DEVICE),
+    GpuMode::On => Ok(CUDA_DEVICE),
+    GpuMode::Auto => best_device(py),
+  }
+}
+
+/// The best torch device available: CUDA, then Apple's Metal Performance Shaders, then the CPU.
+pub fn best_device(py: Python) -> BridgeResult<&'static str> {
+  let torch = import(py, "torch")?;
+  let cuda = torch
+    .getattr("cuda")
+    .and_then(|cuda| cuda.call_method0("is_available")?.is_true())
+    .map_err(|e| PyBridgeError::from_py(py, &e))?;
+  if cuda {
+    return Ok(CUDA_DEVICE);
+  }
+  // Older versions of torch don't have the MPS backend at all.
+  let mps = torch
+    .getattr("backends")
+    .and_then(|backends| {
+      backends
+        .getattr("mps")?
+        .call_method0("is_available")?
+        .is_true()
+    })
+    .unwrap_or(false);
+  Ok(if mps { MPS_DEVICE } else { CPU_DEVICE })
+}
+
+/// Keyword arguments for a Python call.
+pub struct Kwargs<'py>(&'py PyDict);
+
+impl<'py> Kwargs<'py> {
+  pub fn new(py: Python<'py>) -> Self {
+    Self(PyDict::new(py))
+  }
+
+  pub fn set(self, key: &str, value: impl ToPyObject) -> PyResult<Self> {
+    self.0.set_item(key, value)?;
+    Ok(self)
+  }
+
+  /// Sets `key` only if there is a value, leaving the callee's default otherwise.
+  pub fn set_some(self, key: &str, value: Option<impl ToPyObject>) -> PyResult<Self> {
+    match value {
+      Some(value) => self.set(key, value),
+      None => Ok(self),
+    }
+  }
+
+  /// Sets every key of a JSON object, overriding any already set.
+  pub fn update_from_json(self, json: &str) -> PyResult<Self> {
+    let extra: &PyDict = PyModule::import(self.0.py(), "json")?
+      .call_method1("loads", (json,))?
+      .downcast()?;
+    self.0.update(extra.as_mapping())?;
+    Ok(self)
+  }
+
+  pub fn into_dict(self) -> &'py PyDict {
+    self.0
+  }
+}
+
+/// Extracts mono audio samples from a list, numpy array or torch tensor.
+pub fn extract_audio(value: &PyAny) -> PyResult<Vec<f32>> {
+  if let Ok(samples) = value.extract::<Vec<f32>>() {
+    return Ok(samples);
+  }
+  let value = if value.hasattr("cpu")? {
+    value.call_method0("cpu")?.call_method0("numpy")?
+  }
+  else {
+    value
+  };
+  PyModule::import(value.py(), "numpy")?
+    .call_method1("asarray", (value, "float32"))?
+    .call_method0("flatten")?
+    .call_method0("tolist")?
+    .extract()
+}
+
+/// Reads a boolean attribute, such as a model's `is_multi_speaker`.
+pub fn bool_attr(obj: &PyAny, name: &'static str) -> BridgeResult<bool> {
+  obj
+    .getattr(name)
+    .and_then(|value| value.is_true())
+    .map_err(|e| PyBridgeError::extract(obj.py(), name, &e))
+}
+
+/// The sample rate of the audio a `TTS.api.TTS` object produces.
+pub fn output_sample_rate(s: &PyAny) -> BridgeResult<u64> {
+  s.getattr("synthesizer")
+    .and_then(|synthesizer| synthesizer.getattr("output_sample_rate"))
+    .and_then(|sample_rate| sample_rate.extract())
+    .map_err(|e| PyBridgeError::extract(s.py(), "output_sample_rate", &e))
+}
+
+/// The paths of the `config.json` files deciding the sample rate of a Coqui model, found without
+/// loading it: the model's own, then its default vocoder's if it has one. The model is
+/// downloaded if it hasn't been already.
+pub fn model_config_paths(py: Python, model: &str) -> BridgeResult<Vec<String>> {
+  let manage = import(py, "TTS.utils.manage")?;
+  let paths = || -> PyResult<Vec<String>> {
+    let kwargs = Kwargs::new(py).set("progress_bar", false)?.into_dict();
+    let manager = manage.getattr("ModelManager")?.call((), Some(kwargs))?;
+    let (_, config, item): (&PyAny, Option<String>, &PyAny) = manager
+      .call_method1("download_model", (model,))?
+      .extract()?;
+    let mut paths: Vec<String> = config.into_iter().collect();
+    let vocoder: Option<String> = item.call_method1("get", ("default_vocoder",))?.extract()?;
+    if let Some(vocoder) = vocoder {
+      let (_, config, _): (&PyAny, Option<String>, &PyAny) = manager
+        .call_method1("download_model", (vocoder,))?
+        .extract()?;
+      paths.extend(config);
+    }
+    Ok(paths)
+  };
+  paths().map_err(|e| PyBridgeError::from_py(py, &e))
+}
+
+/// Formats a Python exception with its traceback, the way Python itself would print it.
+pub fn format_traceback(py: Python, e: &PyErr) -> String {
+  PyModule::import(py, "traceback")
+    .and_then(|traceback| {
+      traceback
+        .call_method1(
+          "format_exception",
+          (e.get_type(py), e.value(py), e.traceback(py)),
+        )?
+        .extract::<Vec<String>>()
+    })
+    .map(|lines| lines.concat().trim_end().to_owned())
+    .unwrap_or_else(|_| e.to_string())
+}
+
+/// Whether an exception looks like the model or its text cleaners choking on characters outside
+/// its alphabet, such as a `KeyError` looking one up.
+pub fn is_unsupported_character(py: Python, e: &PyErr) -> bool {
+  if e.is_instance_of::<PyKeyError>(py) || e.is_instance_of::<PyUnicodeError>(py) {
+    return true;
+  }
+  let message = e.value(py).to_string().to_lowercase();
+  ["character", "symbol", "vocabulary", "alphabet"]
+    .iter()
+    .any(|word| message.contains(word))
+}
+
+/// Whether an exception is torch running out of GPU memory, on CUDA or MPS.
+pub fn is_gpu_oom(py: Python, e: &PyErr) -> bool {
+  let oom_type = PyModule::import(py, "torch")
+    .and_then(|torch| torch.getattr("cuda")?.getattr("OutOfMemoryError"));
+  if let Ok(oom_type) = oom_type {
+    if e.is_instance(py, oom_type) {
+      return true;
+    }
+  }
+  // Older versions of torch, and the MPS backend, raise a plain RuntimeError.
+  let message = e.value(py).to_string();
+  message.contains("CUDA out of memory") || message.contains("MPS backend out of memory")
+}
+
+/// Releases the memory torch has cached on the GPU.
+pub fn empty_gpu_cache(py: Python) -> PyResult<()> {
+  let torch = PyModule::import(py, "torch")?;
+  torch.getattr("cuda")?.call_method0("empty_cache")?;
+  // Only newer versions of torch have the MPS backend.
+  if let Ok(mps) = torch.getattr("mps") {
+    if torch
+      .getattr("backends")?
+      .getattr("mps")?
+      .call_method0("is_available")?
+      .is_true()?
+    {
+      mps.call_method0("empty_cache")?;
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,51 @@
+use std::{fs, io, path::Path};
+
+use crate::{dsp, wav};
+
+/// A recording of ambience, such as room tone or background noise, that's looped under speech.
+///
+/// It's read from a WAV file of integer or floating point samples, mixed down to mono. Where the
+/// loop has got to is kept from one utterance to the next, so the ambience doesn't restart with
+/// each one.
+#[derive(Debug)]
+pub struct RoomTone {
+  samples: Vec<f32>,
+  sample_rate: u64,
+  position: usize,
+}
+
+impl RoomTone {
+  pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let (samples, sample_rate) = wav::decode(&fs::read(path)?)
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a supported WAV file"))?;
+    if samples.is_empty() {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "no audio"));
+    }
+    Ok(Self {
+      samples,
+      sample_rate,
+      position: 0,
+    })
+  }
+
+  /// Adds the next `samples.len()` samples of the loop, amplified by `gain_db` decibels, to
+  /// mono audio, clipping to [-1, 1]. The recording is resampled the first time it's mixed into
+  /// audio of a different sample rate.
+  pub fn mix(&mut self, samples: &mut [f32], sample_rate: u64, gain_db: f64) {
+    if sample_rate != self.sample_rate {
+      self.samples =
+        dsp::resample_linear(&self.samples, self.sample_rate as u32, sample_rate as u32);
+      self.position = self.position * sample_rate as usize / self.sample_rate as usize;
+      self.sample_rate = sample_rate;
+    }
+    if self.samples.is_empty() {
+      return;
+    }
+    let factor = 10f64.powf(gain_db / 20.0) as f32;
+    for sample in samples {
+      self.position %= self.samples.len();
+      *sample = (*sample + self.samples[self.position] * factor).clamp(-1.0, 1.0);
+      self.position += 1;
+    }
+  }
+}
@@ -0,0 +1,44 @@
+use regex::Regex;
+
+/// An ordered list of regex → replacement rules applied to text before synthesis.
+///
+/// Rules are parsed from a JSON array of `[pattern, replacement]` pairs, e.g.
+/// `[["\\bAPI\\b", "A P I"], ["&", " and "]]`. Replacements may reference capture groups using
+/// the `$1` / `${name}` syntax of the `regex` crate.
+#[derive(Debug, Clone, Default)]
+pub struct ReplacementRules {
+  source: Option<String>,
+  rules: Vec<(Regex, String)>,
+}
+
+impl ReplacementRules {
+  pub fn parse(source: &str) -> Result<Self, String> {
+    let pairs: Vec<(String, String)> =
+      serde_json::from_str(source).map_err(|e| format!("invalid replacement rules JSON: {}", e))?;
+    let rules = pairs
+      .into_iter()
+      .map(|(pattern, replacement)| {
+        Regex::new(&pattern)
+          .map(|regex| (regex, replacement))
+          .map_err(|e| format!("invalid replacement rule pattern {:?}: {}", pattern, e))
+      })
+      .collect::<Result<_, _>>()?;
+    Ok(Self {
+      source: Some(source.to_owned()),
+      rules,
+    })
+  }
+
+  pub fn source(&self) -> Option<&str> {
+    self.source.as_deref()
+  }
+
+  pub fn apply(&self, text: &str) -> String {
+    self
+      .rules
+      .iter()
+      .fold(text.to_owned(), |text, (regex, replacement)| {
+        regex.replace_all(&text, replacement.as_str()).into_owned()
+      })
+  }
+}
@@ -0,0 +1,54 @@
+use std::{
+  collections::BTreeMap,
+  ops::Bound,
+  sync::{Condvar, Mutex},
+};
+
+use once_cell::sync::Lazy;
+
+use crate::filter::Priority;
+
+/// Orders synthesis across all the elements in the process, which share the Python interpreter
+/// and usually a GPU: synthesis waits while any of a higher priority is running or waiting to
+/// run. Synthesis of the same priority runs concurrently.
+#[derive(Debug, Default)]
+struct Scheduler {
+  /// How much synthesis of each priority is running or waiting to run.
+  pending: Mutex<BTreeMap<Priority, usize>>,
+  cond: Condvar,
+}
+
+static SCHEDULER: Lazy<Scheduler> = Lazy::new(Scheduler::default);
+
+/// Permission to synthesise, until it's dropped.
+#[derive(Debug)]
+pub struct Permit {
+  priority: Priority,
+}
+
+impl Drop for Permit {
+  fn drop(&mut self) {
+    let mut pending = SCHEDULER.pending.lock().unwrap();
+    if let Some(count) = pending.get_mut(&self.priority) {
+      *count -= 1;
+      if *count == 0 {
+        pending.remove(&self.priority);
+      }
+    }
+    SCHEDULER.cond.notify_all();
+  }
+}
+
+/// Waits until there's no synthesis of a higher priority than `priority` running or waiting.
+pub fn acquire(priority: Priority) -> Permit {
+  let mut pending = SCHEDULER.pending.lock().unwrap();
+  *pending.entry(priority).or_default() += 1;
+  while pending
+    .range((Bound::Excluded(priority), Bound::Unbounded))
+    .next()
+    .is_some()
+  {
+    pending = SCHEDULER.cond.wait(pending).unwrap();
+  }
+  Permit { priority }
+}
@@ -0,0 +1,67 @@
+/// Abbreviations whose full stop doesn't end a sentence, in lower case without the final stop.
+const ABBREVIATIONS: &[&str] = &[
+  "mr", "mrs", "ms", "dr", "prof", "st", "jr", "sr", "vs", "etc", "e.g", "i.e", "inc", "ltd",
+  "co", "no", "fig", "approx", "mt", "cf", "dept", "est", "gen", "gov", "lt", "col", "sgt", "rev",
+  "u.s", "u.k", "a.m", "p.m",
+];
+
+/// Characters that can close a sentence after its final punctuation.
+const CLOSING: &[char] = &['"', '\'', '”', '’', ')', ']', '»'];
+
+/// Splits text into sentences at full stops, question and exclamation marks and ellipses followed
+/// by a space, and at full-width sentence punctuation. Full stops after abbreviations and
+/// initials, or followed by a lower-case word, don't end a sentence.
+pub fn sentences(text: &str) -> Vec<&str> {
+  let mut sentences = vec![];
+  let mut start = 0;
+  let mut chars = text.char_indices().peekable();
+  while let Some((i, c)) = chars.next() {
+    let full_width = matches!(c, '。' | '！' | '？');
+    if !full_width && !matches!(c, '.' | '!' | '?' | '…') {
+      continue;
+    }
+    // Take in repeated punctuation and closing quotes and brackets.
+    let mut end = i + c.len_utf8();
+    while let Some(&(j, next)) = chars.peek() {
+      if matches!(next, '.' | '!' | '?' | '…') || CLOSING.contains(&next) {
+        end = j + next.len_utf8();
+        chars.next();
+      }
+      else {
+        break;
+      }
+    }
+    let rest = &text[end..];
+    if !full_width && !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+      continue;
+    }
+    if c == '.' && !ends_sentence(&text[start..i], rest) {
+      continue;
+    }
+    let sentence = text[start..end].trim();
+    if !sentence.is_empty() {
+      sentences.push(sentence);
+    }
+    start = end;
+  }
+  let sentence = text[start..].trim();
+  if !sentence.is_empty() {
+    sentences.push(sentence);
+  }
+  sentences
+}
+
+/// Whether a full stop after `before` and followed by `after` ends a sentence.
+fn ends_sentence(before: &str, after: &str) -> bool {
+  let word = before
+    .rsplit(char::is_whitespace)
+    .next()
+    .unwrap_or_default()
+    .trim_start_matches(|c: char| !c.is_alphanumeric())
+    .to_lowercase();
+  let initial = word.chars().count() == 1 && word.chars().all(char::is_alphabetic);
+  if initial || ABBREVIATIONS.contains(&word.as_str()) {
+    return false;
+  }
+  !after.trim_start().starts_with(char::is_lowercase)
+}
@@ -0,0 +1,174 @@
+//! A client for Coqui's TTS server (`tts-server`, or an XTTS server with the same API), so that
+//! text can be synthesised without embedding Python in the process.
+
+use std::{
+  fmt::Write as _,
+  io::{self, BufRead, BufReader, Read, Write},
+  net::TcpStream,
+  time::Duration,
+};
+
+use crate::wav;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// The largest response accepted from the server, over an hour of 16-bit audio at 24kHz, so that
+/// a broken server can't make the process run out of memory.
+const MAX_RESPONSE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// What to synthesise, and in which voice.
+#[derive(Debug, Clone, Copy)]
+pub struct Request<'a> {
+  pub text: &'a str,
+  pub speaker: Option<&'a str>,
+  pub language: Option<&'a str>,
+}
+
+/// Synthesises text with the server at `url`, e.g. `http://localhost:5002`, returning mono
+/// samples and their sample rate.
+pub fn synthesise(url: &str, request: Request) -> io::Result<(Vec<f32>, u64)> {
+  let (host, prefix) = parse_url(url)?;
+  // The text goes in the body of a POST rather than the query string, as long texts would run
+  // into limits on the length of URLs.
+  let mut form = format!("text={}", encode(request.text));
+  for (name, value) in [
+    ("speaker_id", request.speaker),
+    ("language_id", request.language),
+  ] {
+    if let Some(value) = value {
+      let _ = write!(form, "&{}={}", name, encode(value));
+    }
+  }
+  let mut stream = TcpStream::connect(host)?;
+  stream.set_read_timeout(Some(TIMEOUT))?;
+  stream.set_write_timeout(Some(TIMEOUT))?;
+  write!(
+    stream,
+    "POST {}/api/tts HTTP/1.1\r\nHost: {}\r\nAccept: audio/wav\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    prefix,
+    host,
+    form.len(),
+    form
+  )?;
+  let body = read_response(BufReader::new(stream))?;
+  wav::decode(&body).ok_or_else(|| invalid_data("the server's response isn't a WAV file"))
+}
+
+/// Splits an `http://` URL into the host and port to connect to and the path before `/api`.
+fn parse_url(url: &str) -> io::Result<(&str, &str)> {
+  let rest = url
+    .strip_prefix("http://")
+    .ok_or_else(|| invalid_input(format!("{} isn't an http:// URL", url)))?;
+  let (host, path) = match rest.find('/') {
+    Some(slash) => rest.split_at(slash),
+    None => (rest, ""),
+  };
+  if host.is_empty() {
+    return Err(invalid_input(format!("{} has no host", url)));
+  }
+  Ok((host, path.trim_end_matches('/')))
+}
+
+/// Reads an HTTP response, returning its body if the request succeeded.
+fn read_response<R: BufRead>(mut reader: R) -> io::Result<Vec<u8>> {
+  let mut status = String::new();
+  reader.read_line(&mut status)?;
+  let code = status.split_whitespace().nth(1).unwrap_or_default();
+  let mut content_length = None;
+  let mut chunked = false;
+  loop {
+    let mut header = String::new();
+    if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+      break;
+    }
+    if let Some((name, value)) = header.split_once(':') {
+      let value = value.trim();
+      if name.eq_ignore_ascii_case("content-length") {
+        content_length = value.parse::<u64>().ok();
+      }
+      else if name.eq_ignore_ascii_case("transfer-encoding") {
+        chunked = value.eq_ignore_ascii_case("chunked");
+      }
+    }
+  }
+  // The body is read into a buffer that grows as data arrives, rather than one allocated up
+  // front for the length the server claims.
+  let mut body = vec![];
+  if chunked {
+    loop {
+      let mut size = String::new();
+      reader.read_line(&mut size)?;
+      let size = size.split(';').next().unwrap_or_default().trim();
+      let size = u64::from_str_radix(size, 16)
+        .map_err(|_| invalid_data("invalid chunk size in the server's response"))?;
+      if size == 0 {
+        break;
+      }
+      read_body(&mut reader, &mut body, size)?;
+      // Each chunk ends with a line break.
+      reader.read_line(&mut String::new())?;
+    }
+  }
+  else if let Some(len) = content_length {
+    read_body(&mut reader, &mut body, len)?;
+  }
+  else {
+    // Everything up to the end of the stream, which is one byte too many if it's over the limit.
+    (&mut reader)
+      .take(MAX_RESPONSE_BYTES + 1)
+      .read_to_end(&mut body)?;
+    check_body_len(body.len() as u64)?;
+  }
+  if !code.starts_with('2') {
+    return Err(io::Error::other(format!(
+      "the server responded {}: {}",
+      status.trim(),
+      String::from_utf8_lossy(&body).trim()
+    )));
+  }
+  Ok(body)
+}
+
+/// Appends `len` bytes of a response's body to `body`.
+fn read_body<R: BufRead>(reader: &mut R, body: &mut Vec<u8>, len: u64) -> io::Result<()> {
+  check_body_len(body.len() as u64 + len)?;
+  let read = reader.take(len).read_to_end(body)?;
+  if (read as u64) < len {
+    return Err(io::Error::new(
+      io::ErrorKind::UnexpectedEof,
+      "the server's response ended early",
+    ));
+  }
+  Ok(())
+}
+
+fn check_body_len(len: u64) -> io::Result<()> {
+  if len > MAX_RESPONSE_BYTES {
+    return Err(invalid_data(&format!(
+      "the server's response is larger than {} bytes",
+      MAX_RESPONSE_BYTES
+    )));
+  }
+  Ok(())
+}
+
+/// Percent-encodes a form value.
+fn encode(value: &str) -> String {
+  let mut encoded = String::with_capacity(value.len());
+  for byte in value.bytes() {
+    if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+      encoded.push(byte as char);
+    }
+    else {
+      let _ = write!(encoded, "%{:02X}", byte);
+    }
+  }
+  encoded
+}
+
+fn invalid_input(message: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,101 @@
+/// A `<mark name="..."/>` element in SSML text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mark {
+  pub name: String,
+  /// The number of characters of plain text before the mark.
+  pub offset: usize,
+}
+
+/// Text with its SSML markup removed, and the marks that were in it.
+#[derive(Debug, Default, PartialEq)]
+pub struct Document {
+  pub text: String,
+  pub marks: Vec<Mark>,
+}
+
+/// Extracts the plain text and marks from SSML. Other elements are removed, those that separate
+/// words or sentences leaving a space behind, and the predefined XML entities are decoded. A `<`
+/// that doesn't start a tag is kept as text.
+pub fn parse(ssml: &str) -> Document {
+  let mut document = Document::default();
+  let mut chars = 0;
+  let mut rest = ssml;
+  while let Some(start) = rest.find(['<', '&']) {
+    let text = &rest[..start];
+    document.text.push_str(text);
+    chars += text.chars().count();
+    rest = &rest[start..];
+    if rest.starts_with('&') {
+      let (decoded, len) = decode_entity(rest);
+      document.text.push(decoded);
+      chars += 1;
+      rest = &rest[len..];
+      continue;
+    }
+    let end = match rest.find('>') {
+      Some(end) => end,
+      None => break,
+    };
+    let tag = rest[1..end].trim_end_matches('/').trim();
+    rest = &rest[end + 1..];
+    let name = tag
+      .trim_start_matches('/')
+      .split(|c: char| c.is_whitespace())
+      .next()
+      .unwrap_or_default();
+    match name {
+      "mark" => {
+        if let Some(mark_name) = attribute(tag, "name") {
+          document.marks.push(Mark {
+            name: mark_name,
+            offset: chars,
+          });
+        }
+      },
+      "break" | "p" | "s" | "speak"
+        if !document.text.ends_with(char::is_whitespace) && !document.text.is_empty() =>
+      {
+        document.text.push(' ');
+        chars += 1;
+      },
+      _ => (),
+    }
+  }
+  document.text.push_str(rest);
+  document
+}
+
+/// Decodes the entity at the start of `text`, returning the character and the length of the
+/// entity, or a literal `&` if it isn't one.
+fn decode_entity(text: &str) -> (char, usize) {
+  for (entity, c) in [
+    ("&amp;", '&'),
+    ("&lt;", '<'),
+    ("&gt;", '>'),
+    ("&quot;", '"'),
+    ("&apos;", '\''),
+  ] {
+    if text.starts_with(entity) {
+      return (c, entity.len());
+    }
+  }
+  ('&', 1)
+}
+
+/// The value of an attribute of a tag, in single or double quotes.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+  let mut rest = tag;
+  while let Some(index) = rest.find(name) {
+    let after = rest[index + name.len()..].trim_start();
+    let preceded_by_space = rest[..index].ends_with(char::is_whitespace);
+    rest = &rest[index + name.len()..];
+    let value = match after.strip_prefix('=') {
+      Some(value) if preceded_by_space => value.trim_start(),
+      _ => continue,
+    };
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &value[1..];
+    return value.find(quote).map(|end| value[..end].to_owned());
+  }
+  None
+}
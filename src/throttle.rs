@@ -0,0 +1,38 @@
+use std::time::{Duration, Instant};
+
+/// Limits how many characters are synthesised per minute, as a token bucket that refills
+/// steadily and holds up to a minute's worth, so that short bursts go through.
+#[derive(Debug, Default)]
+pub struct Throttle {
+  available: f64,
+  updated: Option<Instant>,
+  /// Whether the last text had to wait or be dropped.
+  pub engaged: bool,
+}
+
+impl Throttle {
+  /// Takes `chars` characters from the budget of `per_minute` at `now`, returning `None` if
+  /// they can be synthesised straight away, or else how long until they can. Texts longer than
+  /// a minute's worth go through once the budget is full, and are paid off afterwards.
+  pub fn acquire(&mut self, chars: usize, per_minute: u32, now: Instant) -> Option<Duration> {
+    let capacity = per_minute as f64;
+    let per_second = capacity / 60.0;
+    self.available = match self.updated {
+      Some(updated) => {
+        (self.available + now.duration_since(updated).as_secs_f64() * per_second).min(capacity)
+      },
+      None => capacity,
+    };
+    self.updated = Some(now);
+    let needed = (chars as f64).min(capacity);
+    if self.available >= needed {
+      self.available -= chars as f64;
+      None
+    }
+    else {
+      Some(Duration::from_secs_f64(
+        (needed - self.available) / per_second,
+      ))
+    }
+  }
+}
@@ -0,0 +1,157 @@
+use std::f64::consts::PI;
+
+/// The length of each DTMF digit, and of the pause after it.
+const DTMF_DIGIT_MS: u32 = 100;
+const AMPLITUDE: f64 = 0.3;
+/// The length of the fade in and out that keeps tones from clicking.
+const FADE_MS: u32 = 5;
+
+/// A tone to generate in place of speech.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tone {
+  /// `[[tone:440,200ms]]`: a sine wave of a frequency in Hz, for a duration in milliseconds or,
+  /// with an `s` suffix, seconds.
+  Sine { frequency: f64, duration_ms: u32 },
+  /// `[[dtmf:5]]`: the dual tones of telephone keypad digits, `0`-`9`, `*`, `#` and `A`-`D`.
+  Dtmf { digits: String },
+}
+
+/// A part of a text, either to be spoken or a tone.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+  Text(String),
+  Tone(Tone),
+}
+
+/// Splits text at tone directives. Text without directives is returned as it is, even if it's
+/// blank, while blank text between directives is dropped. Directives that can't be parsed are
+/// left in the text.
+pub fn split(text: &str) -> Vec<Segment> {
+  let mut segments = vec![];
+  let mut rest = text;
+  let mut spoken = String::new();
+  while let Some(start) = rest.find("[[") {
+    let end = match rest[start..].find("]]") {
+      Some(end) => start + end,
+      None => break,
+    };
+    match parse_directive(&rest[start + 2..end]) {
+      Some(tone) => {
+        spoken.push_str(&rest[..start]);
+        if !spoken.trim().is_empty() {
+          segments.push(Segment::Text(spoken.trim().to_owned()));
+        }
+        spoken.clear();
+        segments.push(Segment::Tone(tone));
+      },
+      None => spoken.push_str(&rest[..end + 2]),
+    }
+    rest = &rest[end + 2..];
+  }
+  spoken.push_str(rest);
+  if segments.is_empty() {
+    return vec![Segment::Text(spoken)];
+  }
+  if !spoken.trim().is_empty() {
+    segments.push(Segment::Text(spoken.trim().to_owned()));
+  }
+  segments
+}
+
+fn parse_directive(directive: &str) -> Option<Tone> {
+  let (kind, arguments) = directive.split_once(':')?;
+  match kind.trim() {
+    "tone" => {
+      let (frequency, duration) = arguments.split_once(',')?;
+      let frequency: f64 = frequency.trim().parse().ok()?;
+      let duration = duration.trim();
+      let duration_ms = match duration.strip_suffix("ms") {
+        Some(ms) => ms.trim().parse().ok()?,
+        None => match duration.strip_suffix('s') {
+          Some(seconds) => (seconds.trim().parse::<f64>().ok()? * 1000.0) as u32,
+          None => duration.parse().ok()?,
+        },
+      };
+      (frequency > 0.0).then_some(Tone::Sine {
+        frequency,
+        duration_ms,
+      })
+    },
+    "dtmf" => {
+      let digits: String = arguments
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+      (!digits.is_empty() && digits.chars().all(|digit| dtmf_frequencies(digit).is_some()))
+        .then_some(Tone::Dtmf { digits })
+    },
+    _ => None,
+  }
+}
+
+/// The low and high frequencies of a DTMF digit.
+fn dtmf_frequencies(digit: char) -> Option<(f64, f64)> {
+  let (row, column) = match digit {
+    '1' => (0, 0),
+    '2' => (0, 1),
+    '3' => (0, 2),
+    'A' => (0, 3),
+    '4' => (1, 0),
+    '5' => (1, 1),
+    '6' => (1, 2),
+    'B' => (1, 3),
+    '7' => (2, 0),
+    '8' => (2, 1),
+    '9' => (2, 2),
+    'C' => (2, 3),
+    '*' => (3, 0),
+    '0' => (3, 1),
+    '#' => (3, 2),
+    'D' => (3, 3),
+    _ => return None,
+  };
+  Some((
+    [697.0, 770.0, 852.0, 941.0][row],
+    [1209.0, 1336.0, 1477.0, 1633.0][column],
+  ))
+}
+
+impl Tone {
+  /// Generates the tone as mono audio.
+  pub fn render(&self, sample_rate: u64) -> Vec<f32> {
+    match self {
+      Tone::Sine {
+        frequency,
+        duration_ms,
+      } => sine(&[*frequency], *duration_ms, sample_rate),
+      Tone::Dtmf { digits } => digits
+        .chars()
+        .filter_map(dtmf_frequencies)
+        .flat_map(|(low, high)| {
+          let mut digit = sine(&[low, high], DTMF_DIGIT_MS, sample_rate);
+          digit.resize(digit.len() * 2, 0.0);
+          digit
+        })
+        .collect(),
+    }
+  }
+}
+
+/// The sum of sine waves of the given frequencies, faded in and out.
+fn sine(frequencies: &[f64], duration_ms: u32, sample_rate: u64) -> Vec<f32> {
+  let len = (duration_ms as u64 * sample_rate / 1000) as usize;
+  let fade = ((FADE_MS as u64 * sample_rate / 1000) as usize).min(len / 2).max(1);
+  let amplitude = AMPLITUDE / frequencies.len() as f64;
+  (0..len)
+    .map(|i| {
+      let t = i as f64 / sample_rate as f64;
+      let envelope = (i.min(len - 1 - i) as f64 / fade as f64).min(1.0);
+      let value: f64 = frequencies
+        .iter()
+        .map(|frequency| (2.0 * PI * frequency * t).sin())
+        .sum();
+      (value * amplitude * envelope) as f32
+    })
+    .collect()
+}
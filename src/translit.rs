@@ -0,0 +1,145 @@
+/// Text transliterated to ASCII, for retrying synthesis with models whose alphabet doesn't cover
+/// the original characters.
+#[derive(Debug)]
+pub struct Transliteration {
+  pub text: String,
+  /// The characters that were replaced, in order of first appearance.
+  pub substituted: Vec<char>,
+}
+
+/// Replaces non-ASCII characters with their closest ASCII equivalents: accented letters with
+/// unaccented ones, ligatures with their letters, Greek and Cyrillic with Latin, and typographic
+/// punctuation with plain punctuation. Characters with no equivalent are dropped.
+pub fn transliterate(text: &str) -> Transliteration {
+  let mut transliterated = String::with_capacity(text.len());
+  let mut substituted = vec![];
+  for c in text.chars() {
+    if c.is_ascii() {
+      transliterated.push(c);
+      continue;
+    }
+    if c.is_whitespace() {
+      transliterated.push(' ');
+    }
+    else if c.is_uppercase() {
+      let lowercase = c.to_lowercase().next().unwrap_or(c);
+      let mut replacement = ascii_equivalent(lowercase).chars();
+      // Keep capitals, e.g. for names and acronyms.
+      if let Some(first) = replacement.next() {
+        transliterated.push(first.to_ascii_uppercase());
+        transliterated.push_str(replacement.as_str());
+      }
+    }
+    else {
+      transliterated.push_str(ascii_equivalent(c));
+    }
+    if !substituted.contains(&c) {
+      substituted.push(c);
+    }
+  }
+  Transliteration {
+    text: transliterated,
+    substituted,
+  }
+}
+
+/// The ASCII equivalent of a lowercase character.
+fn ascii_equivalent(c: char) -> &'static str {
+  match c {
+    'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+    'æ' => "ae",
+    'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+    'ď' | 'đ' | 'ð' => "d",
+    'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+    'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+    'ĥ' | 'ħ' => "h",
+    'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+    'ĵ' => "j",
+    'ķ' => "k",
+    'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+    'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+    'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+    'œ' => "oe",
+    'ŕ' | 'ŗ' | 'ř' => "r",
+    'ś' | 'ŝ' | 'ş' | 'š' | 'ș' => "s",
+    'ß' => "ss",
+    'ţ' | 'ť' | 'ŧ' | 'ț' => "t",
+    'þ' => "th",
+    'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+    'ŵ' => "w",
+    'ý' | 'ÿ' | 'ŷ' => "y",
+    'ź' | 'ż' | 'ž' => "z",
+    // Greek.
+    'α' | 'ά' => "a",
+    'β' => "v",
+    'γ' => "g",
+    'δ' => "d",
+    'ε' | 'έ' => "e",
+    'ζ' => "z",
+    'η' | 'ή' => "i",
+    'θ' => "th",
+    'ι' | 'ί' | 'ϊ' | 'ΐ' => "i",
+    'κ' => "k",
+    'λ' => "l",
+    'μ' => "m",
+    'ν' => "n",
+    'ξ' => "x",
+    'ο' | 'ό' => "o",
+    'π' => "p",
+    'ρ' => "r",
+    'σ' | 'ς' => "s",
+    'τ' => "t",
+    'υ' | 'ύ' | 'ϋ' | 'ΰ' => "y",
+    'φ' => "f",
+    'χ' => "ch",
+    'ψ' => "ps",
+    'ω' | 'ώ' => "o",
+    // Cyrillic.
+    'а' => "a",
+    'б' => "b",
+    'в' => "v",
+    'г' => "g",
+    'д' => "d",
+    'е' | 'ё' | 'э' | 'є' => "e",
+    'ж' => "zh",
+    'з' => "z",
+    'и' | 'й' | 'і' | 'ї' => "i",
+    'к' => "k",
+    'л' => "l",
+    'м' => "m",
+    'н' => "n",
+    'о' => "o",
+    'п' => "p",
+    'р' => "r",
+    'с' => "s",
+    'т' => "t",
+    'у' => "u",
+    'ф' => "f",
+    'х' => "kh",
+    'ц' => "ts",
+    'ч' => "ch",
+    'ш' => "sh",
+    'щ' => "shch",
+    'ы' => "y",
+    'ю' => "yu",
+    'я' => "ya",
+    'ъ' | 'ь' => "",
+    // Punctuation and symbols.
+    '‘' | '’' | '‚' | '′' => "'",
+    '“' | '”' | '„' | '″' | '«' | '»' => "\"",
+    '‐' | '‑' | '‒' | '–' | '—' | '―' | '−' => "-",
+    '…' => "...",
+    '•' | '·' => ",",
+    '¿' | '¡' => "",
+    '€' => "EUR",
+    '£' => "GBP",
+    '¥' => "JPY",
+    '©' => "(c)",
+    '®' => "(r)",
+    '°' => " degrees",
+    '½' => "1/2",
+    '¼' => "1/4",
+    '¾' => "3/4",
+    _ => "",
+  }
+}
@@ -0,0 +1,16 @@
+mod imp;
+
+use gstreamer::{glib, prelude::StaticType, Rank};
+
+glib::wrapper! {
+  pub struct CoquittsVc(ObjectSubclass<imp::CoquittsVc>) @extends gstreamer_base::BaseTransform, gstreamer::Element, gstreamer::Object;
+}
+
+pub fn register(plugin: &gstreamer::Plugin) -> Result<(), glib::BoolError> {
+  gstreamer::Element::register(
+    Some(plugin),
+    "coquittsvc",
+    Rank::None,
+    CoquittsVc::static_type(),
+  )
+}
@@ -0,0 +1,403 @@
+use std::sync::Mutex;
+
+use byte_slice_cast::{AsByteSlice, AsSliceOf};
+use gstreamer::{
+  glib::{self, ParamSpec, Value},
+  param_spec::GstParamSpecBuilderExt,
+  prelude::{PadExt, ParamSpecBuilderExt, ToValue},
+  subclass::{
+    prelude::{
+      ElementImpl, ElementImplExt, GstObjectImpl, ObjectImpl, ObjectSubclass, ObjectSubclassExt,
+    },
+    ElementMetadata,
+  },
+  Buffer, Caps, CapsIntersectMode, ClockTime, DebugCategory, ErrorMessage, Event, EventView,
+  FlowError, LoggableError, PadDirection, PadPresence, PadTemplate,
+};
+use gstreamer_audio::{AudioCapsBuilder, AudioInfo, AUDIO_FORMAT_F32};
+use gstreamer_base::{
+  prelude::BaseTransformExtManual,
+  subclass::{
+    base_transform::{BaseTransformImpl, BaseTransformImplExt, GenerateOutputSuccess},
+    BaseTransformMode,
+  },
+  BaseTransform,
+};
+use once_cell::sync::Lazy;
+use pyo3::{types::PyModule, Py, PyAny, PyResult};
+
+use crate::{dsp, filter::GpuMode, python};
+
+const DEFAULT_MODEL: &str = "voice_conversion_models/multilingual/vctk/freevc24";
+const DEFAULT_GPU: GpuMode = GpuMode::Off;
+const DEFAULT_CHUNK_MS: u32 = 5000;
+
+static CAT: Lazy<DebugCategory> = Lazy::new(|| {
+  DebugCategory::new(
+    "coquittsvc",
+    gstreamer::DebugColorFlags::empty(),
+    Some("Voice conversion filter using Coqui"),
+  )
+});
+
+static CAPS: Lazy<Caps> = Lazy::new(|| {
+  AudioCapsBuilder::new()
+    .format(AUDIO_FORMAT_F32)
+    .channels(1)
+    .build()
+});
+
+#[derive(Debug, Clone)]
+struct Settings {
+  model: String,
+  target_speaker_wav: Option<String>,
+  gpu: GpuMode,
+  chunk_ms: u32,
+}
+
+#[derive(Debug, Default)]
+struct State {
+  input_rate: u32,
+  /// Input samples waiting to be converted.
+  pending: Vec<f32>,
+  pending_pts: Option<ClockTime>,
+}
+
+pub struct CoquittsVc {
+  settings: Mutex<Settings>,
+  state: Mutex<State>,
+  vc: Mutex<Option<Py<PyAny>>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for CoquittsVc {
+  type ParentType = BaseTransform;
+  type Type = super::CoquittsVc;
+
+  const NAME: &'static str = "GstCoquittsVc";
+
+  fn new() -> Self {
+    Self {
+      settings: Mutex::new(Settings {
+        model: DEFAULT_MODEL.into(),
+        target_speaker_wav: None,
+        gpu: DEFAULT_GPU,
+        chunk_ms: DEFAULT_CHUNK_MS,
+      }),
+      state: Mutex::new(State::default()),
+      vc: Mutex::new(None),
+    }
+  }
+}
+
+impl ObjectImpl for CoquittsVc {
+  fn properties() -> &'static [ParamSpec] {
+    static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
+      vec![
+      glib::ParamSpecString::builder("model")
+        .nick("Model")
+        .blurb(&format!("The Coqui voice conversion model to use. Defaults to {}", DEFAULT_MODEL))
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("target-speaker-wav")
+        .nick("Target speaker WAV")
+        .blurb("A WAV file of the voice to convert the input audio to.")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecEnum::builder_with_default("use-gpu", DEFAULT_GPU)
+        .nick("Use GPU")
+        .blurb("Whether to run the model on a GPU. In auto mode the best device available is picked.")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecUInt::builder("chunk-ms")
+        .nick("Chunk duration")
+        .blurb(&format!("How many milliseconds of input audio to collect before converting it. Longer chunks give better quality at the cost of latency. Defaults to {}", DEFAULT_CHUNK_MS))
+        .minimum(1)
+        .default_value(DEFAULT_CHUNK_MS)
+        .mutable_playing()
+        .build(),
+    ]
+    });
+    PROPERTIES.as_ref()
+  }
+
+  fn set_property(&self, _id: usize, value: &Value, pspec: &ParamSpec) {
+    let mut settings = self.settings.lock().unwrap();
+    match pspec.name() {
+      "model" => {
+        settings.model = value.get().unwrap();
+      },
+      "target-speaker-wav" => {
+        settings.target_speaker_wav = value.get().unwrap();
+      },
+      "use-gpu" => {
+        settings.gpu = value.get().unwrap();
+      },
+      "chunk-ms" => {
+        settings.chunk_ms = value.get().unwrap();
+      },
+      other => panic!("no such property: {}", other),
+    }
+  }
+
+  fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
+    let settings = self.settings.lock().unwrap();
+    match pspec.name() {
+      "model" => settings.model.to_value(),
+      "target-speaker-wav" => settings.target_speaker_wav.to_value(),
+      "use-gpu" => settings.gpu.to_value(),
+      "chunk-ms" => settings.chunk_ms.to_value(),
+      other => panic!("no such property: {}", other),
+    }
+  }
+}
+
+impl GstObjectImpl for CoquittsVc {}
+
+impl ElementImpl for CoquittsVc {
+  fn metadata() -> Option<&'static ElementMetadata> {
+    static ELEMENT_METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+      ElementMetadata::new(
+        "Coqui voice conversion",
+        "Filter/Effect/Audio",
+        "Converts speech to another speaker's voice using Coqui",
+        "Jasper Hugo <jasper@avstack.io>",
+      )
+    });
+
+    Some(&*ELEMENT_METADATA)
+  }
+
+  fn pad_templates() -> &'static [PadTemplate] {
+    static PAD_TEMPLATES: Lazy<Vec<PadTemplate>> = Lazy::new(|| {
+      let src_pad_template =
+        PadTemplate::new("src", PadDirection::Src, PadPresence::Always, &CAPS).unwrap();
+      let sink_pad_template =
+        PadTemplate::new("sink", PadDirection::Sink, PadPresence::Always, &CAPS).unwrap();
+      vec![src_pad_template, sink_pad_template]
+    });
+
+    PAD_TEMPLATES.as_ref()
+  }
+}
+
+impl CoquittsVc {
+  fn with_vc<R, F: FnOnce(&PyAny) -> R>(&self, f: F) -> Result<R, ErrorMessage> {
+    let mut vc = self.vc.lock().unwrap();
+    python::with_gil(|py| {
+      if vc.is_none() {
+        let (model, gpu) = {
+          let settings = self.settings.lock().unwrap();
+          (settings.model.clone(), settings.gpu)
+        };
+        gstreamer::debug!(CAT, "with_vc(): loading model {}", model);
+        let loaded = python::gpu_device(py, gpu)
+          .and_then(|device| python::load_tts(py, &model, device, None))
+          .map_err(|e| {
+            e.to_error_message(
+              gstreamer::LibraryError::Init,
+              &format!("Failed to load Coqui voice conversion model {}", model),
+            )
+          })?;
+        *vc = Some(loaded.into());
+      }
+      Ok(f(vc.as_ref().unwrap().as_ref(py)))
+    })
+  }
+
+  /// Converts the pending input audio to the target voice.
+  fn convert(&self) -> Result<Option<Buffer>, FlowError> {
+    let (samples, input_rate, pts) = {
+      let mut state = self.state.lock().unwrap();
+      if state.pending.is_empty() {
+        return Ok(None);
+      }
+      (
+        std::mem::take(&mut state.pending),
+        state.input_rate,
+        state.pending_pts.take(),
+      )
+    };
+    let target = self
+      .settings
+      .lock()
+      .unwrap()
+      .target_speaker_wav
+      .clone()
+      .unwrap_or_default();
+    gstreamer::debug!(
+      CAT,
+      "convert(): converting {} samples at {}Hz",
+      samples.len(),
+      input_rate
+    );
+    let result = self
+      .with_vc(|vc| {
+        voice_conversion(vc, &samples, input_rate, &target)
+          .map_err(|e| python::format_traceback(vc.py(), &e))
+      })
+      .map_err(|e| {
+        self.post_error_message(e);
+        FlowError::Error
+      })?;
+    let (audio, output_rate) = result.map_err(|traceback| {
+      gstreamer::warning!(CAT, "convert(): failed to convert audio:\n{}", traceback);
+      gstreamer::element_imp_error!(
+        self,
+        gstreamer::LibraryError::Failed,
+        ("Failed to convert audio"),
+        ["{}", traceback]
+      );
+      FlowError::Error
+    })?;
+    let mut buffer = Buffer::from_slice(audio.as_byte_slice().to_vec());
+    {
+      let buffer = buffer.get_mut().unwrap();
+      buffer.set_pts(pts);
+      buffer.set_duration(ClockTime::from_nseconds(
+        audio.len() as u64 * 1_000_000_000 / output_rate as u64,
+      ));
+    }
+    Ok(Some(buffer))
+  }
+}
+
+/// Runs the voice conversion model on `samples`, returning the converted audio and its sample
+/// rate.
+fn voice_conversion(
+  vc: &PyAny,
+  samples: &[f32],
+  input_rate: u32,
+  target: &str,
+) -> PyResult<(Vec<f32>, u32)> {
+  let py = vc.py();
+  let synthesizer = vc.getattr("voice_converter")?;
+  let audio_config = synthesizer.getattr("vc_config")?.getattr("audio")?;
+  let model_rate: u32 = audio_config
+    .getattr("input_sample_rate")
+    .or_else(|_| audio_config.getattr("sample_rate"))?
+    .extract()?;
+  let output_rate: u32 = synthesizer.getattr("output_sample_rate")?.extract()?;
+  let numpy = PyModule::import(py, "numpy")?;
+  let source = numpy.call_method1(
+    "array",
+    (
+      dsp::resample_linear(samples, input_rate, model_rate),
+      "float32",
+    ),
+  )?;
+  let wav = synthesizer.call_method1("voice_conversion", (source, target))?;
+  Ok((python::extract_audio(wav)?, output_rate))
+}
+
+impl BaseTransformImpl for CoquittsVc {
+  const MODE: BaseTransformMode = BaseTransformMode::NeverInPlace;
+  const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+  const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+  fn start(&self) -> Result<(), ErrorMessage> {
+    if self.settings.lock().unwrap().target_speaker_wav.is_none() {
+      return Err(gstreamer::error_msg!(
+        gstreamer::LibraryError::Settings,
+        ("The `target-speaker-wav` property must be set")
+      ));
+    }
+    self.with_vc(|_| ())
+  }
+
+  fn stop(&self) -> Result<(), ErrorMessage> {
+    *self.state.lock().unwrap() = State::default();
+    Ok(())
+  }
+
+  fn sink_event(&self, event: Event) -> bool {
+    match event.view() {
+      EventView::Eos(_) => {
+        gstreamer::debug!(CAT, "sink_event(): EOS, converting remaining audio");
+        if let Ok(Some(buffer)) = self.convert() {
+          let _ = self.obj().src_pad().push(buffer);
+        }
+      },
+      EventView::FlushStop(_) => {
+        let mut state = self.state.lock().unwrap();
+        state.pending.clear();
+        state.pending_pts = None;
+      },
+      _ => (),
+    }
+    self.parent_sink_event(event)
+  }
+
+  fn transform_caps(
+    &self,
+    direction: PadDirection,
+    _caps: &Caps,
+    maybe_filter: Option<&Caps>,
+  ) -> Option<Caps> {
+    let mut caps = if direction == PadDirection::Src {
+      CAPS.clone()
+    }
+    else {
+      let output_rate = self.with_vc(|vc| {
+        vc.getattr("voice_converter")
+          .and_then(|synthesizer| synthesizer.getattr("output_sample_rate"))
+          .and_then(|rate| rate.extract::<i32>())
+      });
+      match output_rate {
+        Ok(Ok(output_rate)) => AudioCapsBuilder::new()
+          .format(AUDIO_FORMAT_F32)
+          .channels(1)
+          .rate(output_rate)
+          .build(),
+        Ok(Err(e)) => {
+          gstreamer::warning!(CAT, "transform_caps(): no output sample rate: {}", e);
+          return None;
+        },
+        Err(e) => {
+          self.post_error_message(e);
+          return None;
+        },
+      }
+    };
+    if let Some(filter) = maybe_filter {
+      caps = filter.intersect_with_mode(&caps, CapsIntersectMode::First);
+    }
+    Some(caps)
+  }
+
+  fn set_caps(&self, incaps: &Caps, _outcaps: &Caps) -> Result<(), LoggableError> {
+    let info = AudioInfo::from_caps(incaps)
+      .map_err(|_| gstreamer::loggable_error!(CAT, "Invalid input caps {}", incaps))?;
+    self.state.lock().unwrap().input_rate = info.rate();
+    Ok(())
+  }
+
+  fn generate_output(&self) -> Result<GenerateOutputSuccess, FlowError> {
+    let buffer = match self.take_queued_buffer() {
+      Some(buffer) => buffer,
+      None => return Ok(GenerateOutputSuccess::NoOutput),
+    };
+    let chunk_ms = self.settings.lock().unwrap().chunk_ms;
+    let ready = {
+      let buffer_reader = buffer.map_readable().map_err(|_| FlowError::Error)?;
+      let samples = buffer_reader
+        .as_slice_of::<f32>()
+        .map_err(|_| FlowError::Error)?;
+      let mut state = self.state.lock().unwrap();
+      if state.pending.is_empty() {
+        state.pending_pts = buffer.pts();
+      }
+      state.pending.extend_from_slice(samples);
+      state.pending.len() as u64 * 1000 >= chunk_ms as u64 * state.input_rate as u64
+    };
+    if ready {
+      match self.convert()? {
+        Some(buffer) => Ok(GenerateOutputSuccess::Buffer(buffer)),
+        None => Ok(GenerateOutputSuccess::NoOutput),
+      }
+    }
+    else {
+      Ok(GenerateOutputSuccess::NoOutput)
+    }
+  }
+}
@@ -0,0 +1,273 @@
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+static PATTERN: Lazy<Regex> = Lazy::new(|| {
+  Regex::new(concat!(
+    r"(?P<date>\b(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})\b)",
+    r"|(?P<currency>(?P<symbol>[$€£])(?P<amount>\d{1,3}(?:,\d{3})+|\d+)(?:\.(?P<cents>\d{2}))?)",
+    r"|(?P<ordinal>\b(?P<ordinal_number>\d+)(?:st|nd|rd|th)\b)",
+    r"|(?P<number>\b(?P<integer>\d{1,3}(?:,\d{3})+|\d+)(?:\.(?P<fraction>\d+))?\b)(?P<percent>%)?",
+  ))
+  .unwrap()
+});
+
+const ONES: [&str; 20] = [
+  "zero",
+  "one",
+  "two",
+  "three",
+  "four",
+  "five",
+  "six",
+  "seven",
+  "eight",
+  "nine",
+  "ten",
+  "eleven",
+  "twelve",
+  "thirteen",
+  "fourteen",
+  "fifteen",
+  "sixteen",
+  "seventeen",
+  "eighteen",
+  "nineteen",
+];
+
+const TENS: [&str; 10] = [
+  "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+const SCALES: [&str; 7] = [
+  "",
+  "thousand",
+  "million",
+  "billion",
+  "trillion",
+  "quadrillion",
+  "quintillion",
+];
+
+const MONTHS: [&str; 12] = [
+  "January",
+  "February",
+  "March",
+  "April",
+  "May",
+  "June",
+  "July",
+  "August",
+  "September",
+  "October",
+  "November",
+  "December",
+];
+
+/// Locales supported by the verbaliser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+  English,
+}
+
+impl Locale {
+  /// Picks a locale from a Coqui language identifier such as `en` or `en-us`. Returns `None` if
+  /// the language isn't supported, in which case text should be left as-is.
+  pub fn from_language(language: Option<&str>) -> Option<Self> {
+    match language {
+      None => Some(Locale::English),
+      Some(language) => {
+        let primary = language
+          .split(['-', '_'])
+          .next()
+          .unwrap_or_default()
+          .to_ascii_lowercase();
+        match primary.as_str() {
+          "en" => Some(Locale::English),
+          _ => None,
+        }
+      },
+    }
+  }
+}
+
+/// Expands numbers, ordinals, currency amounts, percentages and ISO dates into words.
+pub fn verbalize(text: &str, locale: Locale) -> String {
+  match locale {
+    Locale::English => PATTERN
+      .replace_all(text, |caps: &Captures| english(caps))
+      .into_owned(),
+  }
+}
+
+fn english(caps: &Captures) -> String {
+  if caps.name("date").is_some() {
+    let year: u64 = caps["year"].parse().unwrap();
+    let month: usize = caps["month"].parse().unwrap();
+    let day: u64 = caps["day"].parse().unwrap();
+    if (1..=12).contains(&month) && (1..=31).contains(&day) {
+      format!(
+        "{} {}, {}",
+        MONTHS[month - 1],
+        ordinal(&cardinal(day)),
+        year_words(year)
+      )
+    }
+    else {
+      [year_words(year), cardinal(month as u64), cardinal(day)].join(" ")
+    }
+  }
+  else if caps.name("currency").is_some() {
+    let (major, minor) = match &caps["symbol"] {
+      "€" => ("euro", "cent"),
+      "£" => ("pound", "penny"),
+      _ => ("dollar", "cent"),
+    };
+    let amount = digits(&caps["amount"]);
+    let mut words = format!("{} {}", integer_words(&amount), plural(major, &amount));
+    if let Some(cents) = caps.name("cents") {
+      let cents = cents.as_str().trim_start_matches('0');
+      if !cents.is_empty() {
+        let minor = match (minor, cents) {
+          ("penny", "1") => "penny",
+          ("penny", _) => "pence",
+          (minor, cents) => plural(minor, cents),
+        };
+        words = format!("{} and {} {}", words, integer_words(cents), minor);
+      }
+    }
+    words
+  }
+  else if caps.name("ordinal").is_some() {
+    ordinal(&integer_words(&caps["ordinal_number"]))
+  }
+  else {
+    let mut words = integer_words(&digits(&caps["integer"]));
+    if let Some(fraction) = caps.name("fraction") {
+      words.push_str(" point ");
+      words.push_str(&digit_words(fraction.as_str()));
+    }
+    if caps.name("percent").is_some() {
+      words.push_str(" percent");
+    }
+    words
+  }
+}
+
+fn digits(s: &str) -> String {
+  s.chars().filter(char::is_ascii_digit).collect()
+}
+
+fn plural<'a>(unit: &'a str, amount: &str) -> &'a str {
+  if amount.trim_start_matches('0') == "1" {
+    unit
+  }
+  else {
+    match unit {
+      "dollar" => "dollars",
+      "euro" => "euros",
+      "pound" => "pounds",
+      "cent" => "cents",
+      other => other,
+    }
+  }
+}
+
+fn integer_words(digits: &str) -> String {
+  match digits.parse::<u64>() {
+    Ok(n) => cardinal(n),
+    // Too long to be read as a number, so read it digit by digit.
+    Err(_) => digit_words(digits),
+  }
+}
+
+fn digit_words(digits: &str) -> String {
+  digits
+    .chars()
+    .filter_map(|c| c.to_digit(10))
+    .map(|d| ONES[d as usize])
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+fn cardinal(n: u64) -> String {
+  if n == 0 {
+    return ONES[0].to_owned();
+  }
+  let mut groups = vec![];
+  let mut remaining = n;
+  let mut scale = 0;
+  while remaining > 0 {
+    let group = remaining % 1000;
+    if group > 0 {
+      let words = below_thousand(group);
+      groups.push(if SCALES[scale].is_empty() {
+        words
+      }
+      else {
+        format!("{} {}", words, SCALES[scale])
+      });
+    }
+    remaining /= 1000;
+    scale += 1;
+  }
+  groups.reverse();
+  groups.join(" ")
+}
+
+fn below_thousand(n: u64) -> String {
+  let hundreds = n / 100;
+  let rest = n % 100;
+  let mut words = vec![];
+  if hundreds > 0 {
+    words.push(format!("{} hundred", ONES[hundreds as usize]));
+  }
+  if rest > 0 {
+    words.push(below_hundred(rest));
+  }
+  words.join(" ")
+}
+
+fn below_hundred(n: u64) -> String {
+  if n < 20 {
+    ONES[n as usize].to_owned()
+  }
+  else if n.is_multiple_of(10) {
+    TENS[(n / 10) as usize].to_owned()
+  }
+  else {
+    format!("{}-{}", TENS[(n / 10) as usize], ONES[(n % 10) as usize])
+  }
+}
+
+fn year_words(year: u64) -> String {
+  let (century, rest) = (year / 100, year % 100);
+  if !(1100..2000).contains(&year) && !(2010..2100).contains(&year) {
+    cardinal(year)
+  }
+  else if rest == 0 {
+    format!("{} hundred", below_hundred(century))
+  }
+  else if rest < 10 {
+    format!("{} oh {}", below_hundred(century), ONES[rest as usize])
+  }
+  else {
+    format!("{} {}", below_hundred(century), below_hundred(rest))
+  }
+}
+
+fn ordinal(cardinal: &str) -> String {
+  let split = cardinal.rfind([' ', '-']).map_or(0, |i| i + 1);
+  let (head, last) = cardinal.split_at(split);
+  let last = match last {
+    "one" => "first".to_owned(),
+    "two" => "second".to_owned(),
+    "three" => "third".to_owned(),
+    "five" => "fifth".to_owned(),
+    "eight" => "eighth".to_owned(),
+    "nine" => "ninth".to_owned(),
+    "twelve" => "twelfth".to_owned(),
+    word if word.ends_with('y') => format!("{}ieth", &word[..word.len() - 1]),
+    word => format!("{}th", word),
+  };
+  format!("{}{}", head, last)
+}
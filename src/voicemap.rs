@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+/// The model and speaker to synthesise a language with, either of which may be left to the
+/// element's settings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MappedVoice {
+  pub model: Option<String>,
+  pub speaker: Option<String>,
+}
+
+/// Maps the languages of a multi-language stream, whether detected or tagged, to the voices to
+/// synthesise them with.
+///
+/// The map is parsed from a JSON object of languages to objects with a `model`, a `speaker` or
+/// both, e.g. `{"en": {"model": "tts_models/en/vctk/vits", "speaker": "p225"}, "de": {"model":
+/// "tts_models/de/thorsten/vits"}}`. Languages are matched case-insensitively, and one with a
+/// region, such as `en-GB`, falls back to the voice for its language.
+#[derive(Debug, Clone, Default)]
+pub struct VoiceMap {
+  source: Option<String>,
+  voices: HashMap<String, MappedVoice>,
+}
+
+impl VoiceMap {
+  pub fn parse(source: &str) -> Result<Self, String> {
+    let value: Value =
+      serde_json::from_str(source).map_err(|e| format!("invalid voice map JSON: {}", e))?;
+    let object = value
+      .as_object()
+      .ok_or_else(|| "voice map JSON is not an object".to_owned())?;
+    let mut voices = HashMap::new();
+    for (language, voice) in object {
+      if !voice.is_object() {
+        return Err(format!("the voice for {} is not an object", language));
+      }
+      let string = |key: &str| match voice.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.clone()).filter(|value| !value.is_empty())),
+        Some(_) => Err(format!("`{}` for {} is not a string", key, language)),
+      };
+      let voice = MappedVoice {
+        model: string("model")?,
+        speaker: string("speaker")?,
+      };
+      voices.insert(normalize_language(language), voice);
+    }
+    Ok(Self {
+      source: Some(source.to_owned()),
+      voices,
+    })
+  }
+
+  pub fn source(&self) -> Option<&str> {
+    self.source.as_deref()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.voices.is_empty()
+  }
+
+  /// The voice for a language, or for the language without its region.
+  pub fn get(&self, language: &str) -> Option<&MappedVoice> {
+    let language = normalize_language(language);
+    self.voices.get(&language).or_else(|| {
+      let (primary, _) = language.split_once('-')?;
+      self.voices.get(primary)
+    })
+  }
+}
+
+fn normalize_language(language: &str) -> String {
+  language.trim().to_lowercase().replace('_', "-")
+}
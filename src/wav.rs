@@ -0,0 +1,58 @@
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Decodes a WAV file of integer or floating point samples into mono samples, mixing down
+/// channels, and their sample rate.
+pub fn decode(wav: &[u8]) -> Option<(Vec<f32>, u64)> {
+  if wav.get(0..4)? != b"RIFF" || wav.get(8..12)? != b"WAVE" {
+    return None;
+  }
+  let mut format = None;
+  let mut data = None;
+  let mut rest = &wav[12..];
+  while rest.len() >= 8 {
+    let id = &rest[0..4];
+    let len = u32::from_le_bytes(rest[4..8].try_into().ok()?) as usize;
+    // Recorders that don't know the length in advance may leave it too long.
+    let chunk = &rest[8..(8 + len).min(rest.len())];
+    match id {
+      b"fmt " => {
+        let mut tag = u16::from_le_bytes(chunk.get(0..2)?.try_into().ok()?);
+        let channels = u16::from_le_bytes(chunk.get(2..4)?.try_into().ok()?) as usize;
+        let sample_rate = u32::from_le_bytes(chunk.get(4..8)?.try_into().ok()?) as u64;
+        let bits = u16::from_le_bytes(chunk.get(14..16)?.try_into().ok()?);
+        if tag == WAVE_FORMAT_EXTENSIBLE {
+          // The format is the first two bytes of the sub-format GUID.
+          tag = u16::from_le_bytes(chunk.get(24..26)?.try_into().ok()?);
+        }
+        format = Some((tag, channels, sample_rate, bits));
+      },
+      b"data" => data = Some(chunk),
+      _ => (),
+    }
+    // Chunks are padded to an even length.
+    rest = rest.get(8 + len + len % 2..).unwrap_or_default();
+  }
+  let (tag, channels, sample_rate, bits) = format?;
+  // A file that's just a header, as a failed synthesis can give, has no samples.
+  let data = data.unwrap_or_default();
+  if channels == 0 || sample_rate == 0 {
+    return None;
+  }
+  let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+    (WAVE_FORMAT_PCM, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+    (WAVE_FORMAT_PCM, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+    (WAVE_FORMAT_PCM, 24) => |b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.0,
+    (WAVE_FORMAT_PCM, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+    (WAVE_FORMAT_IEEE_FLOAT, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+    (WAVE_FORMAT_IEEE_FLOAT, 64) => |b| f64::from_le_bytes(b.try_into().unwrap()) as f32,
+    _ => return None,
+  };
+  let width = bits as usize / 8;
+  let samples = data
+    .chunks_exact(width * channels)
+    .map(|frame| frame.chunks_exact(width).map(decode).sum::<f32>() / channels as f32)
+    .collect();
+  Some((samples, sample_rate))
+}
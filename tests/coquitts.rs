@@ -0,0 +1,739 @@
+//! Tests of the `coquitts` element against the mock `TTS.api` module in `tests/python`, so they
+//! don't need coqui-tts installed.
+
+use std::{
+  io::{BufRead, BufReader, Read, Write},
+  net::TcpListener,
+  sync::Once,
+  thread,
+};
+
+use gstreamer::{glib, prelude::*, Buffer, ClockTime, EventType, Memory};
+use gstreamer_check::Harness;
+
+/// The sample rate of the mock model.
+const SAMPLE_RATE: u64 = 16000;
+/// How many samples of speech the mock model produces per character.
+const SAMPLES_PER_CHAR: u64 = 160;
+
+fn init() {
+  static INIT: Once = Once::new();
+  INIT.call_once(|| {
+    // Python reads the module search path when the interpreter is initialised, which the element
+    // does lazily, so this is early enough for the mock to shadow a real coqui-tts install.
+    std::env::set_var(
+      "PYTHONPATH",
+      concat!(env!("CARGO_MANIFEST_DIR"), "/tests/python"),
+    );
+    gstreamer::init().unwrap();
+    gstcoquitts::plugin_register_static().unwrap();
+  });
+}
+
+/// A harness around a `coquitts` element using the mock model, with the given properties set
+/// from strings as `gst-launch-1.0` would.
+fn harness(properties: &[(&str, &str)]) -> Harness {
+  let mut h = stopped_harness(properties, "text/x-raw,format=utf8", None);
+  h.play();
+  h
+}
+
+/// Like `harness()`, but pushing text with the given caps and only accepting audio with
+/// `sink_caps`, if given. The element isn't started, so that it can be looked at beforehand.
+fn stopped_harness(
+  properties: &[(&str, &str)],
+  src_caps: &str,
+  sink_caps: Option<&str>,
+) -> Harness {
+  init();
+  let element = gstreamer::ElementFactory::make("coquitts")
+    .property("model", "mock")
+    .build()
+    .unwrap();
+  for (name, value) in properties {
+    element.set_property_from_str(name, value);
+  }
+  let mut h = Harness::with_element(&element, Some("sink"), Some("src"));
+  if let Some(sink_caps) = sink_caps {
+    h.set_sink_caps_str(sink_caps);
+  }
+  h.set_src_caps_str(src_caps);
+  h
+}
+
+fn text_buffer(text: &str, pts: ClockTime) -> Buffer {
+  let mut buffer = Buffer::from_slice(text.as_bytes().to_vec());
+  buffer.get_mut().unwrap().set_pts(pts);
+  buffer
+}
+
+fn samples_duration(samples: u64, sample_rate: u64) -> ClockTime {
+  ClockTime::from_nseconds(samples * 1_000_000_000 / sample_rate)
+}
+
+#[test]
+fn negotiates_the_model_sample_rate() {
+  let mut h = harness(&[]);
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  h.pull().unwrap();
+  let caps = h
+    .element()
+    .unwrap()
+    .static_pad("src")
+    .unwrap()
+    .current_caps()
+    .unwrap();
+  let s = caps.structure(0).unwrap();
+  assert_eq!(s.name(), "audio/x-raw");
+  assert_eq!(s.get::<&str>("format").unwrap(), "F32LE");
+  assert_eq!(s.get::<i32>("channels").unwrap(), 1);
+  assert_eq!(s.get::<i32>("rate").unwrap(), SAMPLE_RATE as i32);
+}
+
+#[test]
+fn audio_is_timestamped_like_the_text() {
+  let mut h = harness(&[]);
+  let pts = ClockTime::from_seconds(2);
+  h.push(text_buffer("Hello", pts)).unwrap();
+  let buffer = h.pull().unwrap();
+  let samples = 5 * SAMPLES_PER_CHAR;
+  assert_eq!(buffer.pts(), Some(pts));
+  assert_eq!(
+    buffer.duration(),
+    Some(samples_duration(samples, SAMPLE_RATE))
+  );
+  assert_eq!(buffer.size() as u64, samples * 4);
+}
+
+/// A text buffer split across several memories, as fragmented upstream buffers can be.
+fn fragmented_text_buffer(fragments: &[&[u8]], pts: ClockTime) -> Buffer {
+  let mut buffer = Buffer::new();
+  {
+    let buffer = buffer.get_mut().unwrap();
+    for fragment in fragments {
+      buffer.append_memory(Memory::from_slice(fragment.to_vec()));
+    }
+    buffer.set_pts(pts);
+  }
+  buffer
+}
+
+#[test]
+fn fragmented_text_is_synthesised_whole() {
+  let mut h = harness(&[]);
+  h.push(fragmented_text_buffer(&[b"Hel", b"lo"], ClockTime::ZERO))
+    .unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.size() as u64, 5 * SAMPLES_PER_CHAR * 4);
+}
+
+#[test]
+fn fragmented_text_from_appsrc_is_synthesised_whole() {
+  init();
+  let pipeline = gstreamer::parse_launch(
+    "appsrc name=src format=time caps=\"text/x-raw,format=utf8\" ! coquitts model=mock ! appsink name=sink sync=false",
+  )
+  .unwrap()
+  .downcast::<gstreamer::Bin>()
+  .unwrap();
+  let src = pipeline.by_name("src").unwrap();
+  let sink = pipeline.by_name("sink").unwrap();
+  pipeline.set_state(gstreamer::State::Playing).unwrap();
+  let buffer = fragmented_text_buffer(&[b"Hel", b"lo"], ClockTime::ZERO);
+  let flow = src.emit_by_name::<gstreamer::FlowReturn>("push-buffer", &[&buffer]);
+  assert_eq!(flow, gstreamer::FlowReturn::Ok);
+  let flow = src.emit_by_name::<gstreamer::FlowReturn>("end-of-stream", &[]);
+  assert_eq!(flow, gstreamer::FlowReturn::Ok);
+  let sample = sink
+    .emit_by_name::<Option<gstreamer::Sample>>("pull-sample", &[])
+    .unwrap();
+  assert_eq!(
+    sample.buffer().unwrap().size() as u64,
+    5 * SAMPLES_PER_CHAR * 4
+  );
+  pipeline.set_state(gstreamer::State::Null).unwrap();
+}
+
+fn partial_text_buffer(text: &str, pts: ClockTime) -> Buffer {
+  let mut buffer = text_buffer(text, pts);
+  gstreamer::meta::CustomMeta::add(buffer.get_mut().unwrap(), "CoquittsPartialMeta").unwrap();
+  buffer
+}
+
+#[test]
+fn partial_text_is_synthesised_a_sentence_at_a_time() {
+  let mut h = harness(&[]);
+  h.push(partial_text_buffer("Hi th", ClockTime::ZERO))
+    .unwrap();
+  h.push(partial_text_buffer("ere. How", ClockTime::ZERO))
+    .unwrap();
+  // "Hi there." is complete once the next sentence has started.
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.size() as u64, 9 * SAMPLES_PER_CHAR * 4);
+  h.push(text_buffer(" are you?", ClockTime::ZERO)).unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.size() as u64, 12 * SAMPLES_PER_CHAR * 4);
+}
+
+#[test]
+fn characters_split_across_memories_are_rejoined() {
+  let mut h = harness(&[]);
+  let text = "héllo".as_bytes();
+  // Split inside the two bytes of "é".
+  h.push(fragmented_text_buffer(&[&text[..2], &text[2..]], ClockTime::ZERO))
+    .unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.size() as u64, 5 * SAMPLES_PER_CHAR * 4);
+}
+
+#[test]
+fn leading_silence_starts_early() {
+  let mut h = harness(&[("silence-padding-start-ms", "100")]);
+  h.push(text_buffer("Hello", ClockTime::from_seconds(2)))
+    .unwrap();
+  let buffer = h.pull().unwrap();
+  let samples = 5 * SAMPLES_PER_CHAR + SAMPLE_RATE / 10;
+  assert_eq!(buffer.pts(), Some(ClockTime::from_mseconds(1900)));
+  assert_eq!(
+    buffer.duration(),
+    Some(samples_duration(samples, SAMPLE_RATE))
+  );
+}
+
+#[test]
+fn outputs_f64_when_downstream_asks_for_it() {
+  let mut h = harness(&[]);
+  h.set_sink_caps_str("audio/x-raw,format=F64LE,layout=interleaved,channels=1,rate=16000");
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.size() as u64, 5 * SAMPLES_PER_CHAR * 8);
+}
+
+#[test]
+fn caps_have_an_explicit_layout() {
+  let mut h = harness(&[]);
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  h.pull().unwrap();
+  let caps = h
+    .element()
+    .unwrap()
+    .static_pad("src")
+    .unwrap()
+    .current_caps()
+    .unwrap();
+  let s = caps.structure(0).unwrap();
+  assert_eq!(s.get::<&str>("layout").unwrap(), "interleaved");
+}
+
+#[test]
+fn non_interleaved_output_has_audio_meta() {
+  let mut h = harness(&[]);
+  h.set_sink_caps_str("audio/x-raw,format=F32LE,layout=non-interleaved,channels=1,rate=16000");
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  let buffer = h.pull().unwrap();
+  let meta = buffer.meta::<gstreamer_audio::AudioMeta>().unwrap();
+  assert_eq!(meta.samples(), 5 * SAMPLES_PER_CHAR as usize);
+}
+
+#[test]
+fn dry_run_produces_silence_without_a_model() {
+  let mut h = harness(&[
+    ("model", "unloadable"),
+    ("dry-run", "true"),
+    ("dry-run-sample-rate", "8000"),
+  ]);
+  h.push(text_buffer("abc", ClockTime::ZERO)).unwrap();
+  let buffer = h.pull().unwrap();
+  // 60ms per character.
+  assert_eq!(buffer.duration(), Some(ClockTime::from_mseconds(180)));
+  let map = buffer.map_readable().unwrap();
+  assert!(map.iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn empty_text_can_become_a_gap() {
+  let mut h = harness(&[("empty-text-policy", "gap")]);
+  let mut buffer = text_buffer("  ", ClockTime::from_seconds(1));
+  buffer
+    .get_mut()
+    .unwrap()
+    .set_duration(ClockTime::from_mseconds(500));
+  h.push(buffer).unwrap();
+  loop {
+    let event = h.pull_event().unwrap();
+    if event.type_() == EventType::Gap {
+      if let gstreamer::EventView::Gap(gap) = event.view() {
+        assert_eq!(
+          gap.get(),
+          (
+            ClockTime::from_seconds(1),
+            Some(ClockTime::from_mseconds(500))
+          )
+        );
+      }
+      break;
+    }
+  }
+}
+
+#[test]
+fn text_without_audio_is_skipped() {
+  let mut h = harness(&[]);
+  h.push(text_buffer("#empty Hello", ClockTime::ZERO))
+    .unwrap();
+  h.push(text_buffer("World", ClockTime::from_seconds(1)))
+    .unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.pts(), Some(ClockTime::from_seconds(1)));
+}
+
+#[test]
+fn failed_text_is_left_out_of_the_progress_file() {
+  let path = std::env::temp_dir().join(format!("coquitts-progress-{}", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+  let mut h = harness(&[("progress-file", path.to_str().unwrap())]);
+  for (offset, text) in ["Hello", "#empty World"].into_iter().enumerate() {
+    let mut buffer = text_buffer(text, ClockTime::from_seconds(offset as u64));
+    buffer.get_mut().unwrap().set_offset(offset as u64);
+    h.push(buffer).unwrap();
+  }
+  assert!(h.push_event(gstreamer::event::Eos::new()));
+  while h.pull_event().unwrap().type_() != EventType::Eos {}
+  assert_eq!(std::fs::read_to_string(&path).unwrap(), "0\n");
+  std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn stage_directions_can_be_stripped() {
+  let mut h = harness(&[("strip-stage-directions", "true")]);
+  h.push(text_buffer("(laughs) Hello [music]", ClockTime::ZERO))
+    .unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.size() as u64, 5 * SAMPLES_PER_CHAR * 4);
+}
+
+#[test]
+fn long_text_can_be_rejected() {
+  let mut h = harness(&[("max-chars", "10"), ("long-text-policy", "reject")]);
+  h.push(text_buffer("This text is too long", ClockTime::ZERO))
+    .unwrap();
+  h.push(text_buffer("Short", ClockTime::from_seconds(1)))
+    .unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.pts(), Some(ClockTime::from_seconds(1)));
+}
+
+#[test]
+fn long_text_can_be_truncated() {
+  let mut h = harness(&[("max-chars", "10")]);
+  h.push(text_buffer("This text is too long", ClockTime::ZERO))
+    .unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.size() as u64, 10 * SAMPLES_PER_CHAR * 4);
+}
+
+#[test]
+fn text_beyond_the_rate_limit_can_be_dropped() {
+  let mut h = harness(&[("max-chars-per-minute", "20"), ("throttle-policy", "drop")]);
+  // Twelve characters leave eight of the minute's budget, too few for the next ten.
+  h.push(text_buffer("Hello there.", ClockTime::ZERO))
+    .unwrap();
+  h.push(text_buffer("Ten chars.", ClockTime::from_seconds(1)))
+    .unwrap();
+  h.push(text_buffer("Bye.", ClockTime::from_seconds(2)))
+    .unwrap();
+  assert_eq!(h.pull().unwrap().pts(), Some(ClockTime::ZERO));
+  assert_eq!(h.pull().unwrap().pts(), Some(ClockTime::from_seconds(2)));
+}
+
+#[test]
+fn repeated_text_is_skipped_within_the_dedupe_window() {
+  let mut h = harness(&[("dedupe-window", "10000000000")]);
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  h.push(text_buffer("Hello\n", ClockTime::from_seconds(1)))
+    .unwrap();
+  h.push(text_buffer("Bye", ClockTime::from_seconds(2)))
+    .unwrap();
+  assert_eq!(h.pull().unwrap().pts(), Some(ClockTime::ZERO));
+  assert_eq!(h.pull().unwrap().pts(), Some(ClockTime::from_seconds(2)));
+}
+
+#[test]
+fn low_latency_split_parts_join_seamlessly() {
+  let mut h = harness(&[("low-latency-split", "true")]);
+  h.push(text_buffer("Hi, there", ClockTime::ZERO)).unwrap();
+  let first = h.pull().unwrap();
+  let rest = h.pull().unwrap();
+  assert_eq!(first.size() as u64, 3 * SAMPLES_PER_CHAR * 4);
+  assert_eq!(rest.size() as u64, 5 * SAMPLES_PER_CHAR * 4);
+  assert_eq!(
+    rest.pts(),
+    Some(first.pts().unwrap() + first.duration().unwrap())
+  );
+}
+
+#[test]
+fn dtmf_directives_become_tones() {
+  let mut h = harness(&[]);
+  h.push(text_buffer("Press [[dtmf:5]]", ClockTime::ZERO))
+    .unwrap();
+  let speech = h.pull().unwrap();
+  let tone = h.pull().unwrap();
+  assert_eq!(speech.size() as u64, 5 * SAMPLES_PER_CHAR * 4);
+  // 100ms of tone and 100ms of silence.
+  assert_eq!(tone.duration(), Some(ClockTime::from_mseconds(200)));
+  assert_eq!(
+    tone.pts(),
+    Some(speech.pts().unwrap() + speech.duration().unwrap())
+  );
+}
+
+#[test]
+fn output_can_be_cut_into_blocks() {
+  let mut h = harness(&[("output-block-samples", "320"), ("pad-last-block", "true")]);
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  // 800 samples of speech make two full blocks and one padded.
+  let mut pts = ClockTime::ZERO;
+  for _ in 0..3 {
+    let buffer = h.pull().unwrap();
+    assert_eq!(buffer.size(), 320 * 4);
+    assert_eq!(buffer.pts(), Some(pts));
+    pts += buffer.duration().unwrap();
+  }
+}
+
+#[test]
+fn live_scheduling_timestamps_texts_on_arrival() {
+  let mut h = harness(&[("live-scheduling", "true")]);
+  h.set_time(ClockTime::from_seconds(1)).unwrap();
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  h.push(text_buffer("Hi", ClockTime::ZERO)).unwrap();
+  let first = h.pull().unwrap();
+  assert_eq!(first.pts(), Some(ClockTime::from_seconds(1)));
+  // The second text arrived at the same time, so it follows on from the first.
+  let second = h.pull().unwrap();
+  assert_eq!(
+    second.pts(),
+    Some(first.pts().unwrap() + first.duration().unwrap())
+  );
+}
+
+#[test]
+fn audio_past_the_segment_stop_can_be_clipped() {
+  let mut h = harness(&[("segment-stop-policy", "clip")]);
+  let mut segment = gstreamer::FormattedSegment::<ClockTime>::new();
+  segment.set_stop(ClockTime::from_mseconds(30));
+  assert!(h.push_event(gstreamer::event::Segment::new(&segment)));
+  // "Hello" takes 50ms.
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.duration(), Some(ClockTime::from_mseconds(30)));
+}
+
+#[test]
+fn initial_text_is_synthesised_when_playing() {
+  let mut h = harness(&[("initial-text", "Hello")]);
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.size() as u64, 5 * SAMPLES_PER_CHAR * 4);
+}
+
+#[test]
+fn s16_output_can_be_negotiated() {
+  let mut h = stopped_harness(
+    &[],
+    "text/x-raw,format=utf8",
+    Some("audio/x-raw,format=S16LE"),
+  );
+  h.play();
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.size() as u64, 5 * SAMPLES_PER_CHAR * 2);
+}
+
+/// Pulls `count` buffers and checks that they form one continuous stream from zero, in time and
+/// in samples.
+fn assert_gapless(h: &mut Harness, count: usize) {
+  let mut pts = ClockTime::ZERO;
+  let mut offset = 0;
+  for _ in 0..count {
+    let buffer = h.pull().unwrap();
+    assert_eq!(buffer.pts(), Some(pts));
+    assert_eq!(buffer.offset(), offset);
+    let samples = buffer.size() as u64 / 4;
+    assert_eq!(buffer.offset_end(), offset + samples);
+    pts += buffer.duration().unwrap();
+    offset += samples;
+    assert_eq!(pts, samples_duration(offset, SAMPLE_RATE));
+  }
+}
+
+#[test]
+fn gapless_audio_ignores_text_timestamps() {
+  let mut h = harness(&[
+    ("gapless", "true"),
+    ("silence-padding-start-ms", "10"),
+    ("output-block-samples", "300"),
+  ]);
+  for (text, pts) in [("Hello", 10), ("Hi", 0), ("Goodbye", 5)] {
+    h.push(text_buffer(text, ClockTime::from_seconds(pts)))
+      .unwrap();
+  }
+  // 960, 480 and 1280 samples, each with 160 samples of padding, in blocks of 300.
+  assert_gapless(&mut h, 4 + 2 + 5);
+}
+
+#[test]
+fn gapless_audio_is_continuous_with_several_workers() {
+  let mut h = harness(&[("gapless", "true"), ("num-workers", "3")]);
+  for text in ["One", "Two", "Three", "Four", "Five", "Six"] {
+    h.push(text_buffer(text, ClockTime::ZERO)).unwrap();
+  }
+  assert_gapless(&mut h, 6);
+}
+
+#[test]
+fn audio_is_resampled_to_the_rate_downstream_wants() {
+  let mut h = stopped_harness(
+    &[("resample", "true")],
+    "text/x-raw,format=utf8",
+    Some("audio/x-raw,rate=8000"),
+  );
+  h.play();
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.size() as u64, 5 * SAMPLES_PER_CHAR / 2 * 4);
+  assert_eq!(
+    buffer.duration(),
+    Some(samples_duration(5 * SAMPLES_PER_CHAR, 16000))
+  );
+}
+
+#[test]
+fn csv_rows_are_synthesised_with_their_ids() {
+  let mut h = harness(&[]);
+  h.set_src_caps_str("text/csv");
+  // The second row is split across buffers.
+  h.push(text_buffer("a\tHi\nb\tHel", ClockTime::ZERO))
+    .unwrap();
+  h.push(text_buffer("lo\n", ClockTime::ZERO)).unwrap();
+  for (id, chars) in [("a", 2), ("b", 5)] {
+    let buffer = h.pull().unwrap();
+    assert_eq!(buffer.size() as u64, chars * SAMPLES_PER_CHAR * 4);
+    let meta = gstreamer::meta::CustomMeta::from_buffer(&buffer, "CoquittsRowMeta").unwrap();
+    assert_eq!(meta.structure().get::<&str>("id").unwrap(), id);
+  }
+}
+
+#[test]
+fn speakers_are_listed_in_detail() {
+  let mut h = harness(&[("model", "multi-speaker"), ("speaker", "alice")]);
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  h.pull().unwrap();
+  let detailed = h
+    .element()
+    .unwrap()
+    .property::<gstreamer::Structure>("speakers-detailed");
+  let speakers = detailed.get::<gstreamer::Array>("speakers").unwrap();
+  let bob = speakers[1].get::<gstreamer::Structure>().unwrap();
+  assert_eq!(bob.get::<&str>("name").unwrap(), "bob");
+  assert_eq!(bob.get::<i32>("index").unwrap(), 1);
+  assert!(!bob.has_field("gender"));
+}
+
+#[test]
+fn buffers_record_their_source() {
+  let mut h = harness(&[("model", "multi-speaker"), ("speaker", "alice")]);
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  let buffer = h.pull().unwrap();
+  let meta = gstreamer::meta::CustomMeta::from_buffer(&buffer, "CoquittsSourceMeta").unwrap();
+  let source = meta.structure();
+  assert_eq!(source.get::<&str>("text").unwrap(), "Hello");
+  assert_eq!(source.get::<&str>("model").unwrap(), "multi-speaker");
+  assert_eq!(source.get::<&str>("speaker").unwrap(), "alice");
+  assert!(!source.has_field("speed"));
+}
+
+#[test]
+fn languages_are_synthesised_with_their_mapped_voice() {
+  let mut h = harness(&[
+    ("language", "en-GB"),
+    (
+      "voice-map",
+      r#"{"en": {"model": "multi-speaker", "speaker": "bob"}}"#,
+    ),
+  ]);
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  let buffer = h.pull().unwrap();
+  let meta = gstreamer::meta::CustomMeta::from_buffer(&buffer, "CoquittsSourceMeta").unwrap();
+  let source = meta.structure();
+  assert_eq!(source.get::<&str>("model").unwrap(), "multi-speaker");
+  assert_eq!(source.get::<&str>("speaker").unwrap(), "bob");
+}
+
+#[test]
+fn request_ids_are_passed_on_to_the_audio() {
+  let mut h = stopped_harness(&[], "text/x-raw,format=json", None);
+  h.play();
+  let message = r#"{"text": "Hello", "id": 42}"#;
+  h.push(text_buffer(message, ClockTime::ZERO)).unwrap();
+  let buffer = h.pull().unwrap();
+  let meta = gstreamer::meta::CustomMeta::from_buffer(&buffer, "CoquittsSourceMeta").unwrap();
+  assert_eq!(meta.structure().get::<&str>("id").unwrap(), "42");
+}
+
+#[test]
+fn text_is_normalized() {
+  let mut h = harness(&[]);
+  // A byte order mark, a decomposed é and a run of whitespace.
+  h.push(text_buffer("\u{feff}Cafe\u{301} \n ok", ClockTime::ZERO))
+    .unwrap();
+  let buffer = h.pull().unwrap();
+  // "Café ok" is 7 characters.
+  assert_eq!(buffer.size() as u64, 7 * SAMPLES_PER_CHAR * 4);
+}
+
+#[test]
+fn speaker_index_selects_a_speaker() {
+  let mut h = harness(&[("model", "multi-speaker"), ("speaker-index", "1")]);
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  h.pull().unwrap();
+  let settings = h
+    .element()
+    .unwrap()
+    .property::<gstreamer::Structure>("effective-settings");
+  assert_eq!(settings.get::<&str>("speaker").unwrap(), "bob");
+}
+
+#[test]
+fn speaker_can_come_from_the_caps() {
+  let mut h = harness(&[("model", "multi-speaker")]);
+  h.set_src_caps_str("text/x-raw,format=utf8,tts-speaker=alice");
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  h.pull().unwrap();
+  let settings = h
+    .element()
+    .unwrap()
+    .property::<gstreamer::Structure>("effective-settings");
+  assert_eq!(settings.get::<&str>("speaker").unwrap(), "alice");
+}
+
+#[test]
+fn speaker_labels_are_mapped_and_removed() {
+  let mut h = harness(&[
+    ("model", "multi-speaker"),
+    ("speaker-map", r#"{"JOHN": "bob"}"#),
+  ]);
+  h.push(text_buffer("JOHN: Hi", ClockTime::ZERO)).unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.size() as u64, 2 * SAMPLES_PER_CHAR * 4);
+}
+
+/// Serves `requests` requests like Coqui's TTS server, answering each with a WAV file of
+/// `samples` 16-bit samples at 8kHz. Returns the server's URL.
+fn mock_tts_server(requests: usize, samples: u32) -> String {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let url = format!("http://{}", listener.local_addr().unwrap());
+  thread::spawn(move || {
+    for stream in listener.incoming().take(requests) {
+      let mut stream = stream.unwrap();
+      let mut reader = BufReader::new(stream.try_clone().unwrap());
+      let mut line = String::new();
+      let mut content_length = 0;
+      while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+          content_length = value.trim().parse().unwrap();
+        }
+        line.clear();
+      }
+      // The form with the text.
+      reader.read_exact(&mut vec![0; content_length]).unwrap();
+      let mut wav = vec![];
+      wav.extend_from_slice(b"RIFF");
+      wav.extend_from_slice(&(36 + samples * 2).to_le_bytes());
+      wav.extend_from_slice(b"WAVEfmt ");
+      wav.extend_from_slice(&16u32.to_le_bytes());
+      wav.extend_from_slice(&[1, 0, 1, 0]);
+      wav.extend_from_slice(&8000u32.to_le_bytes());
+      wav.extend_from_slice(&16000u32.to_le_bytes());
+      wav.extend_from_slice(&[2, 0, 16, 0]);
+      wav.extend_from_slice(b"data");
+      wav.extend_from_slice(&(samples * 2).to_le_bytes());
+      wav.resize(wav.len() + samples as usize * 2, 0);
+      write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: audio/wav\r\nContent-Length: {}\r\n\r\n",
+        wav.len()
+      )
+      .unwrap();
+      stream.write_all(&wav).unwrap();
+    }
+  });
+  url
+}
+
+#[test]
+fn server_backend_synthesises_over_http() {
+  // One request to find out the sample rate and one for the text.
+  let url = mock_tts_server(2, 400);
+  let mut h = harness(&[("backend", "server"), ("server-url", &url)]);
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  let buffer = h.pull().unwrap();
+  assert_eq!(buffer.size(), 400 * 4);
+  assert_eq!(buffer.duration(), Some(ClockTime::from_mseconds(50)));
+}
+
+#[test]
+fn properties_round_trip() {
+  init();
+  let element = gstreamer::ElementFactory::make("coquitts").build().unwrap();
+  assert_eq!(element.property::<f64>("pitch-semitones"), 0.0);
+  element.set_property("pitch-semitones", 2.5f64);
+  assert_eq!(element.property::<f64>("pitch-semitones"), 2.5);
+  assert_eq!(element.property::<Option<String>>("speaker"), None);
+  element.set_property("speaker", "alice");
+  assert_eq!(
+    element.property::<Option<String>>("speaker").as_deref(),
+    Some("alice")
+  );
+  element.set_property_from_str("use-gpu", "auto");
+  let use_gpu = element.property_value("use-gpu");
+  let (_, value) = glib::EnumValue::from_value(&use_gpu).unwrap();
+  assert_eq!(value.nick(), "auto");
+}
+
+#[test]
+fn sample_rate_property_loads_the_model() {
+  init();
+  let element = gstreamer::ElementFactory::make("coquitts")
+    .property("model", "mock")
+    .build()
+    .unwrap();
+  assert_eq!(element.property::<u32>("sample-rate"), SAMPLE_RATE as u32);
+  element.set_property("model", "unloadable");
+  assert_eq!(element.property::<u32>("sample-rate"), 0);
+}
+
+#[test]
+fn model_is_loaded_on_start() {
+  let mut h = stopped_harness(&[], "text/x-raw,format=utf8", None);
+  let element = h.element().unwrap();
+  assert!(!element.property::<bool>("is-loaded"));
+  h.play();
+  assert!(element.property::<bool>("is-loaded"));
+}
+
+#[test]
+fn model_can_be_unloaded_and_loaded_again() {
+  let mut h = harness(&[]);
+  let element = h.element().unwrap();
+  assert!(element.emit_by_name::<bool>("unload-model", &[]));
+  assert!(!element.property::<bool>("is-loaded"));
+  assert!(!element.emit_by_name::<bool>("unload-model", &[]));
+  assert!(element.emit_by_name::<bool>("load-model", &[]));
+  assert!(element.property::<bool>("is-loaded"));
+  // Text after unloading loads it again.
+  element.emit_by_name::<bool>("unload-model", &[]);
+  h.push(text_buffer("Hello", ClockTime::ZERO)).unwrap();
+  h.pull().unwrap();
+  assert!(element.property::<bool>("is-loaded"));
+}
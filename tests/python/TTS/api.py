@@ -0,0 +1,71 @@
+"""A stand-in for Coqui's TTS.api module, so that the elements can be tested without coqui-tts.
+
+Speech is a quiet sine wave lasting SECONDS_PER_CHAR for every character of the text, at
+SAMPLE_RATE. The model name `multi-speaker` loads a model that requires a speaker, and
+`unloadable` one that fails to load. Text starting with EMPTY_OUTPUT_PREFIX gives no samples, as
+some failed inferences do.
+"""
+
+import math
+
+SAMPLE_RATE = 16000
+SECONDS_PER_CHAR = 0.01
+SPEAKERS = ["alice", "bob"]
+EMPTY_OUTPUT_PREFIX = "#empty"
+
+
+class Tokenizer:
+    use_phonemes = False
+
+
+class TtsModel:
+    def __init__(self):
+        self.tokenizer = Tokenizer()
+        self.speaker_manager = None
+        self.language_manager = None
+
+
+class Synthesizer:
+    def __init__(self):
+        self.output_sample_rate = SAMPLE_RATE
+        self.tts_model = TtsModel()
+        self.tts_config = {}
+        self.vocoder_model = None
+        self.use_cuda = False
+
+    def split_into_sentences(self, text):
+        return [sentence.strip() + "." for sentence in text.split(".") if sentence.strip()]
+
+
+class TTS:
+    def __init__(self, model_name=None, progress_bar=True, gpu=False):
+        if model_name == "unloadable":
+            raise ValueError(f"model {model_name} can't be loaded")
+        self.model_name = model_name
+        self.synthesizer = Synthesizer()
+        self.is_multi_speaker = model_name == "multi-speaker"
+        self.is_multi_lingual = False
+        self.speakers = SPEAKERS if self.is_multi_speaker else None
+        self.languages = None
+        self.device = "cpu"
+
+    def to(self, device):
+        self.device = device
+        return self
+
+    def tts(
+        self,
+        text,
+        speaker=None,
+        language=None,
+        speaker_wav=None,
+        speed=None,
+        split_sentences=True,
+        **kwargs,
+    ):
+        if self.is_multi_speaker and speaker not in SPEAKERS:
+            raise ValueError(f"unknown speaker {speaker}")
+        if text.startswith(EMPTY_OUTPUT_PREFIX):
+            return []
+        samples = int(len(text) * SECONDS_PER_CHAR * SAMPLE_RATE / (speed or 1.0))
+        return [0.1 * math.sin(2 * math.pi * 440 * i / SAMPLE_RATE) for i in range(samples)]